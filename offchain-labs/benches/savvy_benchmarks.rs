@@ -1,21 +1,61 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use offchain_labs::{Config, OffchainLabs};
+use offchain_labs::bend::{BendProgram, ProgramMetadata};
+use offchain_labs::config::ExecutionConfig;
+use offchain_labs::sequencer::Transaction;
+use std::path::PathBuf;
 
 fn benchmark_transaction_processing(c: &mut Criterion) {
     let config = Config {
-        zk_params_path: "bench_params.json".to_string(),
-        state_db_path: "bench_state.db".to_string(),
+        zk_params_path: PathBuf::from("bench_params.json"),
+        state_db_path: PathBuf::from("bench_state.db"),
+        ..Config::default()
     };
 
     let mut hvm = OffchainLabs::new(config).unwrap();
-    let transaction = vec![1, 2, 3, 4];
+    let transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), vec![1, 2, 3, 4], 1, "bench_program".to_string());
 
     c.bench_function("process transaction", |b| {
         b.iter(|| {
-            hvm.process_transaction(black_box(&transaction)).unwrap();
+            let _ = hvm.process_transaction(black_box(transaction.clone()));
         })
     });
 }
 
-criterion_group!(benches, benchmark_transaction_processing);
-criterion_main!(benches);
\ No newline at end of file
+fn benchmark_program_execution(c: &mut Criterion) {
+    let bytecode = wat::parse_str(r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+                (local.get $ptr)
+                (local.get $len)))
+    "#).unwrap();
+    let program = BendProgram::new(
+        bytecode,
+        ProgramMetadata {
+            name: "Bench Program".to_string(),
+            version: "1.0.0".to_string(),
+            description: "Bend program for benchmarks".to_string(),
+            source: None,
+        },
+        "Bench Author".to_string(),
+    );
+    let inputs = vec![1u8; 64];
+
+    let uncached = ExecutionConfig::default();
+    c.bench_function("execute program", |b| {
+        b.iter(|| program.execute(black_box(inputs.clone()), &uncached).unwrap())
+    });
+
+    let cache_dir = std::env::temp_dir().join("hvm_bench_module_cache");
+    let cached = ExecutionConfig { cache_dir: Some(cache_dir.clone()), ..ExecutionConfig::default() };
+    c.bench_function("execute program (cached module)", |b| {
+        b.iter(|| program.execute(black_box(inputs.clone()), &cached).unwrap())
+    });
+    let _ = std::fs::remove_dir_all(cache_dir);
+}
+
+criterion_group!(benches, benchmark_transaction_processing, benchmark_program_execution);
+criterion_main!(benches);
@@ -0,0 +1,98 @@
+//! Calling convention between the host and a Bend program.
+//!
+//! A program exports its linear memory as `memory`, an allocator
+//! `alloc(len: i32) -> i32` and an entry point
+//! `run(ptr: i32, len: i32) -> (i32, i32)`. The host asks the guest for an
+//! input buffer through `alloc`, copies the inputs into it and calls `run` with
+//! the buffer. `run` returns a pointer and length to its output, which must
+//! lie inside memory and hold a whole number of 32-byte little-endian field
+//! elements.
+
+use crate::error::HVMError;
+use wasmer::{AsStoreMut, AsStoreRef, Instance, Memory, TypedFunction};
+use wasmparser::{ExternalKind, FuncType, ValType};
+
+pub const MEMORY_EXPORT: &str = "memory";
+pub const ALLOC_EXPORT: &str = "alloc";
+pub const ENTRY_EXPORT: &str = "run";
+
+pub const OUTPUT_ELEMENT_SIZE: usize = 32;
+
+pub type EntryPoint = TypedFunction<(i32, i32), (i32, i32)>;
+
+/// Checks an export against the calling convention at deployment time.
+pub fn check_export(name: &str, kind: ExternalKind, func_type: Option<&FuncType>) -> Result<(), HVMError> {
+    let expected: (&[ValType], &[ValType]) = match name {
+        ALLOC_EXPORT => (&[ValType::I32], &[ValType::I32]),
+        ENTRY_EXPORT => (&[ValType::I32, ValType::I32], &[ValType::I32, ValType::I32]),
+        MEMORY_EXPORT if kind == ExternalKind::Memory => return Ok(()),
+        MEMORY_EXPORT => return Err(HVMError::Abi(format!("`{}` must be a memory", MEMORY_EXPORT))),
+        _ => return Ok(()),
+    };
+
+    match func_type {
+        Some(ty) if kind == ExternalKind::Func && ty.params() == expected.0 && ty.results() == expected.1 => Ok(()),
+        _ => Err(HVMError::Abi(format!(
+            "`{}` must be a function of type {:?} -> {:?}", name, expected.0, expected.1
+        ))),
+    }
+}
+
+pub fn memory(instance: &Instance) -> Result<&Memory, HVMError> {
+    instance.exports.get_memory(MEMORY_EXPORT)
+        .map_err(|e| HVMError::Abi(format!("Module does not export `{}`: {}", MEMORY_EXPORT, e)))
+}
+
+pub fn entry_point(store: &impl AsStoreRef, instance: &Instance) -> Result<EntryPoint, HVMError> {
+    instance.exports.get_typed_function(store, ENTRY_EXPORT)
+        .map_err(|e| HVMError::Abi(format!("Module does not export a valid `{}`: {}", ENTRY_EXPORT, e)))
+}
+
+/// Copies the inputs into a buffer allocated by the guest and returns its
+/// pointer and length.
+pub fn write_inputs(store: &mut impl AsStoreMut, instance: &Instance, inputs: &[u8]) -> Result<(i32, i32), HVMError> {
+    let alloc: TypedFunction<i32, i32> = instance.exports.get_typed_function(store, ALLOC_EXPORT)
+        .map_err(|e| HVMError::Abi(format!("Module does not export a valid `{}`: {}", ALLOC_EXPORT, e)))?;
+    let len = i32::try_from(inputs.len())
+        .map_err(|_| HVMError::Abi(format!("Inputs of {} bytes are too large", inputs.len())))?;
+
+    let ptr = alloc.call(store, len)
+        .map_err(|e| HVMError::Execution(format!("Failed to allocate input buffer: {}", e)))?;
+
+    let memory = memory(instance)?;
+    let view = memory.view(store);
+    check_bounds(ptr, len, view.data_size())?;
+    view.write(ptr as u32 as u64, inputs)
+        .map_err(|e| HVMError::Abi(format!("Failed to write inputs to memory: {}", e)))?;
+
+    Ok((ptr, len))
+}
+
+/// Reads the `(ptr, len)` output returned by the entry point.
+pub fn read_outputs(store: &impl AsStoreRef, instance: &Instance, (ptr, len): (i32, i32)) -> Result<Vec<u8>, HVMError> {
+    let memory = memory(instance)?;
+    let view = memory.view(store);
+    check_bounds(ptr, len, view.data_size())?;
+
+    let len = len as u32 as usize;
+    if !len.is_multiple_of(OUTPUT_ELEMENT_SIZE) {
+        return Err(HVMError::Abi(format!(
+            "Output length {} is not a multiple of {}", len, OUTPUT_ELEMENT_SIZE
+        )));
+    }
+
+    let mut output = vec![0u8; len];
+    view.read(ptr as u32 as u64, &mut output)
+        .map_err(|e| HVMError::Abi(format!("Failed to read outputs from memory: {}", e)))?;
+    Ok(output)
+}
+
+fn check_bounds(ptr: i32, len: i32, memory_size: u64) -> Result<(), HVMError> {
+    let end = ptr as u32 as u64 + len as u32 as u64;
+    if end > memory_size {
+        return Err(HVMError::Abi(format!(
+            "Buffer at {} with length {} is outside memory of {} bytes", ptr as u32, len as u32, memory_size
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use crate::config::ExecutionConfig;
+use crate::error::HVMError;
+use log::{debug, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasmer::{Module, Store};
+
+/// Bumped whenever the metering table, the injected middleware or anything
+/// else that changes the compiled code is modified, so stale artifacts are
+/// never loaded.
+const CACHE_VERSION: u32 = 2;
+
+/// On-disk cache of compiled modules, keyed by program id.
+///
+/// Artifacts are only valid for the engine configuration that produced them,
+/// so the key also covers the settings that are baked in at compile time.
+/// The directory must only be writable by the node itself: loading an
+/// artifact maps native code into the process without further checks.
+pub struct ModuleCache {
+    dir: PathBuf,
+}
+
+impl ModuleCache {
+    pub fn new(dir: &Path) -> Result<Self, HVMError> {
+        fs::create_dir_all(dir)
+            .map_err(|e| HVMError::Execution(format!("Failed to create module cache directory: {}", e)))?;
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    /// Loads the compiled module for `program_id`, compiling and storing it on
+    /// a miss. Unreadable or stale artifacts are recompiled and replaced.
+    pub fn load_or_compile(&self, store: &Store, program_id: &str, bytecode: &[u8], config: &ExecutionConfig) -> Result<Module, HVMError> {
+        let path = match self.artifact_path(program_id, config) {
+            Some(path) => path,
+            None => return compile(store, bytecode),
+        };
+
+        if path.exists() {
+            // SAFETY: artifacts in the cache directory were written by `store`
+            // below and are keyed by the engine configuration that built them.
+            match unsafe { Module::deserialize_from_file(store, &path) } {
+                Ok(module) => {
+                    debug!("Loaded compiled module for program {} from cache", program_id);
+                    return Ok(module);
+                }
+                Err(e) => warn!("Discarding cached module for program {}: {}", program_id, e),
+            }
+        }
+
+        let module = compile(store, bytecode)?;
+        if let Err(e) = self.store(&module, &path) {
+            warn!("Failed to cache compiled module for program {}: {}", program_id, e);
+        }
+        Ok(module)
+    }
+
+    pub fn remove(&self, program_id: &str, config: &ExecutionConfig) -> Result<(), HVMError> {
+        match self.artifact_path(program_id, config) {
+            Some(path) if path.exists() => fs::remove_file(path).map_err(HVMError::from),
+            _ => Ok(()),
+        }
+    }
+
+    fn artifact_path(&self, program_id: &str, config: &ExecutionConfig) -> Option<PathBuf> {
+        let valid_id = !program_id.is_empty()
+            && program_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_id {
+            return None;
+        }
+        Some(self.dir.join(format!("{}-v{}-p{}.wasmu", program_id, CACHE_VERSION, config.max_memory_pages)))
+    }
+
+    fn store(&self, module: &Module, path: &Path) -> Result<(), HVMError> {
+        let bytes = module.serialize()
+            .map_err(|e| HVMError::Execution(format!("Failed to serialize module: {}", e)))?;
+        // Write through a temporary file so a concurrent reader never sees a
+        // partially written artifact.
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, &bytes)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+pub fn compile(store: &Store, bytecode: &[u8]) -> Result<Module, HVMError> {
+    Module::new(store, bytecode)
+        .map_err(|e| HVMError::Execution(format!("Failed to create module: {}", e)))
+}
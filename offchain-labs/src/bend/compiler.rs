@@ -0,0 +1,145 @@
+use crate::config::CompilerConfig;
+use crate::error::HVMError;
+use log::debug;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Subcommand of the Bend toolchain that lowers a Bend file to an HVM2 net,
+/// printed to stdout.
+const BEND_LOWER_COMMAND: &str = "gen-hvm";
+/// Subcommand of the HVM toolchain that compiles a net to a WASM module
+/// implementing the program ABI, printed to stdout.
+const HVM_WASM_COMMAND: &str = "gen-wasm";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SourceLanguage {
+    Bend,
+    Hvm,
+}
+
+impl SourceLanguage {
+    /// Infers the language from a `.bend` or `.hvm` file extension.
+    pub fn from_path(path: &Path) -> Result<Self, HVMError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("bend") => Ok(Self::Bend),
+            Some("hvm") => Ok(Self::Hvm),
+            _ => Err(HVMError::Compilation(format!("Unknown source file type: {}", path.display()))),
+        }
+    }
+
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            Self::Bend => "bend",
+            Self::Hvm => "hvm",
+        }
+    }
+}
+
+/// Records what a program was compiled from, so anyone holding the source and
+/// the same toolchain can rebuild the bytecode and compare it with the
+/// deployed program id.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceInfo {
+    pub language: SourceLanguage,
+    pub source_hash: String,
+    /// Version strings reported by the toolchain binaries that were used.
+    pub compiler: String,
+}
+
+pub struct CompiledProgram {
+    pub bytecode: Vec<u8>,
+    pub source: SourceInfo,
+}
+
+/// Compiles Bend source or an HVM2 net to WASM bytecode with the toolchain
+/// configured in `config`. Bend source is lowered to a net first.
+pub fn compile(language: SourceLanguage, source: &str, config: &CompilerConfig) -> Result<CompiledProgram, HVMError> {
+    let source_hash = hash_source(source);
+    let work_dir = WorkDir::new(&source_hash)?;
+    let source_path = work_dir.path.join(format!("main.{}", language.extension()));
+    fs::write(&source_path, source)?;
+
+    let mut compiler = Vec::new();
+    let net_path = match language {
+        SourceLanguage::Bend => {
+            compiler.push(tool_version(&config.bend_path)?);
+            let net = run_tool(&config.bend_path, &[BEND_LOWER_COMMAND.as_ref(), source_path.as_os_str()])?;
+            let net_path = work_dir.path.join("main.hvm");
+            fs::write(&net_path, net)?;
+            net_path
+        }
+        SourceLanguage::Hvm => source_path,
+    };
+    compiler.push(tool_version(&config.hvm_path)?);
+    let bytecode = run_tool(&config.hvm_path, &[HVM_WASM_COMMAND.as_ref(), net_path.as_os_str()])?;
+    debug!("Compiled {:?} source {} to {} bytes of bytecode", language, source_hash, bytecode.len());
+
+    Ok(CompiledProgram {
+        bytecode,
+        source: SourceInfo { language, source_hash, compiler: compiler.join("; ") },
+    })
+}
+
+/// Reads and compiles a `.bend` or `.hvm` file.
+pub fn compile_file(path: &Path, config: &CompilerConfig) -> Result<CompiledProgram, HVMError> {
+    let language = SourceLanguage::from_path(path)?;
+    let source = fs::read_to_string(path)?;
+    compile(language, &source, config)
+}
+
+pub fn hash_source(source: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn run_tool(tool: &Path, args: &[&OsStr]) -> Result<Vec<u8>, HVMError> {
+    let output = Command::new(tool)
+        .args(args)
+        .output()
+        .map_err(|e| HVMError::Compilation(format!("Failed to run {}: {}", tool.display(), e)))?;
+    if !output.status.success() {
+        return Err(HVMError::Compilation(format!(
+            "{} failed with {}: {}",
+            tool.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
+    Ok(output.stdout)
+}
+
+fn tool_version(tool: &Path) -> Result<String, HVMError> {
+    let version = run_tool(tool, &["--version".as_ref()])?;
+    Ok(String::from_utf8_lossy(&version).trim().to_string())
+}
+
+/// Scratch directory for one compilation, removed when dropped.
+struct WorkDir {
+    path: PathBuf,
+}
+
+impl WorkDir {
+    fn new(source_hash: &str) -> Result<Self, HVMError> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "hvm-compile-{}-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            &source_hash[..16],
+        ));
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
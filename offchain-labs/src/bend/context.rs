@@ -0,0 +1,68 @@
+use super::BendProgram;
+use super::registry::ProgramRegistry;
+use crate::zk_rollup::{ProgramStorage, StateChanges};
+use std::collections::{BTreeMap, HashMap};
+
+/// Deployed programs and committed storage visible to an execution.
+///
+/// Programs can only call programs registered here, either by id or by a
+/// `name@requirement` reference resolved through the registry. Storage reads
+/// fall back to the committed storage once a call's own pending writes are
+/// checked.
+#[derive(Clone, Debug, Default)]
+pub struct CallContext {
+    programs: HashMap<String, BendProgram>,
+    registry: ProgramRegistry,
+    storage: BTreeMap<String, ProgramStorage>,
+}
+
+impl CallContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_programs<'a>(programs: impl IntoIterator<Item = &'a BendProgram>, storage: BTreeMap<String, ProgramStorage>) -> Self {
+        Self {
+            programs: programs.into_iter()
+                .map(|program| (program.id().to_string(), program.clone()))
+                .collect(),
+            registry: ProgramRegistry::new(),
+            storage,
+        }
+    }
+
+    pub fn add_program(&mut self, program: BendProgram) {
+        self.programs.insert(program.id().to_string(), program);
+    }
+
+    pub fn set_registry(&mut self, registry: ProgramRegistry) {
+        self.registry = registry;
+    }
+
+    pub fn program(&self, program_id: &str) -> Option<&BendProgram> {
+        self.programs.get(program_id)
+    }
+
+    /// Looks a program up by id, falling back to a registry reference.
+    pub fn resolve(&self, reference: &str) -> Option<&BendProgram> {
+        self.program(reference).or_else(|| {
+            let version = self.registry.resolve_reference(reference).ok()?;
+            self.program(&version.program_id)
+        })
+    }
+
+    pub fn storage(&self, program_id: &str) -> Option<&ProgramStorage> {
+        self.storage.get(program_id)
+    }
+
+    pub fn set_storage(&mut self, program_id: &str, storage: ProgramStorage) {
+        self.storage.insert(program_id.to_string(), storage);
+    }
+
+    /// Commits the writes of a finished call, so later calls see them.
+    pub fn apply_storage_changes(&mut self, changes: &StateChanges) {
+        for (program_id, changes) in changes {
+            self.storage.entry(program_id.clone()).or_default().apply(changes);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Sequential evaluator for HVM2 interaction nets.
+//!
+//! Redexes are reduced one at a time from a stack, so evaluation order and
+//! the interaction count are fully determined by the net and its inputs.
+//! Every interaction costs one unit of gas, including expanding a reference.
+
+use super::net::{self, Book, NodeKind, Numb, Tree};
+use super::ExecutionResult;
+use crate::config::ExecutionConfig;
+use crate::error::HVMError;
+use crate::zk_rollup::StateChanges;
+use ark_bn254::Fr;
+use std::collections::HashMap;
+
+/// Bytes of input packed into each number passed to `@main`.
+pub const INPUT_WORD_SIZE: usize = 3;
+const WASM_PAGE_SIZE: usize = 65536;
+
+#[derive(Clone, Copy, Debug)]
+enum Port {
+    Era,
+    Ref(usize),
+    Num(Numb),
+    Var(usize),
+    Node(NodeKind, usize),
+}
+
+/// A definition with its variables numbered from zero.
+struct Def {
+    root: Template,
+    redexes: Vec<(Template, Template)>,
+    vars: usize,
+}
+
+enum Template {
+    Era,
+    Ref(usize),
+    Num(Numb),
+    Var(usize),
+    Node(NodeKind, Box<Template>, Box<Template>),
+}
+
+struct Runtime<'a> {
+    defs: &'a [Def],
+    /// Auxiliary ports of each node.
+    nodes: Vec<(Port, Port)>,
+    free_nodes: Vec<usize>,
+    /// The port each wire is connected to at the end that was linked first.
+    wires: Vec<Option<Port>>,
+    redexes: Vec<(Port, Port)>,
+    max_nodes: usize,
+    peak_nodes: usize,
+}
+
+/// Reduces `@main` applied to `inputs` to normal form.
+///
+/// Inputs are split into little-endian 24-bit words, and `@main` is applied
+/// to each word in turn. The result must be a number, or constructor nodes
+/// holding numbers and erasers, which become the outputs in depth-first
+/// order.
+pub fn execute(book: &Book, inputs: &[u8], config: &ExecutionConfig) -> Result<ExecutionResult, HVMError> {
+    let (defs, main) = compile(book)?;
+    let mut runtime = Runtime {
+        defs: &defs,
+        nodes: Vec::new(),
+        free_nodes: Vec::new(),
+        wires: Vec::new(),
+        redexes: Vec::new(),
+        max_nodes: config.max_memory_pages as usize * WASM_PAGE_SIZE / std::mem::size_of::<(Port, Port)>(),
+        peak_nodes: 0,
+    };
+
+    let root = runtime.new_wire();
+    let mut target = Port::Var(root);
+    for word in inputs.chunks(INPUT_WORD_SIZE).rev() {
+        let value = word.iter().rev().fold(0u32, |value, byte| value << 8 | *byte as u32);
+        target = Port::Node(NodeKind::Con, runtime.alloc(Port::Num(Numb::U24(value)), target)?);
+    }
+    let mut interactions = 1;
+    runtime.expand(main, target)?;
+
+    while let Some((a, b)) = runtime.redexes.pop() {
+        if interactions >= config.gas_limit {
+            return Err(HVMError::OutOfGas(config.gas_limit));
+        }
+        interactions += 1;
+        runtime.interact(a, b)?;
+    }
+
+    let outputs = runtime.read_outputs(Port::Var(root))?;
+    Ok(ExecutionResult {
+        outputs: outputs.into_iter().map(|value| Fr::from(value as u64)).collect(),
+        gas_used: interactions,
+        memory_usage: (runtime.peak_nodes * std::mem::size_of::<(Port, Port)>()) as u64,
+        storage_changes: StateChanges::new(),
+        events: Vec::new(),
+    })
+}
+
+fn compile(book: &Book) -> Result<(Vec<Def>, usize), HVMError> {
+    let index = book.defs.keys()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect::<HashMap<_, _>>();
+    let main = *index.get(net::ENTRY_DEFINITION)
+        .ok_or_else(|| HVMError::Execution(format!("Net does not define @{}", net::ENTRY_DEFINITION)))?;
+
+    let defs = book.defs.values()
+        .map(|net| {
+            let mut vars = HashMap::new();
+            let root = template(&net.root, &index, &mut vars)?;
+            let redexes = net.redexes.iter()
+                .map(|(a, b)| Ok((template(a, &index, &mut vars)?, template(b, &index, &mut vars)?)))
+                .collect::<Result<_, HVMError>>()?;
+            Ok(Def { root, redexes, vars: vars.len() })
+        })
+        .collect::<Result<_, HVMError>>()?;
+    Ok((defs, main))
+}
+
+fn template<'a>(tree: &'a Tree, index: &HashMap<&str, usize>, vars: &mut HashMap<&'a str, usize>) -> Result<Template, HVMError> {
+    Ok(match tree {
+        Tree::Era => Template::Era,
+        Tree::Ref(name) => Template::Ref(*index.get(name.as_str())
+            .ok_or_else(|| HVMError::Execution(format!("Undefined reference @{}", name)))?),
+        Tree::Num(numb) => Template::Num(*numb),
+        Tree::Var(name) => {
+            let next = vars.len();
+            Template::Var(*vars.entry(name.as_str()).or_insert(next))
+        }
+        Tree::Node(kind, a, b) => Template::Node(*kind, Box::new(template(a, index, vars)?), Box::new(template(b, index, vars)?)),
+    })
+}
+
+impl Runtime<'_> {
+    fn interact(&mut self, a: Port, b: Port) -> Result<(), HVMError> {
+        match (a, b) {
+            (Port::Ref(def), node @ Port::Node(..)) | (node @ Port::Node(..), Port::Ref(def)) => self.expand(def, node),
+            (Port::Node(a_kind, a_node), Port::Node(b_kind, b_node)) if a_kind == b_kind => {
+                let (a1, a2) = self.take(a_node);
+                let (b1, b2) = self.take(b_node);
+                self.link(a1, b1);
+                self.link(a2, b2);
+                Ok(())
+            }
+            (Port::Node(a_kind, a_node), Port::Node(b_kind, b_node)) => self.commute(a_kind, a_node, b_kind, b_node),
+            (Port::Num(numb), Port::Node(NodeKind::Opr, node)) | (Port::Node(NodeKind::Opr, node), Port::Num(numb)) => {
+                self.operate(numb, node)
+            }
+            (Port::Num(numb), Port::Node(NodeKind::Swi, node)) | (Port::Node(NodeKind::Swi, node), Port::Num(numb)) => {
+                self.switch(numb, node)
+            }
+            (nullary, Port::Node(_, node)) | (Port::Node(_, node), nullary) => {
+                let (x, y) = self.take(node);
+                self.link(nullary, x);
+                self.link(nullary, y);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn commute(&mut self, a_kind: NodeKind, a_node: usize, b_kind: NodeKind, b_node: usize) -> Result<(), HVMError> {
+        let (a1, a2) = self.take(a_node);
+        let (b1, b2) = self.take(b_node);
+        let w = [self.new_wire(), self.new_wire(), self.new_wire(), self.new_wire()].map(Port::Var);
+        let b_copy1 = self.alloc(w[0], w[1])?;
+        let b_copy2 = self.alloc(w[2], w[3])?;
+        let a_copy1 = self.alloc(w[0], w[2])?;
+        let a_copy2 = self.alloc(w[1], w[3])?;
+        self.link(a1, Port::Node(b_kind, b_copy1));
+        self.link(a2, Port::Node(b_kind, b_copy2));
+        self.link(b1, Port::Node(a_kind, a_copy1));
+        self.link(b2, Port::Node(a_kind, a_copy2));
+        Ok(())
+    }
+
+    /// `$(x r)` meeting a number applies it to `x` once both are numbers,
+    /// otherwise the number waits in `x`'s place while `x` is reduced.
+    fn operate(&mut self, numb: Numb, node: usize) -> Result<(), HVMError> {
+        let (x, r) = self.take(node);
+        match self.enter(x) {
+            Port::Num(left) => {
+                self.link(r, Port::Num(apply(left, numb)));
+            }
+            x => {
+                let node = self.alloc(Port::Num(numb), r)?;
+                self.link(x, Port::Node(NodeKind::Opr, node));
+            }
+        }
+        Ok(())
+    }
+
+    /// `?(s r)` meeting `0` links `s` with `(r *)`, and meeting `n + 1`
+    /// links it with `(* (n r))`.
+    fn switch(&mut self, numb: Numb, node: usize) -> Result<(), HVMError> {
+        let value = match numb {
+            Numb::U24(value) => value,
+            _ => return Err(HVMError::Execution("Switch on an operator".to_string())),
+        };
+        let (s, r) = self.take(node);
+        let branch = if value == 0 {
+            self.alloc(r, Port::Era)?
+        } else {
+            let pred = self.alloc(Port::Num(Numb::U24(value - 1)), r)?;
+            self.alloc(Port::Era, Port::Node(NodeKind::Con, pred))?
+        };
+        self.link(s, Port::Node(NodeKind::Con, branch));
+        Ok(())
+    }
+
+    fn expand(&mut self, def: usize, target: Port) -> Result<(), HVMError> {
+        let defs = self.defs;
+        let def = &defs[def];
+        let base = self.wires.len();
+        self.wires.resize(base + def.vars, None);
+        let root = self.build(&def.root, base)?;
+        for (a, b) in &def.redexes {
+            let a = self.build(a, base)?;
+            let b = self.build(b, base)?;
+            self.link(a, b);
+        }
+        self.link(root, target);
+        Ok(())
+    }
+
+    fn build(&mut self, template: &Template, base: usize) -> Result<Port, HVMError> {
+        Ok(match template {
+            Template::Era => Port::Era,
+            Template::Ref(def) => Port::Ref(*def),
+            Template::Num(numb) => Port::Num(*numb),
+            Template::Var(var) => Port::Var(base + var),
+            Template::Node(kind, a, b) => {
+                let a = self.build(a, base)?;
+                let b = self.build(b, base)?;
+                Port::Node(*kind, self.alloc(a, b)?)
+            }
+        })
+    }
+
+    /// Connects two ports. Two principal ports form a redex; a wire end is
+    /// either resolved to what its other end was linked to, or records the
+    /// port for when the other end is linked.
+    fn link(&mut self, mut a: Port, mut b: Port) {
+        loop {
+            if let Port::Var(_) = b {
+                std::mem::swap(&mut a, &mut b);
+            }
+            let wire = match a {
+                Port::Var(wire) => wire,
+                _ => return self.redexes.push((a, b)),
+            };
+            match self.wires[wire].take() {
+                Some(other) => a = other,
+                None => return self.wires[wire] = Some(b),
+            }
+        }
+    }
+
+    /// Follows a wire whose other end has already been linked, consuming it.
+    fn enter(&mut self, mut port: Port) -> Port {
+        while let Port::Var(wire) = port {
+            match self.wires[wire].take() {
+                Some(other) => port = other,
+                None => break,
+            }
+        }
+        port
+    }
+
+    /// Follows wires without consuming them, for reading the result.
+    fn peek(&self, mut port: Port) -> Port {
+        while let Port::Var(wire) = port {
+            match self.wires[wire] {
+                Some(other) => port = other,
+                None => break,
+            }
+        }
+        port
+    }
+
+    fn read_outputs(&self, root: Port) -> Result<Vec<u32>, HVMError> {
+        let mut outputs = Vec::new();
+        let mut stack = vec![root];
+        while let Some(port) = stack.pop() {
+            match self.peek(port) {
+                Port::Num(Numb::U24(value)) => outputs.push(value),
+                Port::Era => {}
+                Port::Node(NodeKind::Con, node) => {
+                    let (a, b) = self.nodes[node];
+                    stack.extend([b, a]);
+                }
+                other => return Err(HVMError::Execution(format!("Net result is not data: found {:?}", other))),
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn alloc(&mut self, a: Port, b: Port) -> Result<usize, HVMError> {
+        let node = match self.free_nodes.pop() {
+            Some(node) => {
+                self.nodes[node] = (a, b);
+                node
+            }
+            None => {
+                self.nodes.push((a, b));
+                self.nodes.len() - 1
+            }
+        };
+        let live = self.nodes.len() - self.free_nodes.len();
+        if live > self.max_nodes {
+            return Err(HVMError::Execution(format!("Net exceeds the memory limit of {} nodes", self.max_nodes)));
+        }
+        self.peak_nodes = self.peak_nodes.max(live);
+        Ok(node)
+    }
+
+    fn take(&mut self, node: usize) -> (Port, Port) {
+        self.free_nodes.push(node);
+        self.nodes[node]
+    }
+
+    fn new_wire(&mut self) -> usize {
+        self.wires.push(None);
+        self.wires.len() - 1
+    }
+}
+
+/// Combines the number waiting in an operator node with the one that reached
+/// it. An operator and a plain number make a partial application, and a
+/// partial application and a plain number make the result; anything else is
+/// zero.
+fn apply(left: Numb, right: Numb) -> Numb {
+    match (left, right) {
+        (Numb::Op(op), Numb::U24(value)) | (Numb::U24(value), Numb::Op(op)) => Numb::Partial(op, value),
+        (Numb::Partial(op, a), Numb::U24(b)) | (Numb::U24(b), Numb::Partial(op, a)) => Numb::U24(op.apply(a, b)),
+        _ => Numb::U24(0),
+    }
+}
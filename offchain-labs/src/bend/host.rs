@@ -0,0 +1,325 @@
+//! Host functions available to Bend programs under the [`HOST_MODULE`] import
+//! namespace.
+//!
+//! Storage functions work on the calling program's namespace in rollup state.
+//! Reads see the committed storage with the call's own writes applied on top;
+//! writes are buffered and only returned to the caller when the call succeeds,
+//! so a trap discards them.
+//!
+//! - `storage_read(key_ptr, key_len, value_ptr, value_cap) -> i32` copies up
+//!   to `value_cap` bytes of the value and returns its full length, or `-1`
+//!   when the key is absent.
+//! - `storage_write(key_ptr, key_len, value_ptr, value_len)`
+//! - `storage_remove(key_ptr, key_len)`
+//!
+//! Besides the cost of calling them, storage functions charge
+//! [`storage_byte_cost`](super::metering::storage_byte_cost) for every key
+//! and value byte they read or write, and run out of gas like any other
+//! instruction when the call cannot pay for it.
+//!
+//! `call(id_ptr, id_len, input_ptr, input_len, gas, output_ptr, output_cap) -> i32`
+//! runs another deployed program with the given input. The program is named
+//! by its id, or by `name@requirement` to follow upgrades within a semver
+//! range. The callee's allowance
+//! is `gas` capped at the caller's remaining gas, and what it uses is charged
+//! to the caller; a failed callee consumes its whole allowance. Up to
+//! `output_cap` bytes of the output are copied and its full length returned.
+//! A failed callee, an unknown program id or a call beyond the depth limit
+//! returns one of the negative `CALL_*` codes instead of trapping, and the
+//! callee's writes are discarded.
+//!
+//! `emit(topic_ptr, topic_len, data_ptr, data_len)` appends an event to the
+//! call's log, charging
+//! [`storage_byte_cost`](super::metering::storage_byte_cost) for every topic
+//! and data byte. Like writes, the events of a failed call are discarded.
+
+use super::context::CallContext;
+use super::metering;
+use super::trace::TraceEnv;
+use super::Event;
+use crate::config::ExecutionConfig;
+use crate::zk_rollup::StateChanges;
+use log::debug;
+use std::sync::Arc;
+use wasmer::{AsStoreMut, Function, FunctionEnv, FunctionEnvMut, Global, Imports, Memory, MemoryView, RuntimeError, Value};
+use wasmparser::ValType;
+
+pub const HOST_MODULE: &str = "hvm";
+
+pub const STORAGE_READ: &str = "storage_read";
+pub const STORAGE_WRITE: &str = "storage_write";
+pub const STORAGE_REMOVE: &str = "storage_remove";
+pub const CALL: &str = "call";
+pub const EMIT: &str = "emit";
+
+pub const MAX_KEY_LEN: usize = 256;
+pub const MAX_VALUE_LEN: usize = 16 * 1024;
+pub const MAX_PROGRAM_REFERENCE_LEN: usize = 128;
+pub const MAX_CALL_INPUT_LEN: usize = 64 * 1024;
+pub const MAX_EVENT_TOPIC_LEN: usize = 64;
+pub const MAX_EVENT_DATA_LEN: usize = 16 * 1024;
+/// Most events a call tree can emit.
+pub const MAX_EVENTS: usize = 1024;
+
+pub const CALL_FAILED: i32 = -1;
+pub const CALL_PROGRAM_NOT_FOUND: i32 = -2;
+pub const CALL_DEPTH_EXCEEDED: i32 = -3;
+
+/// Signature of each host function, used to check imports at deployment.
+pub fn signature(name: &str) -> Option<(&'static [ValType], &'static [ValType])> {
+    match name {
+        STORAGE_READ => Some((&[ValType::I32, ValType::I32, ValType::I32, ValType::I32], &[ValType::I32])),
+        STORAGE_WRITE => Some((&[ValType::I32, ValType::I32, ValType::I32, ValType::I32], &[])),
+        STORAGE_REMOVE => Some((&[ValType::I32, ValType::I32], &[])),
+        CALL => Some((&[ValType::I32, ValType::I32, ValType::I32, ValType::I32, ValType::I64, ValType::I32, ValType::I32], &[ValType::I32])),
+        EMIT => Some((&[ValType::I32, ValType::I32, ValType::I32, ValType::I32], &[])),
+        _ => None,
+    }
+}
+
+/// Where a call's remaining gas lives, so host functions and nested calls
+/// can be charged to it.
+#[derive(Clone)]
+pub enum GasCounter {
+    Metered { remaining: Global, exhausted: Global },
+    Traced(FunctionEnv<TraceEnv>),
+}
+
+impl GasCounter {
+    fn remaining(&self, store: &mut impl AsStoreMut) -> Result<u64, RuntimeError> {
+        match self {
+            GasCounter::Metered { remaining, .. } => remaining.get(store).i64()
+                .map(|value| value as u64)
+                .ok_or_else(|| RuntimeError::new("Gas counter has an unexpected type")),
+            GasCounter::Traced(env) => Ok(env.as_ref(store).gas_remaining),
+        }
+    }
+
+    /// Takes `amount` from the remaining gas. When less is left, the call
+    /// is marked out of gas and trapped.
+    fn charge(&self, store: &mut impl AsStoreMut, amount: u64) -> Result<(), RuntimeError> {
+        let remaining = self.remaining(store)?;
+        let exhausted = amount > remaining;
+        let remaining = remaining.saturating_sub(amount);
+        match self {
+            GasCounter::Metered { remaining: global, exhausted: flag } => {
+                global.set(store, Value::I64(remaining as i64))?;
+                if exhausted {
+                    flag.set(store, Value::I32(1))?;
+                }
+            }
+            GasCounter::Traced(env) => {
+                let env = env.as_mut(store);
+                env.gas_remaining = remaining;
+                env.gas_exhausted |= exhausted;
+            }
+        }
+        if exhausted {
+            return Err(RuntimeError::new("out of gas"));
+        }
+        Ok(())
+    }
+}
+
+/// State of one program call within a call tree.
+pub struct CallFrame {
+    pub context: Arc<CallContext>,
+    /// Writes made so far in the call tree, including those of callers.
+    pub changes: StateChanges,
+    /// Events emitted so far in the call tree, including those of callers.
+    pub events: Vec<Event>,
+    pub depth: u32,
+}
+
+impl CallFrame {
+    pub fn new(context: Arc<CallContext>) -> Self {
+        Self { context, changes: StateChanges::new(), events: Vec::new(), depth: 0 }
+    }
+}
+
+pub struct HostEnv {
+    memory: Option<Memory>,
+    gas: Option<GasCounter>,
+    program_id: String,
+    frame: CallFrame,
+    config: ExecutionConfig,
+}
+
+impl HostEnv {
+    pub fn new(program_id: &str, frame: CallFrame, config: &ExecutionConfig) -> Self {
+        Self {
+            memory: None,
+            gas: None,
+            program_id: program_id.to_string(),
+            frame,
+            config: config.clone(),
+        }
+    }
+
+    pub fn set_memory(&mut self, memory: Memory) {
+        self.memory = Some(memory);
+    }
+
+    pub fn set_gas(&mut self, gas: GasCounter) {
+        self.gas = Some(gas);
+    }
+
+    pub fn take_changes(&mut self) -> StateChanges {
+        std::mem::take(&mut self.frame.changes)
+    }
+
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.frame.events)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        match self.frame.changes.get(&self.program_id).and_then(|changes| changes.get(key)) {
+            Some(value) => value.as_deref(),
+            None => self.frame.context.storage(&self.program_id).and_then(|storage| storage.get(key)),
+        }
+    }
+
+    fn set(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        self.frame.changes.entry(self.program_id.clone()).or_default().insert(key, value);
+    }
+
+    fn gas(&self) -> Result<&GasCounter, RuntimeError> {
+        self.gas.as_ref()
+            .ok_or_else(|| RuntimeError::new("Host function called before gas was attached"))
+    }
+
+    /// Charges the host function `name` for moving `bytes` bytes.
+    fn charge_bytes(&self, store: &mut impl AsStoreMut, name: &str, bytes: usize) -> Result<(), RuntimeError> {
+        self.gas()?.charge(store, metering::storage_byte_cost(name).saturating_mul(bytes as u64))
+    }
+}
+
+pub fn define_imports(imports: &mut Imports, store: &mut impl AsStoreMut, env: &FunctionEnv<HostEnv>) {
+    imports.define(HOST_MODULE, STORAGE_READ, Function::new_typed_with_env(store, env, storage_read));
+    imports.define(HOST_MODULE, STORAGE_WRITE, Function::new_typed_with_env(store, env, storage_write));
+    imports.define(HOST_MODULE, STORAGE_REMOVE, Function::new_typed_with_env(store, env, storage_remove));
+    imports.define(HOST_MODULE, CALL, Function::new_typed_with_env(store, env, call));
+    imports.define(HOST_MODULE, EMIT, Function::new_typed_with_env(store, env, emit));
+}
+
+fn storage_read(mut env: FunctionEnvMut<HostEnv>, key_ptr: i32, key_len: i32, value_ptr: i32, value_cap: i32) -> Result<i32, RuntimeError> {
+    let (env, mut store) = env.data_and_store_mut();
+    let key = read_bytes(&memory(env)?.view(&store), key_ptr, key_len, MAX_KEY_LEN)?;
+    let value = env.get(&key).map(<[u8]>::to_vec);
+    env.charge_bytes(&mut store, STORAGE_READ, key.len() + value.as_ref().map_or(0, Vec::len))?;
+
+    match value {
+        Some(value) => {
+            let len = value.len().min(value_cap as u32 as usize);
+            memory(env)?.view(&store).write(value_ptr as u32 as u64, &value[..len])
+                .map_err(|e| RuntimeError::new(format!("storage_read: {}", e)))?;
+            Ok(value.len() as i32)
+        }
+        None => Ok(-1),
+    }
+}
+
+fn storage_write(mut env: FunctionEnvMut<HostEnv>, key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32) -> Result<(), RuntimeError> {
+    let (env, mut store) = env.data_and_store_mut();
+    let (key, value) = {
+        let view = memory(env)?.view(&store);
+        (read_bytes(&view, key_ptr, key_len, MAX_KEY_LEN)?, read_bytes(&view, value_ptr, value_len, MAX_VALUE_LEN)?)
+    };
+    env.charge_bytes(&mut store, STORAGE_WRITE, key.len() + value.len())?;
+    env.set(key, Some(value));
+    Ok(())
+}
+
+fn storage_remove(mut env: FunctionEnvMut<HostEnv>, key_ptr: i32, key_len: i32) -> Result<(), RuntimeError> {
+    let (env, mut store) = env.data_and_store_mut();
+    let key = read_bytes(&memory(env)?.view(&store), key_ptr, key_len, MAX_KEY_LEN)?;
+    env.charge_bytes(&mut store, STORAGE_REMOVE, key.len())?;
+    env.set(key, None);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn call(
+    mut env: FunctionEnvMut<HostEnv>,
+    id_ptr: i32,
+    id_len: i32,
+    input_ptr: i32,
+    input_len: i32,
+    gas: i64,
+    output_ptr: i32,
+    output_cap: i32,
+) -> Result<i32, RuntimeError> {
+    let (env, mut store) = env.data_and_store_mut();
+    let (program_id, input) = {
+        let view = memory(env)?.view(&store);
+        let program_id = String::from_utf8(read_bytes(&view, id_ptr, id_len, MAX_PROGRAM_REFERENCE_LEN)?)
+            .map_err(|_| RuntimeError::new("call: program reference is not valid UTF-8"))?;
+        (program_id, read_bytes(&view, input_ptr, input_len, MAX_CALL_INPUT_LEN)?)
+    };
+
+    if env.frame.depth >= env.config.max_call_depth {
+        return Ok(CALL_DEPTH_EXCEEDED);
+    }
+    let program = match env.frame.context.resolve(&program_id) {
+        Some(program) => program.clone(),
+        None => return Ok(CALL_PROGRAM_NOT_FOUND),
+    };
+    let counter = env.gas()?.clone();
+    // Negative amounts wrap to large ones and are capped like any other.
+    let allowance = (gas as u64).min(counter.remaining(&mut store)?);
+
+    let config = ExecutionConfig { gas_limit: allowance, ..env.config.clone() };
+    let frame = CallFrame {
+        context: env.frame.context.clone(),
+        changes: env.frame.changes.clone(),
+        events: env.frame.events.clone(),
+        depth: env.frame.depth + 1,
+    };
+    match program.execute_frame(input, frame, &config) {
+        Ok(result) => {
+            counter.charge(&mut store, result.gas_used)?;
+            let output = result.output_bytes();
+            env.frame.changes = result.storage_changes;
+            env.frame.events = result.events;
+            let len = output.len().min(output_cap as u32 as usize);
+            memory(env)?.view(&store).write(output_ptr as u32 as u64, &output[..len])
+                .map_err(|e| RuntimeError::new(format!("call: {}", e)))?;
+            Ok(output.len() as i32)
+        }
+        Err(e) => {
+            debug!("Call to program {} failed: {}", program_id, e);
+            counter.charge(&mut store, allowance)?;
+            Ok(CALL_FAILED)
+        }
+    }
+}
+
+fn emit(mut env: FunctionEnvMut<HostEnv>, topic_ptr: i32, topic_len: i32, data_ptr: i32, data_len: i32) -> Result<(), RuntimeError> {
+    let (env, mut store) = env.data_and_store_mut();
+    let (topic, data) = {
+        let view = memory(env)?.view(&store);
+        (read_bytes(&view, topic_ptr, topic_len, MAX_EVENT_TOPIC_LEN)?, read_bytes(&view, data_ptr, data_len, MAX_EVENT_DATA_LEN)?)
+    };
+    env.charge_bytes(&mut store, EMIT, topic.len() + data.len())?;
+    if env.frame.events.len() >= MAX_EVENTS {
+        return Err(RuntimeError::new(format!("emit: more than {} events", MAX_EVENTS)));
+    }
+    env.frame.events.push(Event { program_id: env.program_id.clone(), topic, data });
+    Ok(())
+}
+
+fn memory(env: &HostEnv) -> Result<&Memory, RuntimeError> {
+    env.memory.as_ref()
+        .ok_or_else(|| RuntimeError::new("Host function called before memory was attached"))
+}
+
+fn read_bytes(view: &MemoryView, ptr: i32, len: i32, max_len: usize) -> Result<Vec<u8>, RuntimeError> {
+    let len = len as u32 as usize;
+    if len > max_len {
+        return Err(RuntimeError::new(format!("Buffer of {} bytes exceeds the limit of {} bytes", len, max_len)));
+    }
+    let mut bytes = vec![0u8; len];
+    view.read(ptr as u32 as u64, &mut bytes)
+        .map_err(|e| RuntimeError::new(format!("Failed to read guest memory: {}", e)))?;
+    Ok(bytes)
+}
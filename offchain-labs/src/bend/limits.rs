@@ -0,0 +1,210 @@
+use super::trace::TRACE_MODULE;
+use crate::error::HVMError;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+use wasmer::vm::{MemoryStyle, TableStyle, VMMemory, VMMemoryDefinition, VMTable, VMTableDefinition};
+use wasmer::wasmparser::{BlockType, Operator};
+use wasmer::{
+    AsStoreMut, ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, Instance,
+    LocalFunctionIndex, MemoryError, MemoryType, MiddlewareError, MiddlewareReaderState,
+    ModuleMiddleware, Mutability, Pages, TableType, Tunables, Type,
+};
+use wasmer_types::{GlobalIndex, ImportIndex, ModuleInfo};
+
+const STACK_LIMIT_EXPORT: &str = "hvm_stack_limit";
+const STACK_EXCEEDED_EXPORT: &str = "hvm_stack_exceeded";
+
+/// Tunables that cap every linear memory at a fixed number of pages.
+///
+/// Memories without a declared maximum, or with a larger one, are clamped to
+/// the limit so `memory.grow` past it fails inside the guest instead of
+/// allocating on the host.
+pub struct MemoryLimit<T: Tunables> {
+    max_pages: Pages,
+    base: T,
+}
+
+impl<T: Tunables> MemoryLimit<T> {
+    pub fn new(base: T, max_pages: u32) -> Self {
+        Self {
+            max_pages: Pages(max_pages),
+            base,
+        }
+    }
+
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = *requested;
+        adjusted.maximum = Some(requested.maximum.map_or(self.max_pages, |max| max.min(self.max_pages)));
+        adjusted
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        if ty.minimum > self.max_pages {
+            return Err(MemoryError::Generic(format!(
+                "Initial memory of {} pages exceeds the limit of {} pages",
+                ty.minimum.0, self.max_pages.0
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<T: Tunables> Tunables for MemoryLimit<T> {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(&self.adjust_memory(memory))
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(&self, ty: &MemoryType, style: &MemoryStyle) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base.create_host_memory(&adjusted, style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base.create_vm_memory(&adjusted, style, vm_definition_location)
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+}
+
+/// Call-depth limiting.
+///
+/// Every `call` and `call_indirect` bumps a depth counter before the call and
+/// restores it afterwards. Exceeding the limit raises a flag and traps, which
+/// keeps recursion from exhausting the host stack and fails at the same depth
+/// on every node. Calls into the trace hooks are not counted, so a traced
+/// execution overflows at the same depth as an untraced one.
+#[derive(Debug)]
+pub struct StackLimiter {
+    global_indexes: Mutex<Option<StackGlobals>>,
+    trace_hooks: Mutex<Vec<u32>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct StackGlobals {
+    depth: GlobalIndex,
+    limit: GlobalIndex,
+    exceeded: GlobalIndex,
+}
+
+#[derive(Debug)]
+struct FunctionStackLimiter {
+    globals: StackGlobals,
+    trace_hooks: Vec<u32>,
+}
+
+impl StackLimiter {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            global_indexes: Mutex::new(None),
+            trace_hooks: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+impl ModuleMiddleware for StackLimiter {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        let globals = self.global_indexes.lock().unwrap()
+            .expect("StackLimiter::generate_function_middleware called before transform_module_info");
+        let trace_hooks = self.trace_hooks.lock().unwrap().clone();
+        Box::new(FunctionStackLimiter { globals, trace_hooks })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+        if global_indexes.is_some() {
+            panic!("StackLimiter middleware must not be shared between modules");
+        }
+
+        let depth = module_info.globals.push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I32Const(0));
+
+        let limit = module_info.globals.push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I32Const(0));
+        module_info.exports.insert(STACK_LIMIT_EXPORT.to_string(), ExportIndex::Global(limit));
+
+        let exceeded = module_info.globals.push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I32Const(0));
+        module_info.exports.insert(STACK_EXCEEDED_EXPORT.to_string(), ExportIndex::Global(exceeded));
+
+        *global_indexes = Some(StackGlobals { depth, limit, exceeded });
+        *self.trace_hooks.lock().unwrap() = module_info.imports.iter()
+            .filter(|(key, _)| key.module == TRACE_MODULE)
+            .filter_map(|(_, index)| match index {
+                ImportIndex::Function(function) => Some(function.as_u32()),
+                _ => None,
+            })
+            .collect();
+    }
+}
+
+impl FunctionMiddleware for FunctionStackLimiter {
+    fn feed<'a>(&mut self, operator: Operator<'a>, state: &mut MiddlewareReaderState<'a>) -> Result<(), MiddlewareError> {
+        let depth = self.globals.depth.as_u32();
+        match operator {
+            Operator::Call { function_index } if self.trace_hooks.contains(&function_index) => {
+                state.push_operator(operator);
+            }
+            Operator::Call { .. } | Operator::CallIndirect { .. } => {
+                state.extend(&[
+                    Operator::GlobalGet { global_index: depth },
+                    Operator::I32Const { value: 1 },
+                    Operator::I32Add,
+                    Operator::GlobalSet { global_index: depth },
+                    Operator::GlobalGet { global_index: depth },
+                    Operator::GlobalGet { global_index: self.globals.limit.as_u32() },
+                    Operator::I32GtU,
+                    Operator::If { blockty: BlockType::Empty },
+                    Operator::I32Const { value: 1 },
+                    Operator::GlobalSet { global_index: self.globals.exceeded.as_u32() },
+                    Operator::Unreachable,
+                    Operator::End,
+                ]);
+                state.push_operator(operator);
+                state.extend(&[
+                    Operator::GlobalGet { global_index: depth },
+                    Operator::I32Const { value: 1 },
+                    Operator::I32Sub,
+                    Operator::GlobalSet { global_index: depth },
+                ]);
+            }
+            _ => state.push_operator(operator),
+        }
+        Ok(())
+    }
+}
+
+pub fn set_stack_limit(store: &mut impl AsStoreMut, instance: &Instance, max_depth: u32) -> Result<(), HVMError> {
+    let limit = instance.exports.get_global(STACK_LIMIT_EXPORT)
+        .map_err(|e| HVMError::Execution(format!("Module has no stack limit: {}", e)))?;
+    limit.set(store, (max_depth as i32).into())
+        .map_err(|e| HVMError::Execution(format!("Failed to set stack limit: {}", e)))
+}
+
+pub fn stack_exceeded(store: &mut impl AsStoreMut, instance: &Instance) -> Result<bool, HVMError> {
+    let exceeded = instance.exports.get_global(STACK_EXCEEDED_EXPORT)
+        .map_err(|e| HVMError::Execution(format!("Module has no stack limit: {}", e)))?;
+    Ok(exceeded.get(store).i32().unwrap_or(0) != 0)
+}
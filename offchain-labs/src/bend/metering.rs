@@ -0,0 +1,260 @@
+use super::host;
+use crate::error::HVMError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wasmer::wasmparser::{BlockType, Operator};
+use wasmer::{
+    AsStoreMut, ExportIndex, FunctionMiddleware, Global, GlobalInit, GlobalType, Instance,
+    LocalFunctionIndex, MiddlewareError, MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+};
+use wasmer_types::{GlobalIndex, ImportIndex, ModuleInfo};
+
+const GAS_REMAINING_EXPORT: &str = "hvm_gas_remaining";
+const GAS_EXHAUSTED_EXPORT: &str = "hvm_gas_exhausted";
+
+/// Gas charged for every WebAssembly operator.
+///
+/// Costs are fixed per opcode so every node charges exactly the same amount
+/// for the same call, independent of the host it runs on.
+pub fn operator_cost(operator: &Operator) -> u64 {
+    match operator {
+        Operator::Nop
+        | Operator::Unreachable
+        | Operator::Block { .. }
+        | Operator::Loop { .. }
+        | Operator::Else
+        | Operator::End
+        | Operator::Drop => 0,
+
+        Operator::LocalGet { .. }
+        | Operator::LocalSet { .. }
+        | Operator::LocalTee { .. }
+        | Operator::I32Const { .. }
+        | Operator::I64Const { .. }
+        | Operator::F32Const { .. }
+        | Operator::F64Const { .. }
+        | Operator::Select
+        | Operator::TypedSelect { .. } => 1,
+
+        Operator::GlobalGet { .. } | Operator::GlobalSet { .. } => 2,
+
+        Operator::If { .. } | Operator::Br { .. } | Operator::BrIf { .. } => 2,
+        Operator::BrTable { .. } => 5,
+        Operator::Return => 2,
+        Operator::Call { .. } => 10,
+        Operator::CallIndirect { .. } => 20,
+
+        Operator::I32Mul | Operator::I64Mul | Operator::F32Mul | Operator::F64Mul => 3,
+
+        Operator::I32DivS
+        | Operator::I32DivU
+        | Operator::I32RemS
+        | Operator::I32RemU
+        | Operator::I64DivS
+        | Operator::I64DivU
+        | Operator::I64RemS
+        | Operator::I64RemU
+        | Operator::F32Div
+        | Operator::F64Div
+        | Operator::F32Sqrt
+        | Operator::F64Sqrt => 8,
+
+        Operator::I32Load { .. }
+        | Operator::I64Load { .. }
+        | Operator::F32Load { .. }
+        | Operator::F64Load { .. }
+        | Operator::I32Load8S { .. }
+        | Operator::I32Load8U { .. }
+        | Operator::I32Load16S { .. }
+        | Operator::I32Load16U { .. }
+        | Operator::I64Load8S { .. }
+        | Operator::I64Load8U { .. }
+        | Operator::I64Load16S { .. }
+        | Operator::I64Load16U { .. }
+        | Operator::I64Load32S { .. }
+        | Operator::I64Load32U { .. } => 3,
+
+        Operator::I32Store { .. }
+        | Operator::I64Store { .. }
+        | Operator::F32Store { .. }
+        | Operator::F64Store { .. }
+        | Operator::I32Store8 { .. }
+        | Operator::I32Store16 { .. }
+        | Operator::I64Store8 { .. }
+        | Operator::I64Store16 { .. }
+        | Operator::I64Store32 { .. } => 4,
+
+        Operator::MemorySize { .. } => 2,
+        Operator::MemoryGrow { .. } => 1_000,
+        Operator::MemoryCopy { .. } | Operator::MemoryFill { .. } | Operator::MemoryInit { .. } => 50,
+
+        _ => 1,
+    }
+}
+
+/// Gas charged for a call to a host function, on top of the `call` itself.
+pub fn host_function_cost(name: &str) -> u64 {
+    match name {
+        host::STORAGE_READ => 200,
+        host::STORAGE_WRITE => 500,
+        host::STORAGE_REMOVE => 300,
+        host::CALL => 1000,
+        host::EMIT => 400,
+        _ => 0,
+    }
+}
+
+/// Gas charged per key or value byte a storage host function moves, or per
+/// topic and data byte of an emitted event, on top of its
+/// [`host_function_cost`]. Charged by the host function itself.
+pub fn storage_byte_cost(name: &str) -> u64 {
+    match name {
+        host::STORAGE_READ => 2,
+        host::STORAGE_WRITE => 20,
+        host::STORAGE_REMOVE => 2,
+        host::EMIT => 8,
+        _ => 0,
+    }
+}
+
+/// Instruction-level gas metering.
+///
+/// Every basic block is prefixed with a check against a mutable global holding
+/// the remaining gas. When the block would overdraw it, the exhausted flag is
+/// raised and the instance traps, so running out of gas happens at the same
+/// instruction on every node.
+#[derive(Debug)]
+pub struct Metering {
+    global_indexes: Mutex<Option<MeteringGlobals>>,
+    host_costs: Mutex<HashMap<u32, u64>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct MeteringGlobals {
+    remaining: GlobalIndex,
+    exhausted: GlobalIndex,
+}
+
+#[derive(Debug)]
+struct FunctionMetering {
+    globals: MeteringGlobals,
+    host_costs: HashMap<u32, u64>,
+    accumulated_cost: u64,
+}
+
+impl Metering {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            global_indexes: Mutex::new(None),
+            host_costs: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl ModuleMiddleware for Metering {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        let globals = self.global_indexes.lock().unwrap()
+            .expect("Metering::generate_function_middleware called before transform_module_info");
+        Box::new(FunctionMetering {
+            globals,
+            host_costs: self.host_costs.lock().unwrap().clone(),
+            accumulated_cost: 0,
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+        if global_indexes.is_some() {
+            panic!("Metering middleware must not be shared between modules");
+        }
+
+        let remaining = module_info.globals.push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I64Const(0));
+        module_info.exports.insert(GAS_REMAINING_EXPORT.to_string(), ExportIndex::Global(remaining));
+
+        let exhausted = module_info.globals.push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I32Const(0));
+        module_info.exports.insert(GAS_EXHAUSTED_EXPORT.to_string(), ExportIndex::Global(exhausted));
+
+        *global_indexes = Some(MeteringGlobals { remaining, exhausted });
+        *self.host_costs.lock().unwrap() = module_info.imports.iter()
+            .filter(|(key, _)| key.module == host::HOST_MODULE)
+            .filter_map(|(key, index)| match index {
+                ImportIndex::Function(function) => Some((function.as_u32(), host_function_cost(&key.field))),
+                _ => None,
+            })
+            .collect();
+    }
+}
+
+impl FunctionMiddleware for FunctionMetering {
+    fn feed<'a>(&mut self, operator: Operator<'a>, state: &mut MiddlewareReaderState<'a>) -> Result<(), MiddlewareError> {
+        self.accumulated_cost += operator_cost(&operator);
+        if let Operator::Call { function_index } = operator {
+            self.accumulated_cost += self.host_costs.get(&function_index).copied().unwrap_or(0);
+        }
+
+        match operator {
+            Operator::Loop { .. }
+            | Operator::If { .. }
+            | Operator::End
+            | Operator::Else
+            | Operator::Br { .. }
+            | Operator::BrTable { .. }
+            | Operator::BrIf { .. }
+            | Operator::Call { .. }
+            | Operator::CallIndirect { .. }
+            | Operator::Return if self.accumulated_cost > 0 => {
+                let remaining = self.globals.remaining.as_u32();
+                let cost = self.accumulated_cost as i64;
+                state.extend(&[
+                    Operator::GlobalGet { global_index: remaining },
+                    Operator::I64Const { value: cost },
+                    Operator::I64LtU,
+                    Operator::If { blockty: BlockType::Empty },
+                    Operator::I32Const { value: 1 },
+                    Operator::GlobalSet { global_index: self.globals.exhausted.as_u32() },
+                    Operator::Unreachable,
+                    Operator::End,
+                    Operator::GlobalGet { global_index: remaining },
+                    Operator::I64Const { value: cost },
+                    Operator::I64Sub,
+                    Operator::GlobalSet { global_index: remaining },
+                ]);
+                self.accumulated_cost = 0;
+            }
+            _ => {}
+        }
+
+        state.push_operator(operator);
+        Ok(())
+    }
+}
+
+/// The global holding the remaining gas of a metered instance.
+pub fn gas_counter(instance: &Instance) -> Result<&Global, HVMError> {
+    instance.exports.get_global(GAS_REMAINING_EXPORT)
+        .map_err(|e| HVMError::Execution(format!("Module is not metered: {}", e)))
+}
+
+/// Loads the gas allowance for the next call into a metered instance.
+pub fn set_gas_limit(store: &mut impl AsStoreMut, instance: &Instance, gas_limit: u64) -> Result<(), HVMError> {
+    gas_counter(instance)?.set(store, (gas_limit as i64).into())
+        .map_err(|e| HVMError::Execution(format!("Failed to set gas limit: {}", e)))
+}
+
+pub fn gas_remaining(store: &mut impl AsStoreMut, instance: &Instance) -> Result<u64, HVMError> {
+    gas_counter(instance)?.get(store).i64()
+        .map(|value| value as u64)
+        .ok_or_else(|| HVMError::Execution("Gas counter has an unexpected type".to_string()))
+}
+
+/// The global flagging that a metered instance ran out of gas.
+pub fn gas_exhausted_flag(instance: &Instance) -> Result<&Global, HVMError> {
+    instance.exports.get_global(GAS_EXHAUSTED_EXPORT)
+        .map_err(|e| HVMError::Execution(format!("Module is not metered: {}", e)))
+}
+
+pub fn gas_exhausted(store: &mut impl AsStoreMut, instance: &Instance) -> Result<bool, HVMError> {
+    Ok(gas_exhausted_flag(instance)?.get(store).i32().unwrap_or(0) != 0)
+}
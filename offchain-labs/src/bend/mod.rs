@@ -1,94 +1,320 @@
 use crate::error::HVMError;
 use ark_bn254::Fr;
-use ark_ff::PrimeField;
+use ark_ff::{BigInteger, PrimeField};
 use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
 use ark_relations::lc;
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
-use wasmer::{Store, Module, Instance, Value, imports, Memory};
+use wasmer::{BaseTunables, CompilerConfig, Cranelift, Engine, EngineBuilder, FunctionEnv, Imports, Store, Module, Instance, Target};
 use log::{error, debug};
+use crate::config::ExecutionConfig;
+use crate::zk_rollup::StateChanges;
+use std::sync::Arc;
 
+pub mod abi;
+pub mod cache;
+pub mod compiler;
+pub mod context;
+pub mod evaluator;
+pub mod host;
+pub mod limits;
+pub mod metering;
+pub mod net;
+pub mod optimizer;
+pub mod registry;
 pub mod storage;
+pub mod trace;
+pub mod validation;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+pub use context::CallContext;
+pub use registry::ProgramRegistry;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BendProgram {
     pub id: String,
     pub bytecode: Vec<u8>,
     pub metadata: ProgramMetadata,
     pub author: String,
+    #[serde(default)]
+    pub backend: ExecutionBackend,
+}
+
+/// How a program's bytecode is run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionBackend {
+    /// A WASM module implementing the program ABI, metered per instruction.
+    #[default]
+    Wasm,
+    /// An HVM2 interaction net in text form, run by the built-in evaluator
+    /// and metered per interaction.
+    Hvm,
+}
+
+impl ExecutionBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Wasm => "wasm",
+            Self::Hvm => "hvm",
+        }
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ProgramMetadata {
     pub name: String,
     pub version: String,
     pub description: String,
+    /// Source the bytecode was compiled from, if it was built by the node's
+    /// toolchain rather than supplied as WASM.
+    #[serde(default)]
+    pub source: Option<compiler::SourceInfo>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExecutionResult {
+    pub outputs: Vec<Fr>,
+    pub gas_used: u64,
+    pub memory_usage: u64,
+    /// Writes of the call and of any programs it called, by program id.
+    pub storage_changes: StateChanges,
+    /// Events of the call and of any programs it called, in emission order.
+    pub events: Vec<Event>,
+}
+
+/// Log entry a program emitted through the `emit` host function.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub program_id: String,
+    pub topic: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl ExecutionResult {
+    /// Outputs in the byte layout the program returned them.
+    pub fn output_bytes(&self) -> Vec<u8> {
+        self.outputs.iter()
+            .flat_map(|output| output.into_bigint().to_bytes_le())
+            .collect()
+    }
 }
 
 impl BendProgram {
     pub fn new(bytecode: Vec<u8>, metadata: ProgramMetadata, author: String) -> Self {
+        Self::with_backend(ExecutionBackend::Wasm, bytecode, metadata, author)
+    }
+
+    pub fn with_backend(backend: ExecutionBackend, bytecode: Vec<u8>, metadata: ProgramMetadata, author: String) -> Self {
         let id = Self::generate_id(&bytecode);
-        Self { id, bytecode, metadata, author }
+        Self { id, bytecode, metadata, author, backend }
+    }
+
+    /// A program carrying an HVM2 net, run by the built-in evaluator.
+    pub fn from_net(net: &str, metadata: ProgramMetadata, author: String) -> Self {
+        Self::with_backend(ExecutionBackend::Hvm, net.as_bytes().to_vec(), metadata, author)
+    }
+
+    /// Compiles Bend source or an HVM2 net and records the source hash and
+    /// toolchain versions in the program's metadata.
+    pub fn from_source(
+        language: compiler::SourceLanguage,
+        source: &str,
+        metadata: ProgramMetadata,
+        author: String,
+        config: &crate::config::CompilerConfig,
+    ) -> Result<Self, HVMError> {
+        let compiled = compiler::compile(language, source, config)?;
+        let metadata = ProgramMetadata { source: Some(compiled.source), ..metadata };
+        Ok(Self::new(compiled.bytecode, metadata, author))
+    }
+
+    /// Checks that `source` is what the program was compiled from and that
+    /// compiling it again reproduces the deployed bytecode.
+    pub fn verify_source(&self, source: &str, config: &crate::config::CompilerConfig) -> Result<(), HVMError> {
+        let info = self.metadata.source.as_ref()
+            .ok_or_else(|| HVMError::Compilation(format!("Program {} was not compiled from source", self.id)))?;
+        if compiler::hash_source(source) != info.source_hash {
+            return Err(HVMError::Compilation(format!("Source does not match the hash recorded for program {}", self.id)));
+        }
+        let compiled = compiler::compile(info.language, source, config)?;
+        if Self::generate_id(&compiled.bytecode) != self.id {
+            return Err(HVMError::Compilation(format!(
+                "Compiling the source of program {} with {} does not reproduce its bytecode",
+                self.id, compiled.source.compiler,
+            )));
+        }
+        Ok(())
     }
 
     pub fn id(&self) -> &str {
         &self.id
     }
 
-    pub fn execute(&self, inputs: Vec<u8>) -> Result<Vec<Fr>, HVMError> {
-        let mut store = Store::default();
-        let module = Module::new(&store, &self.bytecode)
-            .map_err(|e| HVMError::Execution(format!("Failed to create module: {}", e)))?;
-        let import_object = imports! {};
-        let instance = Instance::new(&mut store, &module, &import_object)
-            .map_err(|e| HVMError::Execution(format!("Failed to instantiate module: {}", e)))?;
-    
-        let memory = instance.exports.get_memory("memory")
-            .map_err(|e| HVMError::Execution(format!("Module does not export memory: {}", e)))?;
-    
-        self.write_inputs_to_memory(&store, &memory, &inputs)?;
-    
-        let run = instance.exports.get_function("run")
-            .map_err(|e| HVMError::Execution(format!("Failed to get run function: {}", e)))?;
-    
+    /// Hash of everything in the program, which the state root commits to
+    /// once it is deployed.
+    pub fn hash(&self) -> [u8; 32] {
+        let metadata = &self.metadata;
+        let source = metadata.source.as_ref();
+        let fields = [
+            self.id.as_bytes(),
+            &self.bytecode,
+            self.backend.name().as_bytes(),
+            self.author.as_bytes(),
+            metadata.name.as_bytes(),
+            metadata.version.as_bytes(),
+            metadata.description.as_bytes(),
+            source.map_or(&b""[..], |source| source.language.extension().as_bytes()),
+            source.map_or(&b""[..], |source| source.source_hash.as_bytes()),
+            source.map_or(&b""[..], |source| source.compiler.as_bytes()),
+        ];
+        let mut hasher = Sha256::new();
+        for field in fields {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Executes the program on its own, without storage or other programs.
+    pub fn execute(&self, inputs: Vec<u8>, config: &ExecutionConfig) -> Result<ExecutionResult, HVMError> {
+        self.execute_in(inputs, &Arc::new(CallContext::new()), config)
+    }
+
+    /// Executes the program against the storage and programs of `context`.
+    /// Writes are returned in the result rather than applied.
+    pub fn execute_in(&self, inputs: Vec<u8>, context: &Arc<CallContext>, config: &ExecutionConfig) -> Result<ExecutionResult, HVMError> {
+        self.execute_frame(inputs, host::CallFrame::new(context.clone()), config)
+    }
+
+    pub(crate) fn execute_frame(&self, inputs: Vec<u8>, frame: host::CallFrame, config: &ExecutionConfig) -> Result<ExecutionResult, HVMError> {
+        if self.backend == ExecutionBackend::Hvm {
+            // Nets cannot write or emit, so the call tree's writes and events
+            // so far are passed back unchanged.
+            let result = evaluator::execute(&self.net()?, &inputs, config)?;
+            return Ok(ExecutionResult { storage_changes: frame.changes, events: frame.events, ..result });
+        }
+
+        let mut store = create_store(config, true);
+        let module = self.compile(&store, config)?;
+        let host_env = FunctionEnv::new(&mut store, host::HostEnv::new(&self.id, frame, config));
+        let mut import_object = Imports::new();
+        host::define_imports(&mut import_object, &mut store, &host_env);
+        let instance = instantiate(&mut store, &module, &import_object, &host_env)?;
+        metering::set_gas_limit(&mut store, &instance, config.gas_limit)?;
+        limits::set_stack_limit(&mut store, &instance, config.max_stack_depth)?;
+        let gas = host::GasCounter::Metered {
+            remaining: metering::gas_counter(&instance)?.clone(),
+            exhausted: metering::gas_exhausted_flag(&instance)?.clone(),
+        };
+        host_env.as_mut(&mut store).set_gas(gas);
+
+        let mut result = Self::run(&mut store, &instance, &inputs, config, |store| {
+            Ok((metering::gas_remaining(store, &instance)?, metering::gas_exhausted(store, &instance)?))
+        })?;
+        result.storage_changes = host_env.as_mut(&mut store).take_changes();
+        result.events = host_env.as_mut(&mut store).take_events();
+        Ok(result)
+    }
+
+    /// Executes the program while recording an execution trace.
+    ///
+    /// The trace is returned even when execution fails, up to the point of
+    /// failure. Programs called through the `call` host function run untraced
+    /// and show up as a single host call. Only WASM programs can be traced.
+    pub fn execute_traced(&self, inputs: Vec<u8>, context: &Arc<CallContext>, config: &ExecutionConfig) -> (Result<ExecutionResult, HVMError>, trace::ExecutionTrace) {
+        if self.backend != ExecutionBackend::Wasm {
+            let error = HVMError::Execution(format!("Tracing is not supported for {} programs", self.backend.name()));
+            return (Err(error), trace::ExecutionTrace::new());
+        }
+        let mut store = create_store(config, false);
+        let trace_env = FunctionEnv::new(&mut store, trace::TraceEnv::new(config.gas_limit));
+        let frame = host::CallFrame::new(context.clone());
+        let host_env = FunctionEnv::new(&mut store, host::HostEnv::new(&self.id, frame, config));
+        host_env.as_mut(&mut store).set_gas(host::GasCounter::Traced(trace_env.clone()));
+        let result = trace::instrument(&self.bytecode)
+            .and_then(|bytecode| cache::compile(&store, &bytecode))
+            .and_then(|module| {
+                let mut import_object = Imports::new();
+                host::define_imports(&mut import_object, &mut store, &host_env);
+                trace::define_hooks(&mut import_object, &mut store, &trace_env);
+                let instance = instantiate(&mut store, &module, &import_object, &host_env)?;
+                limits::set_stack_limit(&mut store, &instance, config.max_stack_depth)?;
+
+                let mut result = Self::run(&mut store, &instance, &inputs, config, |store| {
+                    let env = trace_env.as_ref(store);
+                    Ok((env.gas_remaining, env.gas_exhausted))
+                })?;
+                result.storage_changes = host_env.as_mut(&mut store).take_changes();
+                result.events = host_env.as_mut(&mut store).take_events();
+                Ok(result)
+            });
+        let trace = std::mem::take(&mut trace_env.as_mut(&mut store).trace);
+        (result, trace)
+    }
+
+    fn run(
+        store: &mut Store,
+        instance: &Instance,
+        inputs: &[u8],
+        config: &ExecutionConfig,
+        gas: impl Fn(&mut Store) -> Result<(u64, bool), HVMError>,
+    ) -> Result<ExecutionResult, HVMError> {
+        let start_memory = abi::memory(instance)?.view(store).data_size();
+
         debug!("Executing WebAssembly module");
-        let result = run.call(&mut store, &[]);
+        let result = Self::call_entry_point(store, instance, inputs);
+        let (gas_remaining, gas_exhausted) = gas(store)?;
+        let gas_used = config.gas_limit - gas_remaining.min(config.gas_limit);
         match result {
-            Ok(output) => {
-                debug!("WebAssembly execution successful");
-                self.read_outputs_from_memory(&store, &memory, &output)
+            Ok(output_bytes) => {
+                debug!("WebAssembly execution successful, gas used: {}", gas_used);
+                let outputs = output_bytes.chunks_exact(abi::OUTPUT_ELEMENT_SIZE)
+                    .map(Fr::from_le_bytes_mod_order)
+                    .collect::<Vec<_>>();
+                let memory_usage = abi::memory(instance)?.view(store).data_size().saturating_sub(start_memory);
+                Ok(ExecutionResult { outputs, gas_used, memory_usage, storage_changes: StateChanges::new(), events: Vec::new() })
+            },
+            Err(_) if gas_exhausted => {
+                error!("WebAssembly execution ran out of gas");
+                Err(HVMError::OutOfGas(config.gas_limit))
+            },
+            Err(_) if limits::stack_exceeded(store, instance)? => {
+                error!("WebAssembly execution exceeded the call depth limit");
+                Err(HVMError::StackOverflow(config.max_stack_depth))
             },
             Err(e) => {
                 error!("WebAssembly execution failed: {}", e);
-                Err(HVMError::Execution(format!("Failed to execute program: {}", e)))
+                Err(e)
             }
         }
     }
 
-    fn write_inputs_to_memory(&self, store: &Store, memory: &Memory, inputs: &[u8]) -> Result<(), HVMError> {
-        let mem_view = memory.view(store);
-        mem_view.write(0, inputs)
-            .map_err(|e| HVMError::Execution(format!("Failed to write inputs to memory: {}", e)))?;
-        Ok(())
+    fn compile(&self, store: &Store, config: &ExecutionConfig) -> Result<Module, HVMError> {
+        match &config.cache_dir {
+            Some(dir) => cache::ModuleCache::new(dir)?.load_or_compile(store, &self.id, &self.bytecode, config),
+            None => cache::compile(store, &self.bytecode),
+        }
     }
 
-    fn read_outputs_from_memory(&self, store: &Store, memory: &Memory, results: &[Value]) -> Result<Vec<Fr>, HVMError> {
-        let ptr = results[0].i32().unwrap() as u64;
-        let len = results[1].i32().unwrap() as u64;
-        let mem_view = memory.view(store);
-        let mut output_bytes = vec![0u8; len as usize];
-        mem_view.read(ptr, &mut output_bytes)
-            .map_err(|e| HVMError::Execution(format!("Failed to read outputs from memory: {}", e)))?;
-    
-        let field_elements = output_bytes.chunks_exact(32)
-            .map(|chunk| Fr::from_le_bytes_mod_order(chunk))
-            .collect::<Vec<_>>();
-    
-        Ok(field_elements)
+    fn call_entry_point(store: &mut Store, instance: &Instance, inputs: &[u8]) -> Result<Vec<u8>, HVMError> {
+        let run = abi::entry_point(store, instance)?;
+        let (ptr, len) = abi::write_inputs(store, instance, inputs)?;
+        let output = run.call(store, ptr, len)
+            .map_err(|e| HVMError::Execution(format!("Failed to execute program: {}", e)))?;
+        abi::read_outputs(store, instance, output)
     }
 
-    pub fn get_public_inputs(&self) -> Vec<Fr> {
-        vec![Fr::from(1u64)]
+    pub fn validate(&self, config: &ExecutionConfig) -> Result<(), HVMError> {
+        match self.backend {
+            ExecutionBackend::Wasm => validation::validate_bytecode(&self.bytecode, config),
+            ExecutionBackend::Hvm => net::validate(&self.net()?, config),
+        }
+    }
+
+    /// Parses the net carried by an HVM program.
+    pub fn net(&self) -> Result<net::Book, HVMError> {
+        let text = std::str::from_utf8(&self.bytecode)
+            .map_err(|e| HVMError::Validation(format!("Net is not valid UTF-8: {}", e)))?;
+        net::parse(text)
     }
 
     fn generate_id(bytecode: &[u8]) -> String {
@@ -98,28 +324,105 @@ impl BendProgram {
     }
 }
 
+fn instantiate(store: &mut Store, module: &Module, import_object: &Imports, host_env: &FunctionEnv<host::HostEnv>) -> Result<Instance, HVMError> {
+    let instance = Instance::new(store, module, import_object)
+        .map_err(|e| HVMError::Execution(format!("Failed to instantiate module: {}", e)))?;
+    let memory = abi::memory(&instance)?.clone();
+    host_env.as_mut(store).set_memory(memory);
+    Ok(instance)
+}
+
+/// Traced executions are compiled without the metering middleware, as the
+/// trace hooks charge gas themselves.
+fn create_store(config: &ExecutionConfig, metered: bool) -> Store {
+    let mut compiler = Cranelift::default();
+    if metered {
+        compiler.push_middleware(metering::Metering::new());
+    }
+    compiler.push_middleware(limits::StackLimiter::new());
+    let mut engine: Engine = EngineBuilder::new(compiler).into();
+    engine.set_tunables(limits::MemoryLimit::new(BaseTunables::for_target(&Target::default()), config.max_memory_pages));
+    Store::new(engine)
+}
+
+/// Circuit proven for every batch. Its public inputs are the same whatever
+/// the batch holds: a commitment to the batch's receipts, then those of the
+/// forced inclusion check.
+#[derive(Default)]
 pub struct BendCircuit {
-    pub inputs: Vec<Fr>,
-    pub outputs: Vec<Fr>,
+    /// Receipts root of the batch as a field element.
+    pub receipts_commitment: Fr,
+    pub forced_inclusion: ForcedInclusionCheck,
+}
+
+impl BendCircuit {
+    pub fn public_inputs(&self) -> Vec<Fr> {
+        std::iter::once(self.receipts_commitment)
+            .chain(self.forced_inclusion.public_inputs())
+            .collect()
+    }
 }
 
-impl Default for BendCircuit {
+/// Forced inclusion rule of a batch: no transaction left in the forced
+/// inclusion queue may have a deadline at or before the batch's number.
+///
+/// Both numbers are public inputs, so the L1 can supply them from its own
+/// queue. The circuit proves `next_deadline - batch_number - 1` fits in
+/// [`FORCED_INCLUSION_SLACK_BITS`] bits, which rules out an overdue
+/// transaction as long as deadlines stay within that range of the batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForcedInclusionCheck {
+    pub batch_number: u64,
+    /// Deadline of the oldest transaction still queued after the batch, or
+    /// `batch_number + 1` when the queue is empty.
+    pub next_deadline: u64,
+}
+
+pub const FORCED_INCLUSION_SLACK_BITS: usize = 32;
+
+impl Default for ForcedInclusionCheck {
     fn default() -> Self {
-        Self {
-            inputs: Vec::new(),
-            outputs: Vec::new(),
-        }
+        Self { batch_number: 0, next_deadline: 1 }
     }
 }
 
-impl ConstraintSynthesizer<Fr> for BendCircuit {
+impl ForcedInclusionCheck {
+    pub fn is_satisfied(&self) -> bool {
+        self.next_deadline.checked_sub(self.batch_number + 1)
+            .is_some_and(|slack| slack >> FORCED_INCLUSION_SLACK_BITS == 0)
+    }
+
+    pub fn public_inputs(&self) -> Vec<Fr> {
+        vec![Fr::from(self.batch_number), Fr::from(self.next_deadline)]
+    }
+}
+
+impl ConstraintSynthesizer<Fr> for ForcedInclusionCheck {
     fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
-        let a = cs.new_witness_variable(|| Ok(Fr::from(10u64)))?;
-        let b = cs.new_witness_variable(|| Ok(Fr::from(20u64)))?;
-        let c = cs.new_input_variable(|| Ok(Fr::from(30u64)))?;
+        let batch_number = cs.new_input_variable(|| Ok(Fr::from(self.batch_number)))?;
+        let next_deadline = cs.new_input_variable(|| Ok(Fr::from(self.next_deadline)))?;
+        let slack = self.next_deadline.wrapping_sub(self.batch_number + 1);
 
-        cs.enforce_constraint(lc!() + a, lc!() + b, lc!() + c)?;
+        let mut sum = lc!();
+        for i in 0..FORCED_INCLUSION_SLACK_BITS {
+            let bit = cs.new_witness_variable(|| Ok(Fr::from((slack >> i) & 1)))?;
+            cs.enforce_constraint(lc!() + bit, lc!() + bit, lc!() + bit)?;
+            sum += (Fr::from(1u64 << i), bit);
+        }
+        let one = ark_relations::r1cs::Variable::One;
+        cs.enforce_constraint(
+            lc!() + next_deadline - batch_number - one,
+            lc!() + one,
+            sum,
+        )
+    }
+}
 
-        Ok(())
+impl ConstraintSynthesizer<Fr> for BendCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let receipts = cs.new_witness_variable(|| Ok(self.receipts_commitment))?;
+        let commitment = cs.new_input_variable(|| Ok(self.receipts_commitment))?;
+        cs.enforce_constraint(lc!() + receipts, lc!() + ark_relations::r1cs::Variable::One, lc!() + commitment)?;
+        self.forced_inclusion.generate_constraints(cs)
     }
-}
\ No newline at end of file
+}
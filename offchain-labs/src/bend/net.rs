@@ -0,0 +1,322 @@
+//! Text format of HVM2 interaction nets.
+//!
+//! A book is a list of definitions `@name = net`, where a net is a root tree
+//! followed by redexes `& tree ~ tree`. Trees are erasers `*`, references
+//! `@name`, numbers, variables and the binary nodes `(a b)` (constructor),
+//! `{a b}` (duplicator), `$(a b)` (operator) and `?(a b)` (switch).
+//!
+//! Numbers are unsigned 24-bit integers written in decimal or `0x` hex, an
+//! operator `[+]`, or an operator with its left operand applied, `[+2]`.
+//! Signed and floating-point numbers are not supported.
+
+use crate::config::ExecutionConfig;
+use crate::error::HVMError;
+use std::collections::BTreeMap;
+
+pub const ENTRY_DEFINITION: &str = "main";
+pub const U24_MAX: u32 = 0xFF_FFFF;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tree {
+    Era,
+    Ref(String),
+    Num(Numb),
+    Var(String),
+    Node(NodeKind, Box<Tree>, Box<Tree>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Con,
+    Dup,
+    Opr,
+    Swi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Numb {
+    U24(u32),
+    Op(Op),
+    /// An operator waiting for its right operand.
+    Partial(Op, u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+/// Operator symbols, longest first so `<<` is not read as `<`.
+const OPERATORS: [(&str, Op); 14] = [
+    ("<<", Op::Shl),
+    (">>", Op::Shr),
+    ("+", Op::Add),
+    ("-", Op::Sub),
+    ("*", Op::Mul),
+    ("/", Op::Div),
+    ("%", Op::Rem),
+    ("=", Op::Eq),
+    ("!", Op::Ne),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("&", Op::And),
+    ("|", Op::Or),
+    ("^", Op::Xor),
+];
+
+impl Op {
+    /// Applies the operator with 24-bit wrapping. Division and remainder by
+    /// zero give zero, and shift amounts are taken modulo 24.
+    pub fn apply(&self, a: u32, b: u32) -> u32 {
+        let result = match self {
+            Op::Add => a.wrapping_add(b),
+            Op::Sub => a.wrapping_sub(b),
+            Op::Mul => a.wrapping_mul(b),
+            Op::Div => a.checked_div(b).unwrap_or(0),
+            Op::Rem => a.checked_rem(b).unwrap_or(0),
+            Op::Eq => (a == b) as u32,
+            Op::Ne => (a != b) as u32,
+            Op::Lt => (a < b) as u32,
+            Op::Gt => (a > b) as u32,
+            Op::And => a & b,
+            Op::Or => a | b,
+            Op::Xor => a ^ b,
+            Op::Shl => a << (b % 24),
+            Op::Shr => a >> (b % 24),
+        };
+        result & U24_MAX
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Net {
+    pub root: Tree,
+    pub redexes: Vec<(Tree, Tree)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Book {
+    pub defs: BTreeMap<String, Net>,
+}
+
+pub fn parse(text: &str) -> Result<Book, HVMError> {
+    let mut parser = Parser { text, pos: 0 };
+    let mut book = Book::default();
+    parser.skip_trivia();
+    while !parser.at_end() {
+        parser.expect('@')?;
+        let name = parser.name()?;
+        parser.expect('=')?;
+        let net = parser.net()?;
+        if book.defs.insert(name.clone(), net).is_some() {
+            return Err(parser.error(&format!("Duplicate definition @{}", name)));
+        }
+    }
+    Ok(book)
+}
+
+/// Checks an untrusted net before it is accepted for deployment: it must
+/// define `@main`, reference only definitions it contains, and use every
+/// variable of a definition exactly twice.
+pub fn validate(book: &Book, config: &ExecutionConfig) -> Result<(), HVMError> {
+    if !book.defs.contains_key(ENTRY_DEFINITION) {
+        return Err(HVMError::Validation(format!("Net does not define @{}", ENTRY_DEFINITION)));
+    }
+    if book.defs.len() as u64 > config.max_functions as u64 {
+        return Err(HVMError::Validation(format!(
+            "Net has {} definitions, more than the limit of {}", book.defs.len(), config.max_functions
+        )));
+    }
+
+    for (name, net) in &book.defs {
+        let mut vars = BTreeMap::new();
+        for tree in net.trees() {
+            let mut stack = vec![tree];
+            while let Some(tree) = stack.pop() {
+                match tree {
+                    Tree::Ref(target) if !book.defs.contains_key(target) => {
+                        return Err(HVMError::Validation(format!("@{} refers to undefined @{}", name, target)));
+                    }
+                    Tree::Var(var) => *vars.entry(var.as_str()).or_insert(0) += 1,
+                    Tree::Node(_, a, b) => stack.extend([&**a, &**b]),
+                    _ => {}
+                }
+            }
+        }
+        if let Some((var, count)) = vars.into_iter().find(|(_, count)| *count != 2) {
+            return Err(HVMError::Validation(format!("Variable {} of @{} is used {} times instead of twice", var, name, count)));
+        }
+    }
+    Ok(())
+}
+
+impl Net {
+    pub fn trees(&self) -> impl Iterator<Item = &Tree> {
+        std::iter::once(&self.root).chain(self.redexes.iter().flat_map(|(a, b)| [a, b]))
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn net(&mut self) -> Result<Net, HVMError> {
+        let root = self.tree()?;
+        let mut redexes = Vec::new();
+        while self.eat('&') {
+            let a = self.tree()?;
+            self.expect('~')?;
+            let b = self.tree()?;
+            redexes.push((a, b));
+        }
+        Ok(Net { root, redexes })
+    }
+
+    fn tree(&mut self) -> Result<Tree, HVMError> {
+        let c = self.peek().ok_or_else(|| self.error("Unexpected end of net"))?;
+        let tree = match c {
+            '*' => {
+                self.advance(1);
+                Tree::Era
+            }
+            '@' => {
+                self.advance(1);
+                Tree::Ref(self.name()?)
+            }
+            '(' => self.node(NodeKind::Con, ')')?,
+            '{' => self.node(NodeKind::Dup, '}')?,
+            '$' => {
+                self.advance(1);
+                self.node(NodeKind::Opr, ')')?
+            }
+            '?' => {
+                self.advance(1);
+                self.node(NodeKind::Swi, ')')?
+            }
+            '[' => Tree::Num(self.operator()?),
+            c if c.is_ascii_digit() => Tree::Num(Numb::U24(self.number()?)),
+            _ => Tree::Var(self.name()?),
+        };
+        self.skip_trivia();
+        Ok(tree)
+    }
+
+    fn node(&mut self, kind: NodeKind, close: char) -> Result<Tree, HVMError> {
+        self.advance(1);
+        self.skip_trivia();
+        let a = self.tree()?;
+        let b = self.tree()?;
+        if !self.eat(close) {
+            return Err(self.error(&format!("Expected '{}'", close)));
+        }
+        Ok(Tree::Node(kind, Box::new(a), Box::new(b)))
+    }
+
+    fn operator(&mut self) -> Result<Numb, HVMError> {
+        self.advance(1);
+        let rest = &self.text[self.pos..];
+        let (symbol, op) = OPERATORS.iter()
+            .find(|(symbol, _)| rest.starts_with(symbol))
+            .ok_or_else(|| self.error("Unknown operator"))?;
+        self.advance(symbol.len());
+        let numb = match self.peek() {
+            Some(c) if c.is_ascii_digit() => Numb::Partial(*op, self.number()?),
+            _ => Numb::Op(*op),
+        };
+        if !self.eat(']') {
+            return Err(self.error("Expected ']'"));
+        }
+        Ok(numb)
+    }
+
+    fn number(&mut self) -> Result<u32, HVMError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            self.advance(1);
+        }
+        let literal = self.text[start..self.pos].replace('_', "");
+        let value = match literal.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => literal.parse(),
+        };
+        value.ok()
+            .filter(|value| *value <= U24_MAX)
+            .ok_or_else(|| self.error(&format!("Unsupported number {}", literal)))
+    }
+
+    fn name(&mut self) -> Result<String, HVMError> {
+        self.skip_trivia();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || "_.-/".contains(c)) {
+            self.advance(1);
+        }
+        if start == self.pos {
+            return Err(self.error("Expected a name"));
+        }
+        let name = self.text[start..self.pos].to_string();
+        self.skip_trivia();
+        Ok(name)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), HVMError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", c)))
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.advance(1);
+            self.skip_trivia();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            let rest = &self.text[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if !trimmed.starts_with("//") {
+                break;
+            }
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.pos += bytes;
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.text.len()
+    }
+
+    fn error(&self, message: &str) -> HVMError {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        HVMError::Validation(format!("Invalid net at line {}: {}", line, message))
+    }
+}
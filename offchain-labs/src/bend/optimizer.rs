@@ -0,0 +1,343 @@
+//! Bytecode optimization for Bend programs.
+//!
+//! The module is rewritten with the following passes:
+//!
+//! - exports outside the calling convention are dropped;
+//! - functions and imports unreachable from the remaining exports, element
+//!   segments and global initializers are removed;
+//! - instructions following an unconditional branch in the same block are
+//!   removed;
+//! - integer arithmetic and comparisons on constant operands are folded;
+//! - custom sections, including the name section, are stripped.
+
+use super::{abi, validation};
+use crate::error::HVMError;
+use wasm_encoder::reencode::{self, Reencode};
+use wasm_encoder::{CodeSection, ExportSection, FunctionSection, ImportSection, Module};
+use wasmparser::{ElementItems, ExternalKind, FunctionBody, Operator, Parser, Payload, TypeRef, Validator};
+
+const KEPT_EXPORTS: [&str; 3] = [abi::MEMORY_EXPORT, abi::ALLOC_EXPORT, abi::ENTRY_EXPORT];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OptimizationStats {
+    pub removed_functions: usize,
+    pub removed_imports: usize,
+    pub removed_exports: usize,
+    pub removed_custom_sections: usize,
+    pub removed_instructions: usize,
+    pub folded_constants: usize,
+}
+
+/// Optimizes `bytecode`, returning the new module and what was changed.
+pub fn optimize(bytecode: &[u8]) -> Result<(Vec<u8>, OptimizationStats), HVMError> {
+    Validator::new_with_features(validation::deterministic_features())
+        .validate_all(bytecode)
+        .map_err(|e| HVMError::Optimization(format!("Invalid module: {}", e)))?;
+
+    let live = live_functions(bytecode)?;
+    let mut function_map = Vec::with_capacity(live.len());
+    let mut next = 0;
+    for &is_live in &live {
+        function_map.push(is_live.then_some(next));
+        next += is_live as u32;
+    }
+
+    let mut optimizer = Optimizer {
+        function_map,
+        imported_functions: 0,
+        next_body: 0,
+        stats: OptimizationStats::default(),
+    };
+    let mut module = Module::new();
+    optimizer.parse_core_module(&mut module, Parser::new(0), bytecode)
+        .map_err(|e| HVMError::Optimization(format!("Failed to rewrite module: {}", e)))?;
+    let optimized = module.finish();
+
+    Validator::new_with_features(validation::deterministic_features())
+        .validate_all(&optimized)
+        .map_err(|e| HVMError::Optimization(format!("Optimized module is invalid: {}", e)))?;
+    Ok((optimized, optimizer.stats))
+}
+
+/// Marks every function reachable from the kept exports, element segments
+/// and global initializers.
+fn live_functions(bytecode: &[u8]) -> Result<Vec<bool>, HVMError> {
+    let err = |e: wasmparser::BinaryReaderError| HVMError::Optimization(format!("Failed to parse module: {}", e));
+
+    let mut function_count = 0;
+    let mut roots = Vec::new();
+    let mut callees: Vec<Vec<u32>> = Vec::new();
+    for payload in Parser::new(0).parse_all(bytecode) {
+        match payload.map_err(err)? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if matches!(import.map_err(err)?.ty, TypeRef::Func(_)) {
+                        function_count += 1;
+                        callees.push(Vec::new());
+                    }
+                }
+            }
+            Payload::FunctionSection(reader) => function_count += reader.count(),
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export.map_err(err)?;
+                    if export.kind == ExternalKind::Func && KEPT_EXPORTS.contains(&export.name) {
+                        roots.push(export.index);
+                    }
+                }
+            }
+            Payload::ElementSection(reader) => {
+                for element in reader {
+                    match element.map_err(err)?.items {
+                        ElementItems::Functions(functions) => {
+                            for function in functions {
+                                roots.push(function.map_err(err)?);
+                            }
+                        }
+                        ElementItems::Expressions(_, expressions) => {
+                            for expression in expressions {
+                                roots.extend(referenced_functions(expression.map_err(err)?.get_operators_reader())?);
+                            }
+                        }
+                    }
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader {
+                    roots.extend(referenced_functions(global.map_err(err)?.init_expr.get_operators_reader())?);
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                callees.push(referenced_functions(body.get_operators_reader().map_err(err)?)?);
+            }
+            _ => {}
+        }
+    }
+
+    let mut live = vec![false; function_count as usize];
+    while let Some(function) = roots.pop() {
+        if let Some(is_live) = live.get_mut(function as usize) {
+            if !*is_live {
+                *is_live = true;
+                roots.extend(&callees[function as usize]);
+            }
+        }
+    }
+    Ok(live)
+}
+
+fn referenced_functions(mut reader: wasmparser::OperatorsReader<'_>) -> Result<Vec<u32>, HVMError> {
+    let mut functions = Vec::new();
+    while !reader.eof() {
+        match reader.read().map_err(|e| HVMError::Optimization(format!("Failed to parse code: {}", e)))? {
+            Operator::Call { function_index } | Operator::RefFunc { function_index } => functions.push(function_index),
+            _ => {}
+        }
+    }
+    Ok(functions)
+}
+
+/// Removes the instructions that follow an unconditional branch up to the end
+/// of the enclosing block. The stack is polymorphic there, so the block's
+/// `end` or `else` still validates.
+fn remove_dead_code<'a>(operators: Vec<Operator<'a>>, stats: &mut OptimizationStats) -> Vec<Operator<'a>> {
+    let mut kept = Vec::with_capacity(operators.len());
+    let mut skipping: Option<u32> = None;
+    for operator in operators {
+        if let Some(depth) = skipping.as_mut() {
+            match operator {
+                Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => *depth += 1,
+                Operator::Else if *depth == 0 => skipping = None,
+                Operator::End if *depth == 0 => skipping = None,
+                Operator::End => *depth -= 1,
+                _ => {}
+            }
+            if skipping.is_some() {
+                stats.removed_instructions += 1;
+                continue;
+            }
+        }
+
+        if matches!(operator, Operator::Unreachable | Operator::Br { .. } | Operator::BrTable { .. } | Operator::Return) {
+            skipping = Some(0);
+        }
+        kept.push(operator);
+    }
+    kept
+}
+
+/// Folds integer operations whose operands are constants pushed right before
+/// them. Division and remainder are left alone, as they can trap.
+fn fold_constants<'a>(operators: Vec<Operator<'a>>, stats: &mut OptimizationStats) -> Vec<Operator<'a>> {
+    let mut folded: Vec<Operator<'a>> = Vec::with_capacity(operators.len());
+    for operator in operators {
+        let result = match folded.as_slice() {
+            [.., Operator::I32Const { value: a }, Operator::I32Const { value: b }] => fold_i32(&operator, *a, *b).map(|value| (2, value)),
+            [.., Operator::I64Const { value: a }, Operator::I64Const { value: b }] => fold_i64(&operator, *a, *b).map(|value| (2, value)),
+            _ => None,
+        }.or_else(|| match (folded.last(), &operator) {
+            (Some(Operator::I32Const { value }), Operator::I32Eqz) => Some((1, Operator::I32Const { value: (*value == 0) as i32 })),
+            (Some(Operator::I64Const { value }), Operator::I64Eqz) => Some((1, Operator::I32Const { value: (*value == 0) as i32 })),
+            _ => None,
+        });
+
+        match result {
+            Some((operands, value)) => {
+                folded.truncate(folded.len() - operands);
+                folded.push(value);
+                stats.folded_constants += 1;
+            }
+            None => folded.push(operator),
+        }
+    }
+    folded
+}
+
+fn fold_i32<'a>(operator: &Operator<'a>, a: i32, b: i32) -> Option<Operator<'a>> {
+    let value = match operator {
+        Operator::I32Add => a.wrapping_add(b),
+        Operator::I32Sub => a.wrapping_sub(b),
+        Operator::I32Mul => a.wrapping_mul(b),
+        Operator::I32And => a & b,
+        Operator::I32Or => a | b,
+        Operator::I32Xor => a ^ b,
+        Operator::I32Shl => a.wrapping_shl(b as u32),
+        Operator::I32ShrS => a.wrapping_shr(b as u32),
+        Operator::I32ShrU => (a as u32).wrapping_shr(b as u32) as i32,
+        Operator::I32Rotl => a.rotate_left(b as u32),
+        Operator::I32Rotr => a.rotate_right(b as u32),
+        Operator::I32Eq => (a == b) as i32,
+        Operator::I32Ne => (a != b) as i32,
+        Operator::I32LtS => (a < b) as i32,
+        Operator::I32LtU => ((a as u32) < (b as u32)) as i32,
+        Operator::I32GtS => (a > b) as i32,
+        Operator::I32GtU => ((a as u32) > (b as u32)) as i32,
+        Operator::I32LeS => (a <= b) as i32,
+        Operator::I32LeU => ((a as u32) <= (b as u32)) as i32,
+        Operator::I32GeS => (a >= b) as i32,
+        Operator::I32GeU => ((a as u32) >= (b as u32)) as i32,
+        _ => return None,
+    };
+    Some(Operator::I32Const { value })
+}
+
+fn fold_i64<'a>(operator: &Operator<'a>, a: i64, b: i64) -> Option<Operator<'a>> {
+    let value = match operator {
+        Operator::I64Add => a.wrapping_add(b),
+        Operator::I64Sub => a.wrapping_sub(b),
+        Operator::I64Mul => a.wrapping_mul(b),
+        Operator::I64And => a & b,
+        Operator::I64Or => a | b,
+        Operator::I64Xor => a ^ b,
+        Operator::I64Shl => a.wrapping_shl(b as u32),
+        Operator::I64ShrS => a.wrapping_shr(b as u32),
+        Operator::I64ShrU => (a as u64).wrapping_shr(b as u32) as i64,
+        Operator::I64Rotl => a.rotate_left(b as u32),
+        Operator::I64Rotr => a.rotate_right(b as u32),
+        comparison => {
+            let result = match comparison {
+                Operator::I64Eq => a == b,
+                Operator::I64Ne => a != b,
+                Operator::I64LtS => a < b,
+                Operator::I64LtU => (a as u64) < (b as u64),
+                Operator::I64GtS => a > b,
+                Operator::I64GtU => (a as u64) > (b as u64),
+                Operator::I64LeS => a <= b,
+                Operator::I64LeU => (a as u64) <= (b as u64),
+                Operator::I64GeS => a >= b,
+                Operator::I64GeU => (a as u64) >= (b as u64),
+                _ => return None,
+            };
+            return Some(Operator::I32Const { value: result as i32 });
+        }
+    };
+    Some(Operator::I64Const { value })
+}
+
+struct Optimizer {
+    /// New index of every function, `None` for removed ones.
+    function_map: Vec<Option<u32>>,
+    imported_functions: u32,
+    next_body: u32,
+    stats: OptimizationStats,
+}
+
+impl Optimizer {
+    fn is_live(&self, function: u32) -> bool {
+        self.function_map[function as usize].is_some()
+    }
+}
+
+impl Reencode for Optimizer {
+    type Error = HVMError;
+
+    fn function_index(&mut self, func: u32) -> u32 {
+        self.function_map[func as usize].expect("live code only references live functions")
+    }
+
+    fn parse_custom_section(&mut self, _module: &mut Module, _section: wasmparser::CustomSectionReader<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        self.stats.removed_custom_sections += 1;
+        Ok(())
+    }
+
+    fn parse_import_section(&mut self, imports: &mut ImportSection, section: wasmparser::ImportSectionReader<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        for import in section {
+            let import = import?;
+            if matches!(import.ty, TypeRef::Func(_)) {
+                let index = self.imported_functions;
+                self.imported_functions += 1;
+                if !self.is_live(index) {
+                    self.stats.removed_imports += 1;
+                    continue;
+                }
+            }
+            self.parse_import(imports, import)?;
+        }
+        Ok(())
+    }
+
+    fn parse_function_section(&mut self, functions: &mut FunctionSection, section: wasmparser::FunctionSectionReader<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        for (offset, type_index) in section.into_iter().enumerate() {
+            let type_index = type_index?;
+            if self.is_live(self.imported_functions + offset as u32) {
+                functions.function(self.type_index(type_index));
+            } else {
+                self.stats.removed_functions += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_export_section(&mut self, exports: &mut ExportSection, section: wasmparser::ExportSectionReader<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        for export in section {
+            let export = export?;
+            if KEPT_EXPORTS.contains(&export.name) {
+                self.parse_export(exports, export);
+            } else {
+                self.stats.removed_exports += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_function_body(&mut self, code: &mut CodeSection, body: FunctionBody<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        let index = self.imported_functions + self.next_body;
+        self.next_body += 1;
+        if !self.is_live(index) {
+            return Ok(());
+        }
+
+        let mut function = self.new_function_with_parsed_locals(&body)?;
+        let mut reader = body.get_operators_reader()?;
+        let mut operators = Vec::new();
+        while !reader.eof() {
+            operators.push(reader.read()?);
+        }
+        let operators = remove_dead_code(operators, &mut self.stats);
+        for operator in fold_constants(operators, &mut self.stats) {
+            function.instruction(&self.instruction(operator)?);
+        }
+        code.function(&function);
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+use crate::bend::BendProgram;
+use crate::crypto::{Signature, SigningKey, VerifyingKey};
+use crate::error::HVMError;
+use semver::{Version, VersionReq};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::BTreeMap;
+
+const DEPLOY_DOMAIN: &[u8] = b"hvm-deploy";
+const TRANSFER_DOMAIN: &[u8] = b"hvm-transfer";
+const FREEZE_DOMAIN: &[u8] = b"hvm-freeze";
+
+/// Maps stable program names to their version history.
+///
+/// Program ids change with every bytecode change, so callers that want to
+/// follow upgrades refer to a program by name, optionally pinned to a semver
+/// requirement. Every change to a name must be signed by its owner, which is
+/// the key that deployed the first version until ownership is transferred.
+/// Each signed message covers the entry's nonce, so a signature cannot be
+/// replayed once the entry has changed. New versions must be greater than the
+/// one before them, and a frozen program accepts no further changes.
+#[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ProgramRegistry {
+    entries: BTreeMap<String, RegistryEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct RegistryEntry {
+    pub owner: VerifyingKey,
+    pub frozen: bool,
+    /// Number of signed changes applied to the entry so far.
+    pub nonce: u64,
+    pub versions: Vec<ProgramVersion>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ProgramVersion {
+    pub version: Version,
+    pub program_id: String,
+}
+
+impl RegistryEntry {
+    /// Hash of everything in the entry, which the state root commits to.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.owner.to_bytes());
+        hasher.update([self.frozen as u8]);
+        hasher.update(self.nonce.to_le_bytes());
+        for version in &self.versions {
+            for field in [version.version.to_string().as_bytes(), version.program_id.as_bytes()] {
+                hasher.update((field.len() as u64).to_le_bytes());
+                hasher.update(field);
+            }
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// A signed change to a registered name, applied when the batch that
+/// includes it is applied.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum RegistryChange {
+    /// See [`ProgramRegistry::transfer_ownership`].
+    Transfer { name: String, new_owner: VerifyingKey, signature: Signature },
+    /// See [`ProgramRegistry::freeze`].
+    Freeze { name: String, signature: Signature },
+}
+
+impl RegistryChange {
+    pub fn name(&self) -> &str {
+        match self {
+            RegistryChange::Transfer { name, .. } | RegistryChange::Freeze { name, .. } => name,
+        }
+    }
+
+    pub fn apply(&self, registry: &mut ProgramRegistry) -> Result<(), HVMError> {
+        match self {
+            RegistryChange::Transfer { name, new_owner, signature } => registry.transfer_ownership(name, *new_owner, signature),
+            RegistryChange::Freeze { name, signature } => registry.freeze(name, signature),
+        }
+    }
+}
+
+/// A program together with its owner's signature over the bytecode hash and
+/// metadata. The program's author is the owner's key.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SignedDeployment {
+    pub program: BendProgram,
+    pub owner: VerifyingKey,
+    pub signature: Signature,
+}
+
+impl SignedDeployment {
+    /// Signs `program` for deployment, setting its author to `key`. `nonce`
+    /// is the registry nonce of the program's name, zero for a new name.
+    pub fn new(program: BendProgram, key: &SigningKey, nonce: u64) -> Self {
+        let program = BendProgram { author: key.verifying_key().to_string(), ..program };
+        let signature = key.sign(&deployment_message(&program, nonce));
+        Self { program, owner: key.verifying_key(), signature }
+    }
+}
+
+impl ProgramRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the deployed program as the newest version of the program
+    /// named in its metadata, registering the name to the signer on first
+    /// use.
+    pub fn register(&mut self, deployment: &SignedDeployment) -> Result<&ProgramVersion, HVMError> {
+        let version = self.check(deployment)?;
+        let program = &deployment.program;
+        let entry = self.entries.entry(program.metadata.name.clone()).or_insert_with(|| RegistryEntry {
+            owner: deployment.owner,
+            frozen: false,
+            nonce: 0,
+            versions: Vec::new(),
+        });
+        entry.nonce += 1;
+        entry.versions.push(ProgramVersion { version, program_id: program.id().to_string() });
+        Ok(entry.versions.last().unwrap())
+    }
+
+    /// Checks that [`register`](Self::register) would accept `deployment`
+    /// without changing the registry, returning the parsed version.
+    pub fn check(&self, deployment: &SignedDeployment) -> Result<Version, HVMError> {
+        let program = &deployment.program;
+        let name = &program.metadata.name;
+        let version = Version::parse(&program.metadata.version)
+            .map_err(|e| HVMError::Registry(format!("Invalid version {} for program {}: {}", program.metadata.version, name, e)))?;
+
+        if program.author != deployment.owner.to_string() {
+            return Err(HVMError::Registry(format!("Author of program {} is not the key that signed it", name)));
+        }
+        deployment.owner.verify(&deployment_message(program, self.nonce(name)), &deployment.signature)?;
+        if let Some(entry) = self.entries.get(name) {
+            Self::check_owner(name, entry, &deployment.owner)?;
+            if let Some(latest) = entry.versions.last() {
+                if version <= latest.version {
+                    return Err(HVMError::Registry(format!("Version {} of program {} is not newer than {}", version, name, latest.version)));
+                }
+            }
+        }
+        Ok(version)
+    }
+
+    /// Hands `name` over to `new_owner`. Signed by the current owner over
+    /// [`transfer_message`].
+    pub fn transfer_ownership(&mut self, name: &str, new_owner: VerifyingKey, signature: &Signature) -> Result<(), HVMError> {
+        let entry = self.owned_entry(name, &transfer_message(name, &new_owner, self.nonce(name)), signature)?;
+        entry.owner = new_owner;
+        entry.nonce += 1;
+        Ok(())
+    }
+
+    /// Permanently rejects further versions and ownership changes of `name`.
+    /// Signed by the current owner over [`freeze_message`].
+    pub fn freeze(&mut self, name: &str, signature: &Signature) -> Result<(), HVMError> {
+        let entry = self.owned_entry(name, &freeze_message(name, self.nonce(name)), signature)?;
+        entry.frozen = true;
+        entry.nonce += 1;
+        Ok(())
+    }
+
+    /// Newest version of `name` matching `requirement`, or the newest version
+    /// overall when no requirement is given.
+    pub fn resolve(&self, name: &str, requirement: Option<&VersionReq>) -> Result<&ProgramVersion, HVMError> {
+        self.versions(name)?
+            .iter()
+            .rev()
+            .find(|entry| requirement.is_none_or(|requirement| requirement.matches(&entry.version)))
+            .ok_or_else(|| HVMError::Registry(format!("No version of program {} matches the requirement", name)))
+    }
+
+    /// Resolves a reference of the form `name` or `name@requirement`.
+    pub fn resolve_reference(&self, reference: &str) -> Result<&ProgramVersion, HVMError> {
+        match reference.split_once('@') {
+            Some((name, requirement)) => {
+                let requirement = VersionReq::parse(requirement)
+                    .map_err(|e| HVMError::Registry(format!("Invalid version requirement {}: {}", requirement, e)))?;
+                self.resolve(name, Some(&requirement))
+            }
+            None => self.resolve(reference, None),
+        }
+    }
+
+    /// Version history of `name`, oldest first.
+    pub fn versions(&self, name: &str) -> Result<&[ProgramVersion], HVMError> {
+        self.entries.get(name)
+            .map(|entry| entry.versions.as_slice())
+            .ok_or_else(|| HVMError::Registry(format!("Program not registered: {}", name)))
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&RegistryEntry> {
+        self.entries.get(name)
+    }
+
+    /// Every registered name with its entry, in name order.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &RegistryEntry)> {
+        self.entries.iter().map(|(name, entry)| (name.as_str(), entry))
+    }
+
+    /// Puts back an entry read from a snapshot.
+    pub(crate) fn restore_entry(&mut self, name: String, entry: RegistryEntry) {
+        self.entries.insert(name, entry);
+    }
+
+    /// Nonce the next signed change to `name` must cover.
+    pub fn nonce(&self, name: &str) -> u64 {
+        self.entries.get(name).map_or(0, |entry| entry.nonce)
+    }
+
+    fn owned_entry(&mut self, name: &str, message: &[u8], signature: &Signature) -> Result<&mut RegistryEntry, HVMError> {
+        let entry = self.entries.get_mut(name)
+            .ok_or_else(|| HVMError::Registry(format!("Program not registered: {}", name)))?;
+        let owner = entry.owner;
+        Self::check_owner(name, entry, &owner)?;
+        owner.verify(message, signature)
+            .map_err(|_| HVMError::Registry(format!("Change to program {} is not signed by its owner", name)))?;
+        Ok(entry)
+    }
+
+    fn check_owner(name: &str, entry: &RegistryEntry, signer: &VerifyingKey) -> Result<(), HVMError> {
+        if entry.frozen {
+            return Err(HVMError::Registry(format!("Program {} is frozen", name)));
+        }
+        if entry.owner != *signer {
+            return Err(HVMError::Registry(format!("Program {} can only be changed by {}", name, entry.owner)));
+        }
+        Ok(())
+    }
+}
+
+/// Message signed to deploy `program`: its bytecode hash and metadata,
+/// including the hash of the source it was compiled from.
+pub fn deployment_message(program: &BendProgram, nonce: u64) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for field in [
+        program.id().as_bytes(),
+        program.metadata.name.as_bytes(),
+        program.metadata.version.as_bytes(),
+        program.metadata.description.as_bytes(),
+        program.author.as_bytes(),
+        program.backend.name().as_bytes(),
+        program.metadata.source.as_ref().map_or(&[][..], |source| source.source_hash.as_bytes()),
+    ] {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field);
+    }
+    signed_message(DEPLOY_DOMAIN, &[&hasher.finalize()], nonce)
+}
+
+pub fn transfer_message(name: &str, new_owner: &VerifyingKey, nonce: u64) -> Vec<u8> {
+    signed_message(TRANSFER_DOMAIN, &[name.as_bytes(), &new_owner.to_bytes()], nonce)
+}
+
+pub fn freeze_message(name: &str, nonce: u64) -> Vec<u8> {
+    signed_message(FREEZE_DOMAIN, &[name.as_bytes()], nonce)
+}
+
+fn signed_message(domain: &[u8], fields: &[&[u8]], nonce: u64) -> Vec<u8> {
+    let mut message = domain.to_vec();
+    for field in fields {
+        message.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        message.extend_from_slice(field);
+    }
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message
+}
@@ -0,0 +1,507 @@
+//! Execution tracing for witness generation and debugging.
+//!
+//! A traced execution runs an instrumented copy of the program in which every
+//! instruction, every load and store, and every direct call to a host function
+//! first calls into one of the hooks imported from [`TRACE_MODULE`]. The hooks
+//! append to an [`ExecutionTrace`] and charge gas from the same per-opcode
+//! table used by the metering middleware, so a traced run uses exactly the
+//! same amount of gas as an untraced one.
+//!
+//! Bulk memory instructions show up as instruction events only.
+
+use super::{host, metering, validation};
+use crate::error::HVMError;
+use std::fmt;
+use wasm_encoder::reencode::{self, Reencode};
+use wasm_encoder::{CodeSection, EntityType, Function, ImportSection, Instruction, Module, TypeSection, ValType};
+use wasmer::{AsStoreMut, Function as HostFunction, FunctionEnv, FunctionEnvMut, Imports, RuntimeError};
+use wasmparser::{FunctionBody, Operator, Parser, Payload, TypeRef, Validator};
+
+pub const TRACE_MODULE: &str = "hvm_trace";
+
+const OP_HOOK: &str = "op";
+const LOAD_HOOK: &str = "load";
+const STORE_HOOK: &str = "store";
+const HOST_CALL_HOOK: &str = "host_call";
+const HOOK_COUNT: u32 = 4;
+
+const TAG_INSTRUCTION: u8 = 0x01;
+const TAG_MEMORY_READ: u8 = 0x02;
+const TAG_MEMORY_WRITE: u8 = 0x03;
+const TAG_HOST_CALL: u8 = 0x04;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// An instruction about to execute, identified by its byte offset in the
+    /// original bytecode.
+    Instruction { offset: u32 },
+    MemoryRead { address: u64, value: u64, width: u8 },
+    MemoryWrite { address: u64, value: u64, width: u8 },
+    /// A direct call to the imported host function with this index.
+    HostCall { function: u32 },
+}
+
+/// Trace of a single execution in a compact binary format.
+///
+/// Each event is a one-byte tag followed by its fields as unsigned LEB128,
+/// except memory access widths which take a single byte.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionTrace {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl ExecutionTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, HVMError> {
+        let mut len = 0;
+        let mut pos = 0;
+        while pos < bytes.len() {
+            decode_event(&bytes, &mut pos)
+                .ok_or_else(|| HVMError::Execution(format!("Malformed trace event at byte {}", pos)))?;
+            len += 1;
+        }
+        Ok(Self { bytes, len })
+    }
+
+    pub fn push(&mut self, event: &TraceEvent) {
+        match *event {
+            TraceEvent::Instruction { offset } => {
+                self.bytes.push(TAG_INSTRUCTION);
+                write_leb128(&mut self.bytes, offset as u64);
+            }
+            TraceEvent::MemoryRead { address, value, width } => {
+                self.bytes.push(TAG_MEMORY_READ);
+                write_leb128(&mut self.bytes, address);
+                write_leb128(&mut self.bytes, value);
+                self.bytes.push(width);
+            }
+            TraceEvent::MemoryWrite { address, value, width } => {
+                self.bytes.push(TAG_MEMORY_WRITE);
+                write_leb128(&mut self.bytes, address);
+                write_leb128(&mut self.bytes, value);
+                self.bytes.push(width);
+            }
+            TraceEvent::HostCall { function } => {
+                self.bytes.push(TAG_HOST_CALL);
+                write_leb128(&mut self.bytes, function as u64);
+            }
+        }
+        self.len += 1;
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = TraceEvent> + '_ {
+        let mut pos = 0;
+        std::iter::from_fn(move || decode_event(&self.bytes, &mut pos))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl fmt::Display for ExecutionTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for event in self.events() {
+            match event {
+                TraceEvent::Instruction { offset } => writeln!(f, "op    @{:#x}", offset)?,
+                TraceEvent::MemoryRead { address, value, width } => {
+                    writeln!(f, "load  [{:#x}; {}] -> {:#x}", address, width, value)?
+                }
+                TraceEvent::MemoryWrite { address, value, width } => {
+                    writeln!(f, "store [{:#x}; {}] <- {:#x}", address, width, value)?
+                }
+                TraceEvent::HostCall { function } => writeln!(f, "host  #{}", function)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_leb128(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut result = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+    None
+}
+
+fn decode_event(bytes: &[u8], pos: &mut usize) -> Option<TraceEvent> {
+    let tag = *bytes.get(*pos)?;
+    *pos += 1;
+    let event = match tag {
+        TAG_INSTRUCTION => TraceEvent::Instruction { offset: u32::try_from(read_leb128(bytes, pos)?).ok()? },
+        TAG_MEMORY_READ | TAG_MEMORY_WRITE => {
+            let address = read_leb128(bytes, pos)?;
+            let value = read_leb128(bytes, pos)?;
+            let width = *bytes.get(*pos)?;
+            *pos += 1;
+            if tag == TAG_MEMORY_READ {
+                TraceEvent::MemoryRead { address, value, width }
+            } else {
+                TraceEvent::MemoryWrite { address, value, width }
+            }
+        }
+        TAG_HOST_CALL => TraceEvent::HostCall { function: u32::try_from(read_leb128(bytes, pos)?).ok()? },
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Host-side state of a traced execution.
+pub struct TraceEnv {
+    pub trace: ExecutionTrace,
+    pub gas_remaining: u64,
+    pub gas_exhausted: bool,
+}
+
+impl TraceEnv {
+    pub fn new(gas_limit: u64) -> Self {
+        Self {
+            trace: ExecutionTrace::new(),
+            gas_remaining: gas_limit,
+            gas_exhausted: false,
+        }
+    }
+}
+
+/// Adds the hooks an instrumented module imports.
+pub fn define_hooks(imports: &mut Imports, store: &mut impl AsStoreMut, env: &FunctionEnv<TraceEnv>) {
+    imports.define(TRACE_MODULE, OP_HOOK, HostFunction::new_typed_with_env(store, env, on_instruction));
+    imports.define(TRACE_MODULE, LOAD_HOOK, HostFunction::new_typed_with_env(store, env, on_load));
+    imports.define(TRACE_MODULE, STORE_HOOK, HostFunction::new_typed_with_env(store, env, on_store));
+    imports.define(TRACE_MODULE, HOST_CALL_HOOK, HostFunction::new_typed_with_env(store, env, on_host_call));
+}
+
+fn on_instruction(mut env: FunctionEnvMut<TraceEnv>, offset: i32, cost: i32) -> Result<(), RuntimeError> {
+    let env = env.data_mut();
+    let cost = cost as u32 as u64;
+    if cost > env.gas_remaining {
+        env.gas_exhausted = true;
+        return Err(RuntimeError::new("out of gas"));
+    }
+    env.gas_remaining -= cost;
+    env.trace.push(&TraceEvent::Instruction { offset: offset as u32 });
+    Ok(())
+}
+
+fn on_load(mut env: FunctionEnvMut<TraceEnv>, address: i64, value: i64, width: i32) {
+    env.data_mut().trace.push(&TraceEvent::MemoryRead { address: address as u64, value: value as u64, width: width as u8 });
+}
+
+fn on_store(mut env: FunctionEnvMut<TraceEnv>, address: i64, value: i64, width: i32) {
+    env.data_mut().trace.push(&TraceEvent::MemoryWrite { address: address as u64, value: value as u64, width: width as u8 });
+}
+
+fn on_host_call(mut env: FunctionEnvMut<TraceEnv>, function: i32) {
+    env.data_mut().trace.push(&TraceEvent::HostCall { function: function as u32 });
+}
+
+/// Rewrites `bytecode` so it reports its execution to the trace hooks.
+///
+/// The hooks are appended to the module's imports, so every defined function
+/// index shifts by [`HOOK_COUNT`] and is remapped throughout the module.
+pub fn instrument(bytecode: &[u8]) -> Result<Vec<u8>, HVMError> {
+    let types = Validator::new_with_features(validation::deterministic_features())
+        .validate_all(bytecode)
+        .map_err(|e| HVMError::Execution(format!("Failed to instrument module: {}", e)))?;
+
+    let mut host_costs = Vec::new();
+    for payload in Parser::new(0).parse_all(bytecode) {
+        let payload = payload.map_err(|e| HVMError::Execution(format!("Failed to instrument module: {}", e)))?;
+        if let Payload::ImportSection(reader) = payload {
+            host_costs.extend(reader.into_iter()
+                .flatten()
+                .filter(|import| matches!(import.ty, TypeRef::Func(_)))
+                .map(|import| match import.module {
+                    host::HOST_MODULE => metering::host_function_cost(import.name),
+                    _ => 0,
+                }));
+        }
+    }
+    let costs = operator_costs(bytecode)?;
+
+    let function_params = (0..types.core_function_count())
+        .map(|index| types[types.core_function_at(index)].unwrap_func().params().len() as u32)
+        .collect();
+
+    let imported_functions = host_costs.len() as u32;
+    let mut instrumenter = Instrumenter {
+        type_count: types.type_count() as u32,
+        imported_functions,
+        host_costs,
+        function_params,
+        costs: costs.into_iter(),
+        next_function: imported_functions,
+        types_emitted: false,
+        imports_emitted: false,
+    };
+    let mut module = Module::new();
+    instrumenter.parse_core_module(&mut module, Parser::new(0), bytecode)
+        .map_err(|e| HVMError::Execution(format!("Failed to instrument module: {}", e)))?;
+    Ok(module.finish())
+}
+
+/// Gas cost of every operator in the code section, in order.
+///
+/// Costs come from [`metering::operator_cost`], which is defined over the
+/// operator type of the `wasmparser` version bundled with wasmer.
+fn operator_costs(bytecode: &[u8]) -> Result<Vec<u64>, HVMError> {
+    use wasmer::wasmparser::{Parser as CostParser, Payload as CostPayload};
+
+    let mut costs = Vec::new();
+    for payload in CostParser::new(0).parse_all(bytecode) {
+        let payload = payload.map_err(|e| HVMError::Execution(format!("Failed to instrument module: {}", e)))?;
+        if let CostPayload::CodeSectionEntry(body) = payload {
+            let mut reader = body.get_operators_reader()
+                .map_err(|e| HVMError::Execution(format!("Failed to instrument module: {}", e)))?;
+            while !reader.eof() {
+                let operator = reader.read()
+                    .map_err(|e| HVMError::Execution(format!("Failed to instrument module: {}", e)))?;
+                costs.push(metering::operator_cost(&operator));
+            }
+        }
+    }
+    Ok(costs)
+}
+
+struct Instrumenter {
+    type_count: u32,
+    imported_functions: u32,
+    host_costs: Vec<u64>,
+    function_params: Vec<u32>,
+    costs: std::vec::IntoIter<u64>,
+    next_function: u32,
+    types_emitted: bool,
+    imports_emitted: bool,
+}
+
+struct Scratch {
+    address: u32,
+    value32: u32,
+    value64: u32,
+}
+
+impl Instrumenter {
+    fn op_hook(&self) -> u32 {
+        self.imported_functions
+    }
+
+    fn load_hook(&self) -> u32 {
+        self.imported_functions + 1
+    }
+
+    fn store_hook(&self) -> u32 {
+        self.imported_functions + 2
+    }
+
+    fn host_call_hook(&self) -> u32 {
+        self.imported_functions + 3
+    }
+
+    fn add_hook_types(&mut self, types: &mut TypeSection) {
+        types.function([ValType::I32, ValType::I32], []);
+        types.function([ValType::I64, ValType::I64, ValType::I32], []);
+        types.function([ValType::I32], []);
+        self.types_emitted = true;
+    }
+
+    fn add_hook_imports(&mut self, imports: &mut ImportSection) {
+        imports.import(TRACE_MODULE, OP_HOOK, EntityType::Function(self.type_count));
+        imports.import(TRACE_MODULE, LOAD_HOOK, EntityType::Function(self.type_count + 1));
+        imports.import(TRACE_MODULE, STORE_HOOK, EntityType::Function(self.type_count + 1));
+        imports.import(TRACE_MODULE, HOST_CALL_HOOK, EntityType::Function(self.type_count + 2));
+        self.imports_emitted = true;
+    }
+
+    /// Calls `hook` with the effective address, the value held in the scratch
+    /// local and the width of the access.
+    fn report_access(&self, function: &mut Function, hook: u32, scratch: &Scratch, access: &MemoryAccess) {
+        let value = scratch.value(access);
+        function.instruction(&Instruction::LocalGet(scratch.address));
+        function.instruction(&Instruction::I64ExtendI32U);
+        if access.offset != 0 {
+            function.instruction(&Instruction::I64Const(access.offset as i64));
+            function.instruction(&Instruction::I64Add);
+        }
+        function.instruction(&Instruction::LocalGet(value));
+        if !access.is_64 {
+            function.instruction(&Instruction::I64ExtendI32U);
+        }
+        function.instruction(&Instruction::I32Const(access.width as i32));
+        function.instruction(&Instruction::Call(hook));
+    }
+}
+
+impl Scratch {
+    fn value(&self, access: &MemoryAccess) -> u32 {
+        if access.is_64 {
+            self.value64
+        } else {
+            self.value32
+        }
+    }
+}
+
+struct MemoryAccess {
+    offset: u64,
+    width: u8,
+    is_64: bool,
+    is_store: bool,
+}
+
+fn memory_access(operator: &Operator) -> Option<MemoryAccess> {
+    let (memarg, width, is_64, is_store) = match operator {
+        Operator::I32Load { memarg } => (memarg, 4, false, false),
+        Operator::I64Load { memarg } => (memarg, 8, true, false),
+        Operator::I32Load8S { memarg } | Operator::I32Load8U { memarg } => (memarg, 1, false, false),
+        Operator::I32Load16S { memarg } | Operator::I32Load16U { memarg } => (memarg, 2, false, false),
+        Operator::I64Load8S { memarg } | Operator::I64Load8U { memarg } => (memarg, 1, true, false),
+        Operator::I64Load16S { memarg } | Operator::I64Load16U { memarg } => (memarg, 2, true, false),
+        Operator::I64Load32S { memarg } | Operator::I64Load32U { memarg } => (memarg, 4, true, false),
+        Operator::I32Store { memarg } => (memarg, 4, false, true),
+        Operator::I64Store { memarg } => (memarg, 8, true, true),
+        Operator::I32Store8 { memarg } => (memarg, 1, false, true),
+        Operator::I32Store16 { memarg } => (memarg, 2, false, true),
+        Operator::I64Store8 { memarg } => (memarg, 1, true, true),
+        Operator::I64Store16 { memarg } => (memarg, 2, true, true),
+        Operator::I64Store32 { memarg } => (memarg, 4, true, true),
+        _ => return None,
+    };
+    Some(MemoryAccess { offset: memarg.offset, width, is_64, is_store })
+}
+
+impl Reencode for Instrumenter {
+    type Error = HVMError;
+
+    fn function_index(&mut self, func: u32) -> u32 {
+        if func < self.imported_functions {
+            func
+        } else {
+            func + HOOK_COUNT
+        }
+    }
+
+    fn parse_type_section(&mut self, types: &mut TypeSection, section: wasmparser::TypeSectionReader<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        reencode::utils::parse_type_section(self, types, section)?;
+        self.add_hook_types(types);
+        Ok(())
+    }
+
+    fn parse_import_section(&mut self, imports: &mut ImportSection, section: wasmparser::ImportSectionReader<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        reencode::utils::parse_import_section(self, imports, section)?;
+        self.add_hook_imports(imports);
+        Ok(())
+    }
+
+    fn intersperse_section_hook(
+        &mut self,
+        module: &mut Module,
+        _after: Option<wasm_encoder::SectionId>,
+        before: Option<wasm_encoder::SectionId>,
+    ) -> Result<(), reencode::Error<Self::Error>> {
+        let past = |id: wasm_encoder::SectionId| before.is_none_or(|before| before as u8 > id as u8);
+        if !self.types_emitted && past(wasm_encoder::SectionId::Type) {
+            let mut types = TypeSection::new();
+            self.add_hook_types(&mut types);
+            module.section(&types);
+        }
+        if !self.imports_emitted && past(wasm_encoder::SectionId::Import) {
+            let mut imports = ImportSection::new();
+            self.add_hook_imports(&mut imports);
+            module.section(&imports);
+        }
+        Ok(())
+    }
+
+    fn parse_function_body(&mut self, code: &mut CodeSection, body: FunctionBody<'_>) -> Result<(), reencode::Error<Self::Error>> {
+        let params = self.function_params[self.next_function as usize];
+        self.next_function += 1;
+
+        let mut locals = Vec::new();
+        let mut local_count = params;
+        for pair in body.get_locals_reader()? {
+            let (count, ty) = pair?;
+            local_count += count;
+            locals.push((count, self.val_type(ty)?));
+        }
+        let scratch = Scratch { address: local_count, value32: local_count + 1, value64: local_count + 2 };
+        locals.extend([(2, ValType::I32), (1, ValType::I64)]);
+        let mut function = Function::new(locals);
+
+        let mut reader = body.get_operators_reader()?;
+        while !reader.eof() {
+            let offset = reader.original_position();
+            let operator = reader.read()?;
+            let mut cost = self.costs.next()
+                .ok_or_else(|| reencode::Error::UserError(HVMError::Execution("Operator cost table is out of sync".to_string())))?;
+
+            if let Operator::Call { function_index } = operator {
+                cost += self.host_costs.get(function_index as usize).copied().unwrap_or(0);
+            }
+
+            function.instruction(&Instruction::I32Const(offset as i32));
+            function.instruction(&Instruction::I32Const(cost as i32));
+            function.instruction(&Instruction::Call(self.op_hook()));
+
+            match memory_access(&operator) {
+                Some(access) if access.is_store => {
+                    function.instruction(&Instruction::LocalSet(scratch.value(&access)));
+                    function.instruction(&Instruction::LocalTee(scratch.address));
+                    function.instruction(&Instruction::LocalGet(scratch.value(&access)));
+                    function.instruction(&self.instruction(operator)?);
+                    self.report_access(&mut function, self.store_hook(), &scratch, &access);
+                }
+                Some(access) => {
+                    function.instruction(&Instruction::LocalTee(scratch.address));
+                    function.instruction(&self.instruction(operator)?);
+                    function.instruction(&Instruction::LocalTee(scratch.value(&access)));
+                    self.report_access(&mut function, self.load_hook(), &scratch, &access);
+                }
+                None => {
+                    if let Operator::Call { function_index } = operator {
+                        if function_index < self.imported_functions {
+                            function.instruction(&Instruction::I32Const(function_index as i32));
+                            function.instruction(&Instruction::Call(self.host_call_hook()));
+                        }
+                    }
+                    function.instruction(&self.instruction(operator)?);
+                }
+            }
+        }
+        code.function(&function);
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use super::{abi, host};
+use crate::config::ExecutionConfig;
+use crate::error::HVMError;
+use wasmparser::{ExternalKind, Parser, Payload, TypeRef, Validator, WasmFeatures};
+
+/// Proposals that are deterministic and safe to run on the sequencer.
+///
+/// Floats, SIMD, threads and the other proposals are left disabled, so the
+/// validator rejects any module that uses them.
+pub(crate) fn deterministic_features() -> WasmFeatures {
+    WasmFeatures::MUTABLE_GLOBAL
+        | WasmFeatures::SIGN_EXTENSION
+        | WasmFeatures::MULTI_VALUE
+        | WasmFeatures::BULK_MEMORY
+}
+
+/// Checks untrusted bytecode before it is accepted for deployment.
+pub fn validate_bytecode(bytecode: &[u8], config: &ExecutionConfig) -> Result<(), HVMError> {
+    let types = Validator::new_with_features(deterministic_features())
+        .validate_all(bytecode)
+        .map_err(|e| HVMError::Validation(format!("Invalid module: {}", e)))?;
+
+    let mut function_count: u64 = 0;
+    let mut required_exports = vec![abi::MEMORY_EXPORT, abi::ALLOC_EXPORT, abi::ENTRY_EXPORT];
+    for payload in Parser::new(0).parse_all(bytecode) {
+        let payload = payload.map_err(|e| HVMError::Validation(format!("Failed to parse module: {}", e)))?;
+        match payload {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import.map_err(|e| HVMError::Validation(format!("Failed to read import: {}", e)))?;
+                    let expected = host::signature(import.name).filter(|_| import.module == host::HOST_MODULE);
+                    match (import.ty, expected) {
+                        (TypeRef::Func(type_index), Some((params, results))) => {
+                            let ty = types[types.core_type_at(type_index).unwrap_sub()].unwrap_func();
+                            if ty.params() != params || ty.results() != results {
+                                return Err(HVMError::Validation(format!(
+                                    "Host function {}::{} must have type {:?} -> {:?}", import.module, import.name, params, results
+                                )));
+                            }
+                            function_count += 1;
+                        }
+                        _ => return Err(HVMError::Validation(format!("Disallowed import: {}::{}", import.module, import.name))),
+                    }
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                function_count += reader.count() as u64;
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    let memory = memory.map_err(|e| HVMError::Validation(format!("Failed to read memory: {}", e)))?;
+                    let max_pages = config.max_memory_pages as u64;
+                    if memory.initial > max_pages || memory.maximum.is_some_and(|max| max > max_pages) {
+                        return Err(HVMError::Validation(format!(
+                            "Memory exceeds the limit of {} pages", config.max_memory_pages
+                        )));
+                    }
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export.map_err(|e| HVMError::Validation(format!("Failed to read export: {}", e)))?;
+                    let func_type = match export.kind {
+                        ExternalKind::Func => Some(types[types.core_function_at(export.index)].unwrap_func()),
+                        _ => None,
+                    };
+                    abi::check_export(export.name, export.kind, func_type)?;
+                    required_exports.retain(|name| *name != export.name);
+                }
+            }
+            Payload::StartSection { .. } => {
+                return Err(HVMError::Validation("Start functions are not allowed".to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(missing) = required_exports.first() {
+        return Err(HVMError::Abi(format!("Module does not export `{}`", missing)));
+    }
+
+    if function_count > config.max_functions as u64 {
+        return Err(HVMError::Validation(format!(
+            "Module defines {} functions, limit is {}", function_count, config.max_functions
+        )));
+    }
+
+    Ok(())
+}
@@ -11,6 +11,16 @@ pub struct Config {
     pub prover_config: ProverConfig,
     pub verifier_config: VerifierConfig,
     pub sequencer_config: SequencerConfig,
+    #[serde(default)]
+    pub execution_config: ExecutionConfig,
+    #[serde(default)]
+    pub compiler_config: CompilerConfig,
+    #[serde(default)]
+    pub fee_config: FeeConfig,
+    #[serde(default)]
+    pub history_config: HistoryConfig,
+    #[serde(default)]
+    pub rpc_config: RpcConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +41,135 @@ pub struct SequencerConfig {
     pub batch_interval_seconds: u64,
     pub max_batch_size: usize,
     pub max_programs_per_batch: usize,
+    /// Number of batches within which a transaction from the L1 forced
+    /// inclusion queue must be included, counting the batch after it was
+    /// queued as the first.
+    #[serde(default = "default_forced_inclusion_batches")]
+    pub forced_inclusion_batches: u64,
+    /// Number of failed batches a transaction can be in before it is
+    /// rejected instead of queued again.
+    #[serde(default = "default_max_batch_attempts")]
+    pub max_batch_attempts: u32,
+}
+
+fn default_forced_inclusion_batches() -> u64 {
+    10
+}
+
+fn default_max_batch_attempts() -> u32 {
+    3
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExecutionConfig {
+    pub gas_limit: u64,
+    pub max_memory_pages: u32,
+    pub max_stack_depth: u32,
+    pub max_functions: u32,
+    pub max_call_depth: u32,
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self {
+            gas_limit: 10_000_000,
+            max_memory_pages: 256,
+            max_stack_depth: 1024,
+            max_functions: 10_000,
+            max_call_depth: 8,
+            cache_dir: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeeConfig {
+    pub initial_base_fee: u64,
+    pub min_base_fee: u64,
+    /// Gas a batch is expected to use. Fuller batches raise the base fee and
+    /// emptier ones lower it.
+    pub target_batch_gas: u64,
+    pub base_fee_change_denominator: u64,
+    /// State account credited with the fees of every batch.
+    pub fee_account: String,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        Self {
+            initial_base_fee: 1,
+            min_base_fee: 1,
+            target_batch_gas: 50_000_000,
+            base_fee_change_denominator: 8,
+            fee_account: "sequencer".to_string(),
+        }
+    }
+}
+
+/// Which states after past batches are kept for historical queries.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    /// Keep the state after every batch, ignoring the other settings.
+    pub archive: bool,
+    /// Number of most recent states kept. The latest is always kept.
+    pub keep_recent: u64,
+    /// States after batches whose number is a multiple of this are kept as
+    /// checkpoints. Zero disables checkpoints.
+    pub checkpoint_interval: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            archive: false,
+            keep_recent: 128,
+            checkpoint_interval: 1000,
+        }
+    }
+}
+
+/// Where the node serves JSON-RPC. HTTP and WebSocket clients share the
+/// same address.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RpcConfig {
+    pub listen_address: String,
+    pub max_connections: u32,
+    /// Batch headers buffered for each WebSocket subscriber. A subscriber
+    /// that falls further behind skips the oldest ones.
+    pub subscription_buffer: usize,
+    /// Hex public key of the bridge relayer. The methods that credit
+    /// deposits and queue forced transactions are only served when it is
+    /// set, and only to calls it signed.
+    #[serde(default)]
+    pub relayer_key: Option<String>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: "127.0.0.1:8545".to_string(),
+            max_connections: 100,
+            subscription_buffer: 64,
+            relayer_key: None,
+        }
+    }
+}
+
+/// Locations of the toolchain binaries used to compile Bend and HVM source.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompilerConfig {
+    pub bend_path: PathBuf,
+    pub hvm_path: PathBuf,
+}
+
+impl Default for CompilerConfig {
+    fn default() -> Self {
+        Self {
+            bend_path: PathBuf::from("bend"),
+            hvm_path: PathBuf::from("hvm"),
+        }
+    }
 }
 
 impl Config {
@@ -67,7 +206,17 @@ impl Default for Config {
                 batch_interval_seconds: 60,
                 max_batch_size: 100,
                 max_programs_per_batch: 10,
+                forced_inclusion_batches: default_forced_inclusion_batches(),
+                max_batch_attempts: default_max_batch_attempts(),
+            },
+            execution_config: ExecutionConfig {
+                cache_dir: Some(PathBuf::from("module_cache")),
+                ..ExecutionConfig::default()
             },
+            compiler_config: CompilerConfig::default(),
+            fee_config: FeeConfig::default(),
+            history_config: HistoryConfig::default(),
+            rpc_config: RpcConfig::default(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+//! Schnorr signatures over Ristretto, provided by `schnorrkel`.
+//!
+//! Secret keys are 32 byte seeds expanded the way Ed25519 expands them, and
+//! every message is signed under the rollup's signing context so signatures
+//! cannot be replayed in another protocol using the same keys.
+
+use crate::error::HVMError;
+use ark_std::rand::{CryptoRng, RngCore};
+use schnorrkel::{signing_context, ExpansionMode, Keypair, MiniSecretKey, PublicKey};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+const SIGNING_CONTEXT: &[u8] = b"hvm-rollup";
+
+pub const SECRET_KEY_LENGTH: usize = 32;
+pub const PUBLIC_KEY_LENGTH: usize = 32;
+pub const SIGNATURE_LENGTH: usize = 64;
+
+#[derive(Clone)]
+pub struct SigningKey {
+    seed: MiniSecretKey,
+    keypair: Keypair,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey {
+    key: PublicKey,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    signature: schnorrkel::Signature,
+}
+
+impl SigningKey {
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut seed = [0u8; SECRET_KEY_LENGTH];
+        rng.fill_bytes(&mut seed);
+        Self::from_bytes(&seed).expect("any 32 bytes are a valid seed")
+    }
+
+    pub fn from_bytes(bytes: &[u8; SECRET_KEY_LENGTH]) -> Result<Self, HVMError> {
+        let seed = MiniSecretKey::from_bytes(bytes)
+            .map_err(|e| HVMError::Signature(format!("Invalid secret key: {}", e)))?;
+        let keypair = seed.expand_to_keypair(ExpansionMode::Ed25519);
+        Ok(Self { seed, keypair })
+    }
+
+    pub fn to_bytes(&self) -> [u8; SECRET_KEY_LENGTH] {
+        self.seed.to_bytes()
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey { key: self.keypair.public }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        Signature { signature: self.keypair.sign(signing_context(SIGNING_CONTEXT).bytes(message)) }
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey").field("verifying_key", &self.verifying_key()).finish_non_exhaustive()
+    }
+}
+
+impl VerifyingKey {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HVMError> {
+        if bytes.iter().all(|&byte| byte == 0) {
+            return Err(HVMError::Signature("Invalid public key: identity".to_string()));
+        }
+        let key = PublicKey::from_bytes(bytes)
+            .map_err(|e| HVMError::Signature(format!("Invalid public key: {}", e)))?;
+        Ok(Self { key })
+    }
+
+    pub fn to_bytes(&self) -> [u8; PUBLIC_KEY_LENGTH] {
+        self.key.to_bytes()
+    }
+
+    /// Parses the hex form the key is displayed in, which is also the name
+    /// of its account.
+    pub fn from_hex(hex: &str) -> Result<Self, HVMError> {
+        let bytes = (0..hex.len()).step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| HVMError::Signature(format!("Invalid public key: {}", hex)))?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), HVMError> {
+        self.key.verify(signing_context(SIGNING_CONTEXT).bytes(message), &signature.signature)
+            .map_err(|_| HVMError::Signature("Signature verification failed".to_string()))
+    }
+}
+
+impl fmt::Debug for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VerifyingKey({})", self)
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_bytes().iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl Signature {
+    pub fn from_bytes(bytes: &[u8; SIGNATURE_LENGTH]) -> Result<Self, HVMError> {
+        let signature = schnorrkel::Signature::from_bytes(bytes)
+            .map_err(|e| HVMError::Signature(format!("Invalid signature: {}", e)))?;
+        Ok(Self { signature })
+    }
+
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_LENGTH] {
+        self.signature.to_bytes()
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Signature(")?;
+        self.to_bytes().iter().try_for_each(|byte| write!(f, "{:02x}", byte))?;
+        write!(f, ")")
+    }
+}
+
+impl Serialize for VerifyingKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_bytes::serialize(&self.to_bytes()[..], serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VerifyingKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = serde_bytes::deserialize(deserializer)?;
+        Self::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Signature {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_bytes::serialize(&self.to_bytes()[..], serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = serde_bytes::deserialize(deserializer)?;
+        let bytes: [u8; SIGNATURE_LENGTH] = bytes.try_into()
+            .map_err(|_| serde::de::Error::custom("Invalid signature length"))?;
+        Self::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
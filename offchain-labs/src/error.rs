@@ -41,6 +41,30 @@ pub enum HVMError {
     #[error("Execution error: {0}")]
     Execution(String),
 
+    #[error("Out of gas: limit of {0} exhausted")]
+    OutOfGas(u64),
+
+    #[error("Stack overflow: call depth limit of {0} exceeded")]
+    StackOverflow(u32),
+
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    #[error("ABI error: {0}")]
+    Abi(String),
+
+    #[error("Registry error: {0}")]
+    Registry(String),
+
+    #[error("Signature error: {0}")]
+    Signature(String),
+
+    #[error("Compilation error: {0}")]
+    Compilation(String),
+
+    #[error("State after batch {0} was pruned")]
+    StatePruned(u64),
+
     #[error("Balance error")]
     InsufficientBalance(),
 }
\ No newline at end of file
@@ -1,18 +1,23 @@
 pub mod config;
+pub mod crypto;
 pub mod error;
 pub mod prover;
 pub mod sequencer;
 pub mod verifier;
 pub mod zk_rollup;
 pub mod bend;
+pub mod rpc;
 
 pub use config::Config;
+use config::{CompilerConfig, ExecutionConfig, FeeConfig, SequencerConfig};
 use std::collections::HashMap;
+use std::path::Path;
 use error::HVMError;
-use sequencer::Transaction;
+use sequencer::{fees, BatchHeader, Deposit, ExecutionReceipt, ForcedTransaction, ProgramStatus, Receipt, StoredBatch, Transaction, Withdrawal, WithdrawalProof};
 use prover::ZKProver;
-use verifier::ZKVerifier;
-use bend::{BendProgram, storage::Storage};
+use verifier::{AuditReport, Auditor, ZKVerifier};
+use bend::{BendProgram, registry::SignedDeployment, storage::Storage};
+use zk_rollup::{StateProof, StorageProof};
 
 use ark_bn254::Bn254;
 use ark_groth16::{Groth16, ProvingKey, VerifyingKey};
@@ -23,101 +28,331 @@ pub struct OffchainLabs {
     sequencer: sequencer::Sequencer,
     verifier: ZKVerifier,
     storage: Storage,
-    user_balances: HashMap<String, u64>,
+    /// Sender and fee reserved for each queued transaction, by transaction
+    /// hash.
+    reserved_fees: HashMap<String, (String, u64)>,
+    execution_config: ExecutionConfig,
+    compiler_config: CompilerConfig,
+    sequencer_config: SequencerConfig,
+    fee_config: FeeConfig,
 }
 
 impl OffchainLabs {
     pub fn new(config: Config) -> Result<Self, HVMError> {        
+        let sequencer = sequencer::Sequencer::with_fee_config(zk_rollup::State::default(), config.sequencer_config.clone(), config.fee_config.clone())
+            .with_history(config.history_config.clone());
+        Self::with_sequencer(config, sequencer)
+    }
+
+    /// Starts a node from a snapshot of the state after the batch with
+    /// `header`, which should come from a source the node trusts. Later
+    /// batches are then applied with [`replay_batch`](Self::replay_batch).
+    pub fn from_snapshot(config: Config, snapshot_path: &Path, header: &BatchHeader) -> Result<Self, HVMError> {
+        let sequencer = sequencer::Sequencer::from_snapshot(snapshot_path, header, config.sequencer_config.clone(), config.fee_config.clone())?
+            .with_history(config.history_config.clone());
+        Self::with_sequencer(config, sequencer)
+    }
+
+    fn with_sequencer(config: Config, sequencer: sequencer::Sequencer) -> Result<Self, HVMError> {
         let (pk, vk) = Self::generate_zk_keys(&config)?;
         
-        let prover = ZKProver::new(pk);
-        let sequencer = sequencer::Sequencer::new(zk_rollup::State::default(), config.sequencer_config.clone());
+        let mut prover = ZKProver::new(pk, config.execution_config.clone());
+        for program in sequencer.deployed_programs() {
+            prover.add_program(program.clone());
+        }
         let verifier = ZKVerifier::new(vk);
         let storage = Storage::new();
-        let user_balances = HashMap::new();
 
         Ok(Self {
             prover,
             sequencer,
             verifier,
             storage,
-            user_balances,
+            reserved_fees: HashMap::new(),
+            execution_config: config.execution_config,
+            compiler_config: config.compiler_config,
+            sequencer_config: config.sequencer_config,
+            fee_config: config.fee_config,
         })
     }
 
-    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<bool, HVMError> {
-        self.sequencer.process_transaction(transaction)?;
-    
-        if let Some(batch) = self.sequencer.create_batch(true)? {
-            let proof = self.prover.generate_proof(&batch)?;
-            let is_valid = self.verifier.verify_proof(&proof, &batch.programs().iter().flat_map(|p| p.get_public_inputs()).collect::<Vec<_>>())?;
-            
-            if is_valid {
-                self.sequencer.apply_proof(proof, &batch)?;
+    /// Queues a transaction and proves a batch with it right away, returning
+    /// its receipt. The most the transaction can cost is reserved from the
+    /// sender's balance until the batch is applied, which only charges for
+    /// the gas it used.
+    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<Receipt, HVMError> {
+        // Checked before anything is reserved from the sender's balance.
+        transaction.verify_signature()?;
+        let sender = transaction.sender.clone();
+        let transaction_hash = transaction.hash();
+        let max_fee = fees::max_fee(transaction.fee_limit, self.sequencer.fee_market().base_fee(), self.execution_config.gas_limit);
+        self.reserve_balance(&sender, max_fee)?;
+        if let Err(e) = self.sequencer.process_transaction(transaction) {
+            self.refund_excess_balance(&sender, max_fee, 0);
+            return Err(e);
+        }
+        self.reserved_fees.insert(transaction_hash.clone(), (sender, max_fee));
+        if !self.process_batch()? {
+            return Err(HVMError::Verifier("Batch proof failed verification".to_string()));
+        }
+        self.receipt(&transaction_hash).cloned()
+            .ok_or_else(|| HVMError::Sequencer(format!("No receipt for transaction {}", transaction_hash)))
+    }
+
+    /// Queues a transaction read from the L1 forced inclusion queue and proves
+    /// a batch with it right away. Returns `None` if it was already queued.
+    pub fn process_forced_transaction(&mut self, forced: ForcedTransaction) -> Result<Option<Receipt>, HVMError> {
+        let transaction_hash = forced.transaction.hash();
+        if !self.sequencer.submit_forced_transaction(forced)? {
+            return Ok(None);
+        }
+        if !self.process_batch()? {
+            return Err(HVMError::Verifier("Batch proof failed verification".to_string()));
+        }
+        self.receipt(&transaction_hash).cloned()
+            .map(Some)
+            .ok_or_else(|| HVMError::Sequencer(format!("No receipt for transaction {}", transaction_hash)))
+    }
+
+    /// Receipt of an applied transaction, by [`Transaction::hash`].
+    pub fn receipt(&self, transaction_hash: &str) -> Option<&Receipt> {
+        self.sequencer.receipt(transaction_hash)
+    }
+
+    /// Proves the next batch and applies it if the proof verifies, then
+    /// releases the fees reserved for its transactions. A batch that is not
+    /// applied is reverted.
+    fn process_batch(&mut self) -> Result<bool, HVMError> {
+        let mut batch = match self.sequencer.create_batch(true)? {
+            Some(batch) => batch,
+            None => return Ok(true),
+        };
+        if !self.prove_and_apply(&mut batch)? {
+            return Ok(false);
+        }
+        for tx in batch.transactions() {
+            if let Some((sender, reserved)) = self.reserved_fees.remove(&tx.hash()) {
+                self.refund_excess_balance(&sender, reserved, 0);
             }
-    
-            Ok(is_valid)
-        } else {
-            Ok(true)
         }
+        Ok(true)
     }
 
-    pub fn submit_program(&mut self, program: BendProgram) -> Result<(), HVMError> {
-        self.sequencer.submit_program(program.clone())?;
-        self.storage.store_program(program)
+    fn prove_and_apply(&mut self, batch: &mut sequencer::Batch) -> Result<bool, HVMError> {
+        let verified = self.sequencer.forced_inclusion_check(batch)
+            .and_then(|forced_inclusion| {
+                let proof = self.prover.generate_proof(batch, self.sequencer.state(), forced_inclusion)?;
+                let is_valid = self.verifier.verify_proof(&proof, &batch.public_inputs(forced_inclusion))?;
+                Ok((proof, is_valid))
+            });
+        let proof = match verified {
+            Ok((proof, true)) => proof,
+            Ok((_, false)) => {
+                self.revert_batch(batch, "Batch proof failed verification");
+                return Ok(false);
+            }
+            Err(e) => {
+                self.revert_batch(batch, &e.to_string());
+                return Err(e);
+            }
+        };
+        if let Err(e) = self.sequencer.apply_proof(proof, batch) {
+            self.revert_batch(batch, &e.to_string());
+            return Err(e);
+        }
+        self.load_deployed_programs(batch)?;
+        Ok(true)
     }
 
-    pub fn deploy_program(&mut self, program: BendProgram) -> Result<(), HVMError> {
-        self.sequencer.deploy_program(program.clone())?;
-        self.storage.store_program(program)
+    /// Makes the programs a batch deployed available to the prover.
+    fn load_deployed_programs(&mut self, batch: &sequencer::Batch) -> Result<(), HVMError> {
+        for deployment in batch.programs() {
+            if matches!(self.sequencer.program_status(deployment.program.id()), Some(ProgramStatus::Deployed { .. })) {
+                self.prover.add_program(deployment.program.clone());
+                self.storage.store_program(deployment.program.clone())?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn execute_program(&mut self, program_id: &str, inputs: Vec<u8>, user_id: &str) -> Result<Vec<u8>, HVMError> {
-        let program = self.storage.load_program(program_id)?;
-        let estimated_usage = self.prover.estimate_resource_usage(&program)?;
-        let required_balance = self.calculate_required_balance(&estimated_usage);
+    /// Applies a batch produced by another node on top of the current state.
+    /// See [`Sequencer::replay_batch`](sequencer::Sequencer::replay_batch).
+    pub fn replay_batch(&mut self, proof: zk_rollup::Proof, batch: &sequencer::Batch, header: &BatchHeader) -> Result<(), HVMError> {
+        self.sequencer.replay_batch(proof, batch, header, &self.execution_config)?;
+        self.load_deployed_programs(batch)
+    }
 
-        self.check_and_deduct_balance(user_id, required_balance)?;
+    /// Header of an applied batch, committing to the state it led to.
+    pub fn batch_header(&self, batch_number: u64) -> Option<&BatchHeader> {
+        self.sequencer.batch_header(batch_number)
+    }
 
-        let result = self.sequencer.execute_program(program_id, inputs)?;
+    /// An applied batch with its header and proof.
+    pub fn batch(&self, batch_number: u64) -> Option<&StoredBatch> {
+        self.sequencer.applied_batch(batch_number)
+    }
 
-        let actual_usage = self.prover.estimate_resource_usage(&program)?;
-        let actual_cost = self.calculate_actual_cost(&actual_usage);
-        self.refund_excess_balance(user_id, required_balance, actual_cost);
+    /// Number of batches applied, which is the number of the next one.
+    pub fn batch_count(&self) -> u64 {
+        self.sequencer.state().nonce()
+    }
 
-        Ok(result)
+    /// Re-executes every batch this node applied on a new sequencer from the
+    /// genesis state and checks it against its proof and header. A node
+    /// started from a snapshot reports its first batch as out of order.
+    pub fn audit_batches(&self) -> AuditReport {
+        let sequencer = sequencer::Sequencer::with_fee_config(zk_rollup::State::default(), self.sequencer_config.clone(), self.fee_config.clone())
+            .with_forced_queue(self.sequencer.forced_queue().clone());
+        Auditor::new(sequencer, Some(self.verifier.clone()), self.execution_config.clone())
+            .audit(self.sequencer.applied_batches())
     }
 
-    fn calculate_required_balance(&self, usage: &prover::ResourceUsage) -> u64 {
-        usage.cpu_cycles + usage.memory_usage
+    /// Writes the current state to a snapshot file that other nodes can
+    /// start from, checking it against [`batch_header`](Self::batch_header).
+    pub fn export_snapshot(&self, path: &Path) -> Result<zk_rollup::SnapshotManifest, HVMError> {
+        self.sequencer.export_snapshot(path, zk_rollup::DEFAULT_ENTRIES_PER_CHUNK)
     }
 
-    fn check_and_deduct_balance(&mut self, user_id: &str, amount: u64) -> Result<(), HVMError> {
-        let balance = self.user_balances.entry(user_id.to_string()).or_insert(0);
-        if *balance < amount {
-            return Err(HVMError::InsufficientBalance());
+    /// Rolls a batch back in the sequencer. Transactions that are queued
+    /// again keep their fee reservations; rejected ones get them back.
+    fn revert_batch(&mut self, batch: &sequencer::Batch, reason: &str) {
+        let failure = self.sequencer.revert_batch(batch, reason).clone();
+        for hash in &failure.rejected {
+            if let Some((sender, reserved)) = self.reserved_fees.remove(hash) {
+                self.refund_excess_balance(&sender, reserved, 0);
+            }
         }
-        *balance -= amount;
-        Ok(())
     }
 
-    fn calculate_actual_cost(&self, usage: &prover::ResourceUsage) -> u64 {
-        usage.cpu_cycles + usage.memory_usage
+    /// Batches that were reverted, oldest first.
+    pub fn batch_failures(&self) -> &[sequencer::BatchFailure] {
+        self.sequencer.batch_failures()
+    }
+
+    /// Compiles Bend source or an HVM2 net with the configured toolchain. The
+    /// result still has to be signed and submitted to be deployed.
+    pub fn compile_program(&self, language: bend::compiler::SourceLanguage, source: &str, metadata: bend::ProgramMetadata, author: String) -> Result<BendProgram, HVMError> {
+        let program = BendProgram::from_source(language, source, metadata, author, &self.compiler_config)?;
+        program.validate(&self.execution_config)?;
+        Ok(program)
+    }
+
+    /// Submits a signed program for deployment with the next batch. Its
+    /// progress can be followed through [`program_status`](Self::program_status).
+    pub fn submit_program(&mut self, deployment: SignedDeployment) -> Result<(), HVMError> {
+        self.sequencer.submit_program(deployment, &self.execution_config)
+    }
+
+    /// Submits a signed program and proves a batch with it right away.
+    pub fn deploy_program(&mut self, deployment: SignedDeployment) -> Result<(), HVMError> {
+        let program_id = deployment.program.id().to_string();
+        self.submit_program(deployment)?;
+        self.process_batch()?;
+        match self.sequencer.program_status(&program_id) {
+            Some(ProgramStatus::Deployed { .. }) => Ok(()),
+            status => Err(HVMError::Sequencer(format!("Program {} was not deployed: {:?}", program_id, status))),
+        }
+    }
+
+    pub fn program_status(&self, program_id: &str) -> Option<&ProgramStatus> {
+        self.sequencer.program_status(program_id)
+    }
+
+    /// Executes a deployed program against the committed state with the gas
+    /// `fee_limit` pays for at the current base fee. `program` is either a
+    /// program id or a `name@requirement` reference pinning the caller to a
+    /// version range.
+    ///
+    /// Its writes and events are not kept, so nobody is charged for it.
+    /// Changes to the state go through signed transactions, see
+    /// [`process_transaction`](Self::process_transaction).
+    pub fn execute_program(&self, program: &str, inputs: Vec<u8>, fee_limit: u64) -> Result<ExecutionReceipt, HVMError> {
+        let gas_price = self.sequencer.fee_market().base_fee();
+        let config = ExecutionConfig {
+            gas_limit: fees::gas_limit(fee_limit, gas_price, self.execution_config.gas_limit),
+            ..self.execution_config.clone()
+        };
+        if config.gas_limit == 0 {
+            return Err(HVMError::Sequencer(format!("Fee limit {} is below the base fee of {}", fee_limit, gas_price)));
+        }
+        self.run_program(program, inputs, &config)
+    }
+
+    /// Executes a deployed program against the committed state without
+    /// charging anyone or keeping its writes, up to the execution gas limit.
+    pub fn simulate_program(&self, program: &str, inputs: Vec<u8>) -> Result<ExecutionReceipt, HVMError> {
+        self.run_program(program, inputs, &self.execution_config)
+    }
+
+    fn run_program(&self, program: &str, inputs: Vec<u8>, config: &ExecutionConfig) -> Result<ExecutionReceipt, HVMError> {
+        let program_id = self.sequencer.resolve_program(program)?.id().to_string();
+        let result = self.sequencer.execute_program(&program_id, inputs, config)?;
+        Ok(ExecutionReceipt {
+            program_id,
+            outputs: result.output_bytes(),
+            gas_used: result.gas_used,
+            gas_price: self.sequencer.fee_market().base_fee(),
+        })
+    }
+
+    pub fn program_versions(&self, name: &str) -> Result<&[bend::registry::ProgramVersion], HVMError> {
+        self.sequencer.registry().versions(name)
+    }
+
+    fn reserve_balance(&mut self, user_id: &str, amount: u64) -> Result<(), HVMError> {
+        self.sequencer.reserve_funds(user_id, amount)
     }
 
     fn refund_excess_balance(&mut self, user_id: &str, deducted: u64, actual_cost: u64) {
         if actual_cost < deducted {
-            let refund = deducted - actual_cost;
-            *self.user_balances.entry(user_id.to_string()).or_insert(0) += refund;
+            self.sequencer.release_funds(user_id, deducted - actual_cost);
+        }
+    }
+
+    /// Credits an L1 deposit by proving a batch with it right away. Returns
+    /// `false` if the deposit was already credited.
+    pub fn process_deposit(&mut self, deposit: Deposit) -> Result<bool, HVMError> {
+        let nonce = deposit.nonce;
+        if !self.sequencer.submit_deposit(deposit)? {
+            return Ok(false);
+        }
+        self.process_batch()?;
+        if self.sequencer.state().deposit_nonce() <= nonce {
+            return Err(HVMError::Sequencer(format!("Deposit {} was not credited", nonce)));
         }
+        Ok(true)
     }
 
-    pub fn deposit_funds(&mut self, user_id: &str, amount: u64) {
-        *self.user_balances.entry(user_id.to_string()).or_insert(0) += amount;
+    /// Reserves the amount of a withdrawal and proves a batch with it right
+    /// away, which debits it, returning the proof to claim it with on the L1.
+    pub fn process_withdrawal(&mut self, withdrawal: Withdrawal) -> Result<WithdrawalProof, HVMError> {
+        let hash = withdrawal.hash();
+        self.sequencer.submit_withdrawal(withdrawal)?;
+        self.process_batch()?;
+        self.withdrawal_proof(&hash)
     }
 
+    /// Proof for claiming an applied withdrawal, by [`Withdrawal::hash`].
+    pub fn withdrawal_proof(&self, withdrawal_hash: &str) -> Result<WithdrawalProof, HVMError> {
+        self.sequencer.withdrawal_proof(withdrawal_hash)
+    }
+
+    /// Balance of an account less what is reserved for its queued
+    /// transactions, withdrawals and fees.
     pub fn get_balance(&self, user_id: &str) -> u64 {
-        *self.user_balances.get(user_id).unwrap_or(&0)
+        self.sequencer.available_balance(user_id)
+    }
+
+    /// Nonce the next transaction of `account` has to have, counting its
+    /// queued transactions.
+    pub fn next_nonce(&self, account: &str) -> u64 {
+        self.sequencer.next_nonce(account)
+    }
+
+    /// Nonce the next withdrawal from `account` has to have, counting its
+    /// queued withdrawals.
+    pub fn next_withdrawal_nonce(&self, account: &str) -> u64 {
+        self.sequencer.next_withdrawal_nonce(account)
     }
 
     fn generate_zk_keys(_config: &Config) -> Result<(ProvingKey<Bn254>, VerifyingKey<Bn254>), HVMError> {
@@ -131,6 +366,34 @@ impl OffchainLabs {
         Ok(self.sequencer.get_current_state())
     }
 
+    /// State right after batch `batch_number` was applied. Fails with
+    /// [`HVMError::StatePruned`] if the retention policy dropped it.
+    pub fn get_state_at(&self, batch_number: u64) -> Result<zk_rollup::State, HVMError> {
+        self.sequencer.state_at(batch_number)
+    }
+
+    /// Balance, nonce and withdrawal nonce of `account` after batch
+    /// `batch_number`, proven against the state root in that batch's header.
+    pub fn get_account_at(&self, account: &str, batch_number: u64) -> Result<StateProof, HVMError> {
+        self.sequencer.state_at(batch_number)?.account_proof(account)
+            .ok_or_else(|| HVMError::Sequencer(format!("Account {} is not in the state after batch {}", account, batch_number)))
+    }
+
+    /// State and deposit nonces after batch `batch_number`, proven against
+    /// that batch's state root.
+    pub fn get_counters_at(&self, batch_number: u64) -> Result<StateProof, HVMError> {
+        Ok(self.sequencer.state_at(batch_number)?.counters_proof())
+    }
+
+    /// Value at `key` in a program's storage after batch `batch_number`,
+    /// proven against that batch's state root.
+    pub fn get_storage_at(&self, program_id: &str, key: &[u8], batch_number: u64) -> Result<StorageProof, HVMError> {
+        self.sequencer.state_at(batch_number)?.storage_proof(program_id, key)
+            .ok_or_else(|| HVMError::Sequencer(format!(
+                "Program {} has no value at key {:?} after batch {}", program_id, key, batch_number
+            )))
+    }
+
     pub fn pending_transactions_count(&self) -> usize {
         self.sequencer.pending_transactions_count()
     }
@@ -151,7 +414,7 @@ impl OffchainLabs {
         self.prover.estimate_resource_usage(program)
     }
 
-    pub fn optimize_program(&self, program: &BendProgram) -> Result<BendProgram, HVMError> {
-        self.prover.optimize_program(program)
+    pub fn optimize_program(&self, program: &BendProgram, sample_inputs: &[Vec<u8>]) -> Result<prover::OptimizationReport, HVMError> {
+        self.prover.optimize_program(program, sample_inputs)
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,5 @@
-use offchain_labs::{Config, OffchainLabs, sequencer::Transaction};
-use log::{info, error};
+use offchain_labs::{Config, OffchainLabs, rpc::{self, RollupRpc}};
+use log::info;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -9,31 +9,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("Failed to load config: {}. Using default configuration.", e);
         Config::default()
     });
-    
-    let mut hvm = OffchainLabs::new(config)?;
+    let rpc_config = config.rpc_config.clone();
 
-    info!("OffchainLabs initialized");
+    let hvm = OffchainLabs::new(config)?;
 
-    let transactions = vec![
-        Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string()),
-        Transaction::new("Bob".to_string(), "Charlie".to_string(), vec![50], 1, "test_program".to_string()),
-        Transaction::new("Charlie".to_string(), "Alice".to_string(), vec![25], 1, "test_program".to_string()),
-    ];
+    info!("OffchainLabs initialized");
 
-    for (i, tx) in transactions.iter().enumerate() {
-        match hvm.process_transaction(tx.clone()) {
-            Ok(is_valid) => {
-                info!("Transaction {} processed. Valid: {}", i, is_valid);
-            }
-            Err(e) => {
-                error!("Error processing transaction {}: {}", i, e);
-            }
-        }
-    }
+    let rpc = RollupRpc::new(hvm, rpc_config.subscription_buffer);
+    let (address, handle) = rpc::serve(rpc, &rpc_config).await?;
+    info!("Serving JSON-RPC over HTTP and WebSocket on {}", address);
 
-    info!("All transactions processed");
-    info!("Pending transactions: {}", hvm.pending_transactions_count());
-    info!("Current state: {:?}", hvm.get_current_state()?);
+    tokio::signal::ctrl_c().await?;
+    info!("Shutting down");
+    handle.stop()?;
+    handle.stopped().await;
 
     Ok(())
-}
\ No newline at end of file
+}
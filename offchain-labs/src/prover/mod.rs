@@ -1,45 +1,46 @@
 use crate::error::HVMError;
-use crate::zk_rollup::Proof;
-use crate::sequencer::Batch;
+use crate::zk_rollup::{Proof, State};
+use crate::sequencer::{fees, Batch, ReceiptStatus};
 use crate::Transaction;
-use crate::bend::{BendProgram, BendCircuit};
+use crate::bend::{BendProgram, CallContext, ExecutionBackend, ForcedInclusionCheck};
+use crate::bend::optimizer::{self, OptimizationStats};
 use ark_bn254::Bn254;
 use ark_groth16::{Groth16, ProvingKey};
 use ark_snark::SNARK;
 use ark_serialize::CanonicalSerialize;
 use ark_std::rand::thread_rng;
-use wasmer::{Store, Module, Instance, Cranelift};
-use std::time::Instant;
+use crate::config::ExecutionConfig;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct ZKProver {
     proving_key: ProvingKey<Bn254>,
     program_cache: HashMap<String, BendProgram>,
+    execution_config: ExecutionConfig,
 }
 
 impl ZKProver {
-    pub fn new(proving_key: ProvingKey<Bn254>) -> Self {
+    pub fn new(proving_key: ProvingKey<Bn254>, execution_config: ExecutionConfig) -> Self {
         Self {
             proving_key,
             program_cache: HashMap::new(),
+            execution_config,
         }
     }
 
-    pub fn generate_proof(&self, batch: &Batch) -> Result<Proof, HVMError> {
-        let mut inputs = Vec::new();
-        let mut outputs = Vec::new();
-
-        for transaction in batch.transactions() {
-            let program = self.get_program_for_transaction(transaction)?;
-            let execution_result = program.execute(transaction.amount.clone())?;
-            inputs.extend(program.get_public_inputs());
-            outputs.extend(execution_result);
+    /// Executes the batch against `state` and proves it with the forced
+    /// inclusion inputs the L1 derives for it. See [`execute_batch`] for
+    /// what is recorded on the batch.
+    pub fn generate_proof(&self, batch: &mut Batch, state: &State, forced_inclusion: ForcedInclusionCheck) -> Result<Proof, HVMError> {
+        if !forced_inclusion.is_satisfied() {
+            return Err(HVMError::Prover(format!(
+                "Batch {} leaves a forced transaction due at batch {} in the queue",
+                forced_inclusion.batch_number, forced_inclusion.next_deadline,
+            )));
         }
+        execute_batch(self.program_cache.values(), batch, state, &self.execution_config);
 
-        let circuit = BendCircuit {
-            inputs,
-            outputs,
-        };
+        let circuit = batch.circuit(forced_inclusion);
         let mut rng = thread_rng();
         
         let proof = Groth16::<Bn254>::prove(&self.proving_key, circuit, &mut rng)
@@ -52,51 +53,61 @@ impl ZKProver {
         Ok(Proof::new(proof_bytes))
     }
 
-    fn get_program_for_transaction(&self, transaction: &Transaction) -> Result<&BendProgram, HVMError> {
-        let program_id = &transaction.program_id;
-        println!("Program Id: {:?}", program_id);
-
-        self.program_cache.get(program_id)
-            .ok_or_else(|| HVMError::Prover(format!("Program not found for ID: {}", program_id)))
-    }
-
     pub fn estimate_resource_usage(&self, program: &BendProgram) -> Result<ResourceUsage, HVMError> {
-        let mut store = Store::new(Cranelift::default());
-        let module = Module::new(&mut store, &program.bytecode)
-            .map_err(|e| HVMError::Estimation(format!("Failed to create module: {}", e)))?;
-        let import_object = wasmer::imports! {};
-        let instance = Instance::new(&mut store, &module, &import_object)
-            .map_err(|e| HVMError::Estimation(format!("Failed to instantiate module: {}", e)))?;
-
-        let memory = instance.exports.get_memory("memory")
-            .map_err(|e| HVMError::Estimation(format!("Failed to get memory: {}", e)))?;
-
-        let run = instance.exports.get_function("run")
-            .map_err(|e| HVMError::Estimation(format!("Failed to get run function: {}", e)))?;
-
-        let start_memory = memory.view(&store).data_size() as u64;
-        let start_time = Instant::now();
-
-        run.call(&mut store, &[])
-            .map_err(|e| HVMError::Estimation(format!("Failed to execute program: {}", e)))?;
-
-        let end_time = Instant::now();
-        let end_memory = memory.view(&store).data_size() as u64;
+        let result = program.execute(Vec::new(), &self.execution_config)
+            .map_err(|e| match e {
+                HVMError::OutOfGas(_) => e,
+                e => HVMError::Estimation(format!("Failed to execute program: {}", e)),
+            })?;
 
         Ok(ResourceUsage {
-            cpu_cycles: end_time.duration_since(start_time).as_micros() as u64,
-            memory_usage: end_memory - start_memory,
+            gas_used: result.gas_used,
+            memory_usage: result.memory_usage,
         })
     }
 
-    pub fn optimize_program(&self, program: &BendProgram) -> Result<BendProgram, HVMError> {
-        let optimized_bytecode = program.bytecode.clone();
-
-        Ok(BendProgram::new(
+    /// Optimizes the program's bytecode and checks that the result behaves
+    /// the same on `sample_inputs`, or on empty inputs when none are given.
+    ///
+    /// Runs that the original program cannot finish within the gas limit are
+    /// allowed to succeed once optimized; any other difference in outputs,
+    /// storage writes or failure is an error.
+    pub fn optimize_program(&self, program: &BendProgram, sample_inputs: &[Vec<u8>]) -> Result<OptimizationReport, HVMError> {
+        if program.backend != ExecutionBackend::Wasm {
+            return Err(HVMError::Optimization(format!("{} programs cannot be optimized", program.backend.name())));
+        }
+        let (optimized_bytecode, stats) = optimizer::optimize(&program.bytecode)?;
+        let optimized = BendProgram::new(
             optimized_bytecode,
             program.metadata.clone(),
             program.author.clone()
-        ))
+        );
+
+        let default_inputs = [Vec::new()];
+        let sample_inputs = if sample_inputs.is_empty() { &default_inputs[..] } else { sample_inputs };
+        let mut original_gas = 0;
+        let mut optimized_gas = 0;
+        for (i, inputs) in sample_inputs.iter().enumerate() {
+            let original_result = program.execute(inputs.clone(), &self.execution_config);
+            let optimized_result = optimized.execute(inputs.clone(), &self.execution_config);
+            match (original_result, optimized_result) {
+                (Ok(original), Ok(result)) if original.outputs == result.outputs && original.storage_changes == result.storage_changes => {
+                    original_gas += original.gas_used;
+                    optimized_gas += result.gas_used;
+                }
+                (Err(HVMError::OutOfGas(_)), Ok(_)) | (Err(_), Err(_)) => {}
+                _ => return Err(HVMError::Optimization(format!("Optimized program behaves differently on sample input {}", i))),
+            }
+        }
+
+        Ok(OptimizationReport {
+            original_size: program.bytecode.len(),
+            optimized_size: optimized.bytecode.len(),
+            original_gas,
+            optimized_gas,
+            stats,
+            program: optimized,
+        })
     }
 
     pub fn add_program(&mut self, program: BendProgram) {
@@ -104,12 +115,99 @@ impl ZKProver {
     }
 }
 
+/// Result of [`ZKProver::optimize_program`]. Gas figures are totals over the
+/// sample inputs that both versions ran to completion.
+#[derive(Debug)]
+pub struct OptimizationReport {
+    pub program: BendProgram,
+    pub original_size: usize,
+    pub optimized_size: usize,
+    pub original_gas: u64,
+    pub optimized_gas: u64,
+    pub stats: OptimizationStats,
+}
+
 #[derive(Debug)]
 pub struct ResourceUsage {
-    pub cpu_cycles: u64,
+    pub gas_used: u64,
     pub memory_usage: u64,
 }
 
-pub fn create_zk_prover(proving_key: ProvingKey<Bn254>) -> ZKProver {
-    ZKProver::new(proving_key)
+/// Executes the transactions of a batch against `state` with `programs`,
+/// recording their storage writes and a receipt for each on the batch.
+///
+/// Each transaction runs with the gas its fee limit pays for at the batch's
+/// gas price, and each forced transaction with
+/// [`FORCED_TRANSACTION_GAS`](fees::FORCED_TRANSACTION_GAS). A transaction
+/// that runs out of gas or fails is charged for all of its gas and its
+/// writes and events are dropped; one whose program is not deployed fails
+/// without being charged. The outcome only depends on the arguments, so a
+/// batch can be executed again to check the results it was applied with.
+pub fn execute_batch<'a>(
+    programs: impl IntoIterator<Item = &'a BendProgram>,
+    batch: &mut Batch,
+    state: &State,
+    execution_config: &ExecutionConfig,
+) {
+    let programs: HashMap<&str, &BendProgram> = programs.into_iter()
+        .map(|program| (program.id(), program))
+        .collect();
+    let mut context = CallContext::with_programs(programs.values().copied(), state.program_storage.clone());
+    context.set_registry(state.registry.clone());
+    let mut context = Arc::new(context);
+    let mut storage_changes = Vec::new();
+    let mut receipts = Vec::new();
+
+    for (index, transaction) in batch.transactions().iter().enumerate() {
+        let program = match get_program_for_transaction(&programs, &context, transaction) {
+            Ok(program) => program,
+            Err(e) => {
+                receipts.push((ReceiptStatus::Failed { reason: e.to_string() }, 0, Vec::new(), Vec::new()));
+                continue;
+            }
+        };
+        let config = ExecutionConfig {
+            gas_limit: if batch.is_forced(index) {
+                fees::FORCED_TRANSACTION_GAS.min(execution_config.gas_limit)
+            } else {
+                fees::gas_limit(transaction.fee_limit, batch.gas_price(), execution_config.gas_limit)
+            },
+            ..execution_config.clone()
+        };
+        let execution_result = match program.execute_in(transaction.amount.clone(), &context, &config) {
+            Ok(result) => result,
+            Err(HVMError::OutOfGas(limit)) => {
+                receipts.push((ReceiptStatus::OutOfGas, limit, Vec::new(), Vec::new()));
+                continue;
+            }
+            Err(e) => {
+                receipts.push((ReceiptStatus::Failed { reason: e.to_string() }, config.gas_limit, Vec::new(), Vec::new()));
+                continue;
+            }
+        };
+        Arc::make_mut(&mut context).apply_storage_changes(&execution_result.storage_changes);
+        receipts.push((ReceiptStatus::Success, execution_result.gas_used, execution_result.output_bytes(), execution_result.events));
+        storage_changes.push(execution_result.storage_changes);
+    }
+    for (program_id, changes) in storage_changes.into_iter().flatten() {
+        batch.record_storage_changes(&program_id, changes);
+    }
+    for (status, gas_used, return_data, events) in receipts {
+        batch.record_receipt(status, gas_used, return_data, events);
+    }
+}
+
+/// The program `transaction` calls, by id or by a `name@requirement`
+/// reference resolved against the registry of the state.
+fn get_program_for_transaction<'a>(programs: &HashMap<&str, &'a BendProgram>, context: &CallContext, transaction: &Transaction) -> Result<&'a BendProgram, HVMError> {
+    let program_id = &transaction.program_id;
+    println!("Program Id: {:?}", program_id);
+
+    context.resolve(program_id)
+        .and_then(|program| programs.get(program.id()).copied())
+        .ok_or_else(|| HVMError::Prover(format!("Program not found for ID: {}", program_id)))
+}
+
+pub fn create_zk_prover(proving_key: ProvingKey<Bn254>, execution_config: ExecutionConfig) -> ZKProver {
+    ZKProver::new(proving_key, execution_config)
 }
\ No newline at end of file
@@ -0,0 +1,92 @@
+use crate::bend::registry::SignedDeployment;
+use crate::crypto::Signature;
+use crate::sequencer::{
+    BatchHeader, Deposit, ExecutionReceipt, ForcedTransaction, ProgramStatus, Receipt, StoredBatch, Transaction, Withdrawal,
+    WithdrawalProof,
+};
+use crate::zk_rollup::{StateProof, StorageProof};
+use jsonrpsee::core::{RpcResult, SubscriptionResult};
+use jsonrpsee::proc_macros::rpc;
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub account: String,
+    /// Balance less what is reserved for queued transactions, withdrawals
+    /// and fees.
+    pub balance: u64,
+    /// Nonce the account's next transaction has to have.
+    pub nonce: u64,
+    /// Nonce the account's next withdrawal has to have.
+    pub withdrawal_nonce: u64,
+}
+
+/// Methods served under the `rollup_` prefix. Methods that prove a batch
+/// block until it is applied or reverted.
+#[rpc(server, namespace = "rollup")]
+pub trait RollupApi {
+    /// The transaction must be signed by its sender.
+    #[method(name = "sendTransaction", blocking)]
+    fn send_transaction(&self, transaction: Transaction) -> RpcResult<Receipt>;
+
+    /// The withdrawal must be signed by the owner of its account.
+    #[method(name = "submitWithdrawal", blocking)]
+    fn submit_withdrawal(&self, withdrawal: Withdrawal) -> RpcResult<WithdrawalProof>;
+
+    #[method(name = "getWithdrawalProof", blocking)]
+    fn get_withdrawal_proof(&self, withdrawal_hash: String) -> RpcResult<WithdrawalProof>;
+
+    #[method(name = "getAccount", blocking)]
+    fn get_account(&self, account: String) -> RpcResult<AccountInfo>;
+
+    /// Balance and nonces of an account after a batch, proven against its
+    /// state root.
+    #[method(name = "getAccountProof", blocking)]
+    fn get_account_proof(&self, account: String, batch_number: u64) -> RpcResult<StateProof>;
+
+    #[method(name = "getStorageProof", blocking)]
+    fn get_storage_proof(&self, program_id: String, key: Vec<u8>, batch_number: u64) -> RpcResult<StorageProof>;
+
+    #[method(name = "deployProgram", blocking)]
+    fn deploy_program(&self, deployment: SignedDeployment) -> RpcResult<ProgramStatus>;
+
+    #[method(name = "getProgramStatus", blocking)]
+    fn get_program_status(&self, program_id: String) -> RpcResult<Option<ProgramStatus>>;
+
+    /// Runs a program against the committed state without charging for it
+    /// or keeping its writes. `program` is a program id or a
+    /// `name@requirement` reference.
+    #[method(name = "callProgram", blocking)]
+    fn call_program(&self, program: String, inputs: Vec<u8>) -> RpcResult<ExecutionReceipt>;
+
+    #[method(name = "getBatch", blocking)]
+    fn get_batch(&self, batch_number: u64) -> RpcResult<Option<StoredBatch>>;
+
+    #[method(name = "getBatchHeader", blocking)]
+    fn get_batch_header(&self, batch_number: u64) -> RpcResult<Option<BatchHeader>>;
+
+    #[method(name = "getReceipt", blocking)]
+    fn get_receipt(&self, transaction_hash: String) -> RpcResult<Option<Receipt>>;
+
+    #[method(name = "pendingTransactions", blocking)]
+    fn pending_transactions(&self) -> RpcResult<Vec<Transaction>>;
+
+    /// Header of every batch applied from now on. WebSocket only.
+    #[subscription(name = "subscribeBatches" => "batch", unsubscribe = "unsubscribeBatches", item = BatchHeader)]
+    async fn subscribe_batches(&self) -> SubscriptionResult;
+}
+
+/// Methods the bridge relayer forwards L1 events with, also under the
+/// `rollup_` prefix. They are only served when the node has a relayer key,
+/// and every call must carry the relayer's signature over
+/// [`bridge_message`](super::bridge_message) of its method and argument.
+#[rpc(server, namespace = "rollup")]
+pub trait BridgeApi {
+    /// Returns `null` if the forced transaction was already queued.
+    #[method(name = "sendForcedTransaction", blocking)]
+    fn send_forced_transaction(&self, forced: ForcedTransaction, signature: Signature) -> RpcResult<Option<Receipt>>;
+
+    /// Returns `false` if the deposit was already credited.
+    #[method(name = "submitDeposit", blocking)]
+    fn submit_deposit(&self, deposit: Deposit, signature: Signature) -> RpcResult<bool>;
+}
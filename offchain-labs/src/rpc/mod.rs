@@ -0,0 +1,221 @@
+mod api;
+
+pub use api::{AccountInfo, BridgeApiServer, RollupApiServer};
+
+use crate::bend::registry::SignedDeployment;
+use crate::config::RpcConfig;
+use crate::crypto::{Signature, VerifyingKey};
+use crate::error::HVMError;
+use crate::sequencer::{
+    BatchHeader, Deposit, ExecutionReceipt, ForcedTransaction, ProgramStatus, Receipt, StoredBatch, Transaction, Withdrawal,
+    WithdrawalProof,
+};
+use crate::zk_rollup::{StateProof, StorageProof};
+use crate::OffchainLabs;
+use jsonrpsee::core::{async_trait, RpcResult, SubscriptionResult};
+use jsonrpsee::server::{PendingSubscriptionSink, Server, ServerHandle, SubscriptionMessage};
+use jsonrpsee::types::ErrorObjectOwned;
+use log::warn;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Error code of node errors without a code of their own.
+pub const NODE_ERROR: i32 = -32000;
+pub const INSUFFICIENT_BALANCE: i32 = -32001;
+/// The state asked for was dropped by the node's history retention policy.
+pub const STATE_PRUNED: i32 = -32002;
+/// A signature is missing, malformed or not by the key it has to be by.
+pub const UNAUTHORIZED: i32 = -32003;
+
+const BRIDGE_DOMAIN: &[u8] = b"hvm-bridge";
+
+/// JSON-RPC front end of a node. Calls take turns on the node, and the
+/// header of every batch they apply is sent to batch subscribers.
+#[derive(Clone)]
+pub struct RollupRpc {
+    node: Arc<Mutex<OffchainLabs>>,
+    batches: broadcast::Sender<BatchHeader>,
+}
+
+impl RollupRpc {
+    pub fn new(node: OffchainLabs, subscription_buffer: usize) -> Self {
+        let (batches, _) = broadcast::channel(subscription_buffer.max(1));
+        Self { node: Arc::new(Mutex::new(node)), batches }
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&OffchainLabs) -> Result<T, HVMError>) -> RpcResult<T> {
+        f(&*self.lock()?).map_err(rpc_error)
+    }
+
+    fn write<T>(&self, f: impl FnOnce(&mut OffchainLabs) -> Result<T, HVMError>) -> RpcResult<T> {
+        let mut node = self.lock()?;
+        let first = node.batch_count();
+        let result = f(&mut node);
+        for batch_number in first..node.batch_count() {
+            if let Some(header) = node.batch_header(batch_number) {
+                // Only fails when nobody is subscribed.
+                let _ = self.batches.send(header.clone());
+            }
+        }
+        result.map_err(rpc_error)
+    }
+
+    fn lock(&self) -> RpcResult<MutexGuard<'_, OffchainLabs>> {
+        self.node.lock()
+            .map_err(|_| rpc_error(HVMError::StorageLock("A call panicked while holding the node".to_string())))
+    }
+}
+
+/// Bridge methods of a node, for the relayer holding `relayer`'s key.
+#[derive(Clone)]
+pub struct BridgeRpc {
+    rpc: RollupRpc,
+    relayer: VerifyingKey,
+}
+
+impl BridgeRpc {
+    pub fn new(rpc: RollupRpc, relayer: VerifyingKey) -> Self {
+        Self { rpc, relayer }
+    }
+
+    fn authorize(&self, method: &str, payload: &impl Serialize, signature: &Signature) -> RpcResult<()> {
+        bridge_message(method, payload)
+            .and_then(|message| self.relayer.verify(&message, signature))
+            .map_err(rpc_error)
+    }
+}
+
+/// Message the relayer signs to call bridge `method`, such as
+/// `rollup_submitDeposit`, with `payload`.
+pub fn bridge_message(method: &str, payload: &impl Serialize) -> Result<Vec<u8>, HVMError> {
+    let mut message = BRIDGE_DOMAIN.to_vec();
+    message.extend((method.len() as u32).to_le_bytes());
+    message.extend(method.as_bytes());
+    message.extend(serde_json::to_vec(payload)?);
+    Ok(message)
+}
+
+/// Serves `rpc` over HTTP and WebSocket on `config.listen_address`, until
+/// the returned handle is stopped or dropped. Returns the address bound,
+/// which tells the port when the configured one is `0`. The bridge methods
+/// are only served if `config.relayer_key` is set.
+pub async fn serve(rpc: RollupRpc, config: &RpcConfig) -> Result<(SocketAddr, ServerHandle), HVMError> {
+    let mut module = rpc.clone().into_rpc();
+    if let Some(relayer_key) = &config.relayer_key {
+        let bridge = BridgeRpc::new(rpc, VerifyingKey::from_hex(relayer_key)?);
+        module.merge(bridge.into_rpc())
+            .map_err(|e| HVMError::Config(e.to_string()))?;
+    }
+    let server = Server::builder()
+        .max_connections(config.max_connections)
+        .build(config.listen_address.as_str())
+        .await?;
+    let address = server.local_addr()?;
+    Ok((address, server.start(module)))
+}
+
+fn rpc_error(e: HVMError) -> ErrorObjectOwned {
+    let code = match e {
+        HVMError::InsufficientBalance() => INSUFFICIENT_BALANCE,
+        HVMError::StatePruned(_) => STATE_PRUNED,
+        HVMError::Signature(_) => UNAUTHORIZED,
+        _ => NODE_ERROR,
+    };
+    ErrorObjectOwned::owned(code, e.to_string(), None::<()>)
+}
+
+#[async_trait]
+impl RollupApiServer for RollupRpc {
+    fn send_transaction(&self, transaction: Transaction) -> RpcResult<Receipt> {
+        self.write(|node| node.process_transaction(transaction))
+    }
+
+    fn submit_withdrawal(&self, withdrawal: Withdrawal) -> RpcResult<WithdrawalProof> {
+        self.write(|node| node.process_withdrawal(withdrawal))
+    }
+
+    fn get_withdrawal_proof(&self, withdrawal_hash: String) -> RpcResult<WithdrawalProof> {
+        self.read(|node| node.withdrawal_proof(&withdrawal_hash))
+    }
+
+    fn get_account(&self, account: String) -> RpcResult<AccountInfo> {
+        self.read(|node| Ok(AccountInfo {
+            balance: node.get_balance(&account),
+            nonce: node.next_nonce(&account),
+            withdrawal_nonce: node.next_withdrawal_nonce(&account),
+            account,
+        }))
+    }
+
+    fn get_account_proof(&self, account: String, batch_number: u64) -> RpcResult<StateProof> {
+        self.read(|node| node.get_account_at(&account, batch_number))
+    }
+
+    fn get_storage_proof(&self, program_id: String, key: Vec<u8>, batch_number: u64) -> RpcResult<StorageProof> {
+        self.read(|node| node.get_storage_at(&program_id, &key, batch_number))
+    }
+
+    fn deploy_program(&self, deployment: SignedDeployment) -> RpcResult<ProgramStatus> {
+        let program_id = deployment.program.id().to_string();
+        self.write(|node| {
+            node.deploy_program(deployment)?;
+            node.program_status(&program_id).cloned()
+                .ok_or_else(|| HVMError::Sequencer(format!("Program {} has no status", program_id)))
+        })
+    }
+
+    fn get_program_status(&self, program_id: String) -> RpcResult<Option<ProgramStatus>> {
+        self.read(|node| Ok(node.program_status(&program_id).cloned()))
+    }
+
+    fn call_program(&self, program: String, inputs: Vec<u8>) -> RpcResult<ExecutionReceipt> {
+        self.read(|node| node.simulate_program(&program, inputs))
+    }
+
+    fn get_batch(&self, batch_number: u64) -> RpcResult<Option<StoredBatch>> {
+        self.read(|node| Ok(node.batch(batch_number).cloned()))
+    }
+
+    fn get_batch_header(&self, batch_number: u64) -> RpcResult<Option<BatchHeader>> {
+        self.read(|node| Ok(node.batch_header(batch_number).cloned()))
+    }
+
+    fn get_receipt(&self, transaction_hash: String) -> RpcResult<Option<Receipt>> {
+        self.read(|node| Ok(node.receipt(&transaction_hash).cloned()))
+    }
+
+    fn pending_transactions(&self) -> RpcResult<Vec<Transaction>> {
+        self.read(|node| Ok(node.get_pending_transactions().iter().cloned().collect()))
+    }
+
+    async fn subscribe_batches(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        let mut batches = self.batches.subscribe();
+        let sink = pending.accept().await?;
+        loop {
+            let header = tokio::select! {
+                _ = sink.closed() => return Ok(()),
+                header = batches.recv() => header,
+            };
+            match header {
+                Ok(header) => sink.send(SubscriptionMessage::from_json(&header)?).await?,
+                Err(RecvError::Lagged(skipped)) => warn!("Batch subscriber {:?} skipped {} headers", sink.subscription_id(), skipped),
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BridgeApiServer for BridgeRpc {
+    fn send_forced_transaction(&self, forced: ForcedTransaction, signature: Signature) -> RpcResult<Option<Receipt>> {
+        self.authorize("rollup_sendForcedTransaction", &forced, &signature)?;
+        self.rpc.write(|node| node.process_forced_transaction(forced))
+    }
+
+    fn submit_deposit(&self, deposit: Deposit, signature: Signature) -> RpcResult<bool> {
+        self.authorize("rollup_submitDeposit", &deposit, &signature)?;
+        self.rpc.write(|node| node.process_deposit(deposit))
+    }
+}
@@ -1,18 +1,75 @@
+use super::deposit::Deposit;
+use super::fees::FeeCharge;
+use super::forced::ForcedInclusion;
+use super::receipt::{self, Receipt, ReceiptStatus};
 use super::transaction::Transaction;
-use crate::bend::BendProgram;
+use super::withdrawal::{self, Withdrawal};
+use crate::bend::{BendCircuit, Event, ForcedInclusionCheck};
+use crate::bend::registry::{RegistryChange, SignedDeployment};
+use crate::zk_rollup::{Proof, StorageChanges};
+use ark_bn254::Fr;
+use ark_ff::PrimeField;
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Batch {
     transactions: Vec<Transaction>,
-    programs: Vec<BendProgram>,
+    programs: Vec<SignedDeployment>,
+    #[serde(default)]
+    deposits: Vec<Deposit>,
+    #[serde(default)]
+    withdrawals: Vec<Withdrawal>,
+    #[serde(default)]
+    fee_charges: Vec<FeeCharge>,
+    #[serde(default)]
+    registry_changes: Vec<RegistryChange>,
     timestamp: u64,
     batch_id: u64,
+    /// Position of the batch in the chain of applied batches.
+    #[serde(default)]
+    batch_number: u64,
+    #[serde(default)]
+    forced_inclusion: ForcedInclusion,
+    storage_changes: BTreeMap<String, StorageChanges>,
+    gas_price: u64,
+    /// Receipt of each executed transaction, in order.
+    receipts: Vec<Receipt>,
+}
+
+/// Summary of a batch that commits to its receipts and, once applied, to
+/// the state it leads to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchHeader {
+    pub batch_id: u64,
+    pub batch_number: u64,
+    pub timestamp: u64,
+    pub transaction_count: u32,
+    pub gas_price: u64,
+    pub gas_used: u64,
+    pub receipts_root: [u8; 32],
+    pub withdrawals_root: [u8; 32],
+    pub forced_queue_start: u64,
+    pub forced_count: u64,
+    /// Root of the state after the batch was applied. See
+    /// [`State::root`](crate::zk_rollup::State::root).
+    #[serde(default)]
+    pub state_root: [u8; 32],
 }
 
 impl Batch {
-    pub fn new(transactions: Vec<Transaction>, programs: Vec<BendProgram>) -> Self {
+    /// A batch that runs the forced transactions it includes before
+    /// `transactions`.
+    pub fn new(
+        batch_number: u64,
+        forced_inclusion: ForcedInclusion,
+        transactions: Vec<Transaction>,
+        programs: Vec<SignedDeployment>,
+        deposits: Vec<Deposit>,
+        withdrawals: Vec<Withdrawal>,
+        gas_price: u64,
+    ) -> Self {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -20,12 +77,25 @@ impl Batch {
         
         static BATCH_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
         let batch_id = BATCH_COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let transactions = forced_inclusion.transactions.iter()
+            .map(|forced| forced.transaction.clone())
+            .chain(transactions)
+            .collect();
 
         Self {
             transactions,
             programs,
+            deposits,
+            withdrawals,
+            fee_charges: Vec::new(),
+            registry_changes: Vec::new(),
             timestamp,
             batch_id,
+            batch_number,
+            forced_inclusion,
+            storage_changes: BTreeMap::new(),
+            gas_price,
+            receipts: Vec::new(),
         }
     }
 
@@ -33,10 +103,43 @@ impl Batch {
         &self.transactions
     }
 
-    pub fn programs(&self) -> &[BendProgram] {
+    /// Programs deployed when the batch's proof is applied.
+    pub fn programs(&self) -> &[SignedDeployment] {
         &self.programs
     }
 
+    /// L1 deposits credited when the batch's proof is applied.
+    pub fn deposits(&self) -> &[Deposit] {
+        &self.deposits
+    }
+
+    /// Withdrawals committed to in the batch's withdrawals tree.
+    pub fn withdrawals(&self) -> &[Withdrawal] {
+        &self.withdrawals
+    }
+
+    /// Adds fees charged outside of the batch's transactions, which are
+    /// debited when it is applied.
+    pub fn with_fee_charges(mut self, fee_charges: Vec<FeeCharge>) -> Self {
+        self.fee_charges = fee_charges;
+        self
+    }
+
+    pub fn fee_charges(&self) -> &[FeeCharge] {
+        &self.fee_charges
+    }
+
+    /// Adds signed ownership transfers and freezes, which are applied to the
+    /// registry when the batch is applied.
+    pub fn with_registry_changes(mut self, registry_changes: Vec<RegistryChange>) -> Self {
+        self.registry_changes = registry_changes;
+        self
+    }
+
+    pub fn registry_changes(&self) -> &[RegistryChange] {
+        &self.registry_changes
+    }
+
     pub fn timestamp(&self) -> u64 {
         self.timestamp
     }
@@ -44,4 +147,131 @@ impl Batch {
     pub fn batch_id(&self) -> u64 {
         self.batch_id
     }
-}
\ No newline at end of file
+
+    pub fn batch_number(&self) -> u64 {
+        self.batch_number
+    }
+
+    pub fn forced_inclusion(&self) -> &ForcedInclusion {
+        &self.forced_inclusion
+    }
+
+    /// Whether the transaction at `index` came from the forced inclusion
+    /// queue.
+    pub fn is_forced(&self, index: usize) -> bool {
+        index < self.forced_inclusion.transactions.len()
+    }
+
+    /// Circuit proving the executed batch under `forced_inclusion`, which
+    /// comes from the L1 queue rather than from the batch. See
+    /// [`ForcedQueue::check`](super::ForcedQueue::check).
+    pub fn circuit(&self, forced_inclusion: ForcedInclusionCheck) -> BendCircuit {
+        BendCircuit {
+            receipts_commitment: Fr::from_le_bytes_mod_order(&receipt::receipts_root(&self.receipts)),
+            forced_inclusion,
+        }
+    }
+
+    /// Public inputs of the batch's proof. See [`BendCircuit::public_inputs`].
+    pub fn public_inputs(&self, forced_inclusion: ForcedInclusionCheck) -> Vec<Fr> {
+        self.circuit(forced_inclusion).public_inputs()
+    }
+
+    /// The batch as it was created, before its transactions were executed.
+    pub fn unexecuted(&self) -> Self {
+        Self {
+            storage_changes: BTreeMap::new(),
+            receipts: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Storage writes made by the batch's transactions, keyed by program id.
+    pub fn storage_changes(&self) -> &BTreeMap<String, StorageChanges> {
+        &self.storage_changes
+    }
+
+    /// Records the writes of a transaction, on top of those made by earlier
+    /// transactions in the batch.
+    pub fn record_storage_changes(&mut self, program_id: &str, changes: StorageChanges) {
+        self.storage_changes.entry(program_id.to_string()).or_default().extend(changes);
+    }
+
+    /// Base fee in effect when the batch was created, paid per unit of gas.
+    pub fn gas_price(&self) -> u64 {
+        self.gas_price
+    }
+
+    /// Records the outcome of the next transaction, charging it for
+    /// `gas_used` gas at the batch's gas price unless it is forced.
+    pub fn record_receipt(&mut self, status: ReceiptStatus, gas_used: u64, return_data: Vec<u8>, events: Vec<Event>) -> &Receipt {
+        let index = self.receipts.len();
+        let gas_price = if self.is_forced(index) { 0 } else { self.gas_price };
+        let transaction_hash = self.transactions.get(index)
+            .map(Transaction::hash)
+            .unwrap_or_default();
+        self.receipts.push(Receipt {
+            transaction_hash,
+            status,
+            gas_used,
+            fee: gas_used.saturating_mul(gas_price),
+            return_data,
+            events,
+            batch_number: self.batch_number,
+            index: index as u32,
+        });
+        &self.receipts[index]
+    }
+
+    pub fn receipts(&self) -> &[Receipt] {
+        &self.receipts
+    }
+
+    pub fn total_fees(&self) -> u64 {
+        self.receipts.iter().fold(0, |total, receipt| total.saturating_add(receipt.fee))
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.receipts.iter().fold(0, |total, receipt| total.saturating_add(receipt.gas_used))
+    }
+
+    /// Header of the batch before it is applied, with a zero state root.
+    /// The sequencer keeps the headers of applied batches.
+    pub fn header(&self) -> BatchHeader {
+        BatchHeader {
+            batch_id: self.batch_id,
+            batch_number: self.batch_number,
+            timestamp: self.timestamp,
+            transaction_count: self.transactions.len() as u32,
+            gas_price: self.gas_price,
+            gas_used: self.gas_used(),
+            receipts_root: receipt::receipts_root(&self.receipts),
+            withdrawals_root: withdrawal::withdrawals_root(self.batch_number, &self.withdrawals),
+            forced_queue_start: self.forced_inclusion.queue_start,
+            forced_count: self.forced_inclusion.transactions.len() as u64,
+            state_root: [0u8; 32],
+        }
+    }
+}
+
+/// Record of a batch that was created but not applied, and of what became
+/// of its transactions.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchFailure {
+    pub batch_id: u64,
+    pub batch_number: u64,
+    pub reason: String,
+    /// Hashes of the transactions queued again for a later batch.
+    pub requeued: Vec<String>,
+    /// Hashes of the transactions that were in too many failed batches, and
+    /// of the later transactions of their senders, which were dropped.
+    pub rejected: Vec<String>,
+}
+
+/// An applied batch, kept with the proof it was applied with and its header.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredBatch {
+    pub header: BatchHeader,
+    pub batch: Batch,
+    pub proof: Proof,
+}
@@ -0,0 +1,22 @@
+use serde::{Serialize, Deserialize};
+
+/// Funds locked on the L1 for a rollup account.
+///
+/// Deposits are numbered in the order the L1 emitted them. The sequencer
+/// only accepts the next deposit in that order and state records how many
+/// have been credited, so a deposit that is forwarded again is ignored
+/// rather than credited twice.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub nonce: u64,
+    pub account: String,
+    pub amount: u64,
+    /// L1 block the deposit event was finalized in.
+    pub l1_block: u64,
+}
+
+impl Deposit {
+    pub fn new(nonce: u64, account: String, amount: u64, l1_block: u64) -> Self {
+        Self { nonce, account, amount, l1_block }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::config::FeeConfig;
+use serde::{Serialize, Deserialize};
+
+/// Gas price of the next batch.
+///
+/// The base fee follows EIP-1559: after each batch it moves towards the
+/// target by at most `1 / base_fee_change_denominator` of its value, up when
+/// the batch used more gas than the target and down when it used less, and
+/// never below the configured minimum. Every transaction in a batch pays the
+/// base fee for each unit of gas it uses.
+#[derive(Clone, Debug)]
+pub struct FeeMarket {
+    base_fee: u64,
+    config: FeeConfig,
+}
+
+impl FeeMarket {
+    pub fn new(config: FeeConfig) -> Self {
+        Self { base_fee: config.initial_base_fee.max(config.min_base_fee), config }
+    }
+
+    /// The fee market after a batch that was created at `gas_price` and used
+    /// `gas_used` gas, for a node that starts from that batch.
+    pub fn resume(config: FeeConfig, gas_price: u64, gas_used: u64) -> Self {
+        let mut market = Self { base_fee: gas_price.max(config.min_base_fee), config };
+        market.update(gas_used);
+        market
+    }
+
+    pub fn base_fee(&self) -> u64 {
+        self.base_fee
+    }
+
+    pub fn fee_account(&self) -> &str {
+        &self.config.fee_account
+    }
+
+    /// Adjusts the base fee after a batch that used `gas_used` gas.
+    pub fn update(&mut self, gas_used: u64) {
+        let target = self.config.target_batch_gas.max(1) as u128;
+        let denominator = self.config.base_fee_change_denominator.max(1) as u128;
+        let base_fee = self.base_fee as u128;
+        let gas_used = gas_used as u128;
+        let base_fee = if gas_used > target {
+            let delta = (base_fee * (gas_used - target) / target / denominator).max(1);
+            base_fee.saturating_add(delta).min(u64::MAX as u128)
+        } else {
+            base_fee - base_fee * (target - gas_used) / target / denominator
+        };
+        self.base_fee = (base_fee as u64).max(self.config.min_base_fee);
+    }
+}
+
+/// Gas a forced transaction runs with. Forced transactions pay for
+/// inclusion on the L1 and are not charged on the rollup, so their fee limit
+/// does not bound their execution.
+pub const FORCED_TRANSACTION_GAS: u64 = 1_000_000;
+
+/// Gas an execution may use at `gas_price`: as much as `fee_limit` pays for,
+/// up to the execution gas limit.
+pub fn gas_limit(fee_limit: u64, gas_price: u64, max_gas: u64) -> u64 {
+    match fee_limit.checked_div(gas_price) {
+        Some(gas) => gas.min(max_gas),
+        None => max_gas,
+    }
+}
+
+/// Most an execution with `fee_limit` can be charged at `gas_price`.
+pub fn max_fee(fee_limit: u64, gas_price: u64, max_gas: u64) -> u64 {
+    gas_limit(fee_limit, gas_price, max_gas).saturating_mul(gas_price)
+}
+
+/// Fee charged to an account outside of a transaction, such as for a direct
+/// program execution. It is reserved right away and debited with the next
+/// batch, so that the state only changes when batches are applied.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeCharge {
+    pub account: String,
+    pub amount: u64,
+}
@@ -0,0 +1,95 @@
+use super::batch::Batch;
+use super::transaction::Transaction;
+use crate::bend::ForcedInclusionCheck;
+use crate::error::HVMError;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+/// Transaction a user posted to the L1 forced inclusion queue.
+///
+/// The sequencer has to include it in one of the
+/// `forced_inclusion_batches` batches starting with batch number
+/// `queued_at_batch`, which is the number of batches the L1 had accepted
+/// when it was queued. Forced transactions pay for inclusion on the L1 and
+/// are not charged fees on the rollup.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForcedTransaction {
+    /// Position in the L1 queue.
+    pub index: u64,
+    pub queued_at_batch: u64,
+    pub l1_block: u64,
+    pub transaction: Transaction,
+}
+
+impl ForcedTransaction {
+    pub fn new(index: u64, queued_at_batch: u64, l1_block: u64, transaction: Transaction) -> Self {
+        Self { index, queued_at_batch, l1_block, transaction }
+    }
+
+    /// Last batch number the transaction can be included in.
+    pub fn deadline(&self, forced_inclusion_batches: u64) -> u64 {
+        (self.queued_at_batch + forced_inclusion_batches.max(1)) - 1
+    }
+}
+
+/// Part of the forced inclusion queue a batch consumed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForcedInclusion {
+    /// Queue index of the first forced transaction in the batch, or of the
+    /// next one to be queued if the batch has none.
+    pub queue_start: u64,
+    /// Forced transactions included, in queue order.
+    pub transactions: Vec<ForcedTransaction>,
+}
+
+/// The L1 forced inclusion queue as the node read it, kept apart from the
+/// batches built from it.
+///
+/// The L1 only accepts a batch proof whose forced inclusion inputs it can
+/// derive from its own queue, so the node checks every batch against this
+/// record rather than against what the batch says about itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForcedQueue {
+    transactions: BTreeMap<u64, ForcedTransaction>,
+}
+
+impl ForcedQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a transaction read from the L1 queue.
+    pub fn push(&mut self, forced: ForcedTransaction) {
+        self.transactions.insert(forced.index, forced);
+    }
+
+    pub fn get(&self, index: u64) -> Option<&ForcedTransaction> {
+        self.transactions.get(&index)
+    }
+
+    /// Forced inclusion inputs of `batch`'s proof, as the L1 computes them.
+    ///
+    /// The batch has to pick up the queue at `queue_index`, the position the
+    /// last applied batch left it at, and include exactly the transactions
+    /// queued from there on. The next deadline is that of the first queued
+    /// transaction the batch leaves out.
+    pub fn check(&self, batch: &Batch, queue_index: u64, forced_inclusion_batches: u64) -> Result<ForcedInclusionCheck, HVMError> {
+        let inclusion = batch.forced_inclusion();
+        if inclusion.queue_start != queue_index {
+            return Err(HVMError::Sequencer(format!(
+                "Batch {} starts the forced inclusion queue at {}, expected {}", batch.batch_number(), inclusion.queue_start, queue_index
+            )));
+        }
+        for (index, forced) in (queue_index..).zip(&inclusion.transactions) {
+            if self.get(index) != Some(forced) {
+                return Err(HVMError::Sequencer(format!(
+                    "Batch {} includes a forced transaction {} that is not in the L1 queue", batch.batch_number(), index
+                )));
+            }
+        }
+        let queue_end = queue_index + inclusion.transactions.len() as u64;
+        let next_deadline = self.get(queue_end)
+            .map_or(batch.batch_number() + 1, |forced| forced.deadline(forced_inclusion_batches));
+        Ok(ForcedInclusionCheck { batch_number: batch.batch_number(), next_deadline })
+    }
+}
@@ -1,66 +1,312 @@
 use crate::error::HVMError;
-use crate::zk_rollup::{Proof, State};
-use crate::config::SequencerConfig;
-use crate::bend::BendProgram;
+use crate::zk_rollup::{self, Proof, SnapshotManifest, State, StateHistory};
+use crate::config::{ExecutionConfig, FeeConfig, HistoryConfig, SequencerConfig};
+use crate::bend::{BendProgram, CallContext, ExecutionResult, ForcedInclusionCheck, ProgramRegistry};
+use crate::bend::registry::{RegistryChange, SignedDeployment};
+use crate::prover::execute_batch;
+use crate::crypto::{Signature, VerifyingKey};
 use std::time::{Duration, Instant};
-use std::collections::{VecDeque, HashMap};
-use ark_serialize::CanonicalSerialize;
+use std::collections::{BTreeMap, VecDeque, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use log::{error, warn};
 
 pub mod batch;
+pub mod deposit;
+pub mod fees;
+pub mod forced;
+pub mod program_status;
+pub mod receipt;
 pub mod transaction;
+pub mod withdrawal;
 
-pub use batch::Batch;
-pub use transaction::Transaction;
+pub use batch::{Batch, BatchFailure, BatchHeader, StoredBatch};
+pub use deposit::Deposit;
+pub use fees::{FeeCharge, FeeMarket};
+pub use forced::{ForcedInclusion, ForcedQueue, ForcedTransaction};
+pub use program_status::ProgramStatus;
+pub use receipt::{ExecutionReceipt, Receipt, ReceiptStatus};
+pub use transaction::{Transaction, TransactionSignature};
+pub use withdrawal::{Withdrawal, WithdrawalProof};
 
 pub struct Sequencer {
     state: State,
     pending_transactions: VecDeque<Transaction>,
+    /// Nonce of the next transaction to accept from each account, counting
+    /// those queued or in unapplied batches.
+    next_nonces: HashMap<String, u64>,
     processed_transactions: Vec<Transaction>,
-    pending_programs: VecDeque<BendProgram>,
+    pending_programs: VecDeque<SignedDeployment>,
+    pending_deposits: VecDeque<Deposit>,
+    /// Nonce of the next deposit to accept, counting those in unapplied
+    /// batches.
+    next_deposit_nonce: u64,
+    pending_withdrawals: VecDeque<Withdrawal>,
+    /// Nonce of the next withdrawal to accept from each account, counting
+    /// those queued or in unapplied batches.
+    next_withdrawal_nonces: HashMap<String, u64>,
+    pending_fee_charges: VecDeque<FeeCharge>,
+    /// Funds held back from each account for queued transactions,
+    /// withdrawals and fee charges. The state itself only changes when a
+    /// batch is applied.
+    reserved: HashMap<String, u64>,
+    pending_forced: VecDeque<ForcedTransaction>,
+    /// Every forced transaction read from the L1, which batches are checked
+    /// against.
+    forced_queue: ForcedQueue,
+    /// Queue index of the next forced transaction to accept.
+    next_forced_index: u64,
+    /// Withdrawals of applied batches, by batch number.
+    applied_withdrawals: HashMap<u64, Vec<Withdrawal>>,
+    /// Batch number and index of each applied withdrawal, by withdrawal
+    /// hash.
+    withdrawal_locations: HashMap<String, (u64, u32)>,
     processed_programs: Vec<BendProgram>,
-    deployed_programs: HashMap<String, BendProgram>,
+    /// Statuses of programs that are not in an applied batch yet. Those of
+    /// applied batches are in the state.
+    program_statuses: HashMap<String, ProgramStatus>,
+    /// Ownership transfers and freezes waiting for a batch.
+    pending_registry_changes: VecDeque<RegistryChange>,
+    /// Names with a deployment or registry change that is checked but not
+    /// yet applied.
+    pending_program_names: HashSet<String>,
+    /// Receipts of applied batches, by transaction hash.
+    receipts: HashMap<String, Receipt>,
+    /// State before the batch that was last created, to roll back to if it
+    /// is not applied.
+    pre_batch_state: Option<(u64, State)>,
+    /// Failed batches each pending transaction was in, by transaction hash.
+    failed_attempts: HashMap<String, u32>,
+    batch_failures: Vec<BatchFailure>,
+    /// Headers of applied batches, by batch number.
+    headers: BTreeMap<u64, BatchHeader>,
+    /// Applied batches with their proofs, by batch number.
+    applied_batches: BTreeMap<u64, StoredBatch>,
+    history: StateHistory,
+    fee_market: FeeMarket,
     config: SequencerConfig,
     last_batch_time: Instant,
 }
 
 impl Sequencer {
     pub fn new(initial_state: State, config: SequencerConfig) -> Self {
+        Self::with_fee_config(initial_state, config, FeeConfig::default())
+    }
+
+    pub fn with_fee_config(initial_state: State, config: SequencerConfig, fee_config: FeeConfig) -> Self {
         Self {
+            next_deposit_nonce: initial_state.deposit_nonce(),
+            next_forced_index: initial_state.forced_queue_index(),
             state: initial_state,
             pending_transactions: VecDeque::new(),
+            next_nonces: HashMap::new(),
             processed_transactions: Vec::new(),
             pending_programs: VecDeque::new(),
+            pending_deposits: VecDeque::new(),
+            pending_withdrawals: VecDeque::new(),
+            next_withdrawal_nonces: HashMap::new(),
+            pending_fee_charges: VecDeque::new(),
+            reserved: HashMap::new(),
+            pending_forced: VecDeque::new(),
+            forced_queue: ForcedQueue::new(),
+            applied_withdrawals: HashMap::new(),
+            withdrawal_locations: HashMap::new(),
             processed_programs: Vec::new(),
-            deployed_programs: HashMap::new(),
+            program_statuses: HashMap::new(),
+            pending_registry_changes: VecDeque::new(),
+            pending_program_names: HashSet::new(),
+            receipts: HashMap::new(),
+            pre_batch_state: None,
+            failed_attempts: HashMap::new(),
+            batch_failures: Vec::new(),
+            headers: BTreeMap::new(),
+            applied_batches: BTreeMap::new(),
+            history: StateHistory::default(),
+            fee_market: FeeMarket::new(fee_config),
             config,
             last_batch_time: Instant::now(),
         }
     }
 
+    /// Keeps the states after past batches according to `config` instead of
+    /// the default retention policy.
+    pub fn with_history(mut self, config: HistoryConfig) -> Self {
+        self.history.set_config(config);
+        self
+    }
+
+    /// Queues a transaction for the next batch. It must be signed by its
+    /// sender and carry the sender's [next nonce](Self::next_nonce).
     pub fn process_transaction(&mut self, transaction: Transaction) -> Result<(), HVMError> {
+        transaction.verify_signature()?;
+        let next_nonce = self.next_nonce(&transaction.sender);
+        if transaction.nonce != next_nonce {
+            return Err(HVMError::Sequencer(format!(
+                "Nonce {} of {} is not the next one, expected {}", transaction.nonce, transaction.sender, next_nonce
+            )));
+        }
         if self.pending_transactions.len() >= self.config.max_pending_transactions {
             return Err(HVMError::Sequencer("Max pending transactions reached".to_string()));
         }
+        if transaction.fee_limit < self.fee_market.base_fee() {
+            return Err(HVMError::Sequencer(format!(
+                "Fee limit {} is below the base fee of {}", transaction.fee_limit, self.fee_market.base_fee()
+            )));
+        }
+        self.next_nonces.insert(transaction.sender.clone(), next_nonce + 1);
         self.pending_transactions.push_back(transaction);
         Ok(())
     }
 
-    pub fn submit_program(&mut self, program: BendProgram) -> Result<(), HVMError> {
+    /// Nonce the next transaction of `account` has to have, counting those
+    /// already queued.
+    pub fn next_nonce(&self, account: &str) -> u64 {
+        self.next_nonces.get(account).copied()
+            .unwrap_or_else(|| self.state.account_nonce(account))
+    }
+
+    /// Queues an L1 deposit for the next batch, which must include it.
+    ///
+    /// Deposits are accepted strictly in nonce order. One that was already
+    /// accepted is ignored and `false` is returned, so deposits can safely be
+    /// forwarded again; one that skips ahead is an error.
+    pub fn submit_deposit(&mut self, deposit: Deposit) -> Result<bool, HVMError> {
+        if deposit.nonce < self.next_deposit_nonce {
+            return Ok(false);
+        }
+        if deposit.nonce > self.next_deposit_nonce {
+            return Err(HVMError::Sequencer(format!(
+                "Deposit {} is ahead of the next expected deposit {}", deposit.nonce, self.next_deposit_nonce
+            )));
+        }
+        self.next_deposit_nonce += 1;
+        self.pending_deposits.push_back(deposit);
+        Ok(true)
+    }
+
+    /// Queues a transaction from the L1 forced inclusion queue.
+    ///
+    /// Like deposits, forced transactions are accepted strictly in queue
+    /// order and one that was already accepted is ignored with `false`.
+    /// Fee limits are not checked, as forced transactions are not charged.
+    pub fn submit_forced_transaction(&mut self, forced: ForcedTransaction) -> Result<bool, HVMError> {
+        if forced.index < self.next_forced_index {
+            return Ok(false);
+        }
+        if forced.index > self.next_forced_index {
+            return Err(HVMError::Sequencer(format!(
+                "Forced transaction {} is ahead of the next expected index {}", forced.index, self.next_forced_index
+            )));
+        }
+        self.next_forced_index += 1;
+        self.forced_queue.push(forced.clone());
+        self.pending_forced.push_back(forced);
+        Ok(true)
+    }
+
+    /// Reserves the amount of a withdrawal from its account and queues it for
+    /// the next batch, which debits it. It must be signed by the account's
+    /// owner and carry the account's [next withdrawal
+    /// nonce](Self::next_withdrawal_nonce).
+    pub fn submit_withdrawal(&mut self, withdrawal: Withdrawal) -> Result<(), HVMError> {
+        withdrawal.verify_signature()?;
+        let next_nonce = self.next_withdrawal_nonce(&withdrawal.account);
+        if withdrawal.nonce != next_nonce {
+            return Err(HVMError::Sequencer(format!(
+                "Withdrawal nonce {} of {} is not the next one, expected {}", withdrawal.nonce, withdrawal.account, next_nonce
+            )));
+        }
+        if withdrawal.amount == 0 {
+            return Err(HVMError::Sequencer("Withdrawal amount must not be zero".to_string()));
+        }
+        self.reserve_funds(&withdrawal.account, withdrawal.amount)?;
+        self.next_withdrawal_nonces.insert(withdrawal.account.clone(), next_nonce + 1);
+        self.pending_withdrawals.push_back(withdrawal);
+        Ok(())
+    }
+
+    /// Nonce the next withdrawal from `account` has to have, counting those
+    /// already queued.
+    pub fn next_withdrawal_nonce(&self, account: &str) -> u64 {
+        self.next_withdrawal_nonces.get(account).copied()
+            .unwrap_or_else(|| self.state.withdrawal_nonce(account))
+    }
+
+    /// Admits a signed program for deployment in a later batch.
+    ///
+    /// The program is validated against `execution_config` and checked
+    /// against the registry before it is queued. Only one deployment or
+    /// registry change per program name can be pending at a time, since each
+    /// is signed over the registry nonce it will be applied at.
+    pub fn submit_program(&mut self, deployment: SignedDeployment, execution_config: &ExecutionConfig) -> Result<(), HVMError> {
         if self.pending_programs.len() >= self.config.max_pending_programs {
             return Err(HVMError::Sequencer("Max pending programs reached".to_string()));
         }
-        self.pending_programs.push_back(program);
+        let program_id = deployment.program.id().to_string();
+        if matches!(self.program_status(&program_id), Some(status) if !matches!(status, ProgramStatus::Rejected { .. })) {
+            return Err(HVMError::Sequencer(format!("Program already submitted: {}", program_id)));
+        }
+        let name = deployment.program.metadata.name.clone();
+        if self.pending_program_names.contains(&name) {
+            return Err(HVMError::Sequencer(format!("A deployment of program {} is already pending", name)));
+        }
+
+        self.program_statuses.insert(program_id.clone(), ProgramStatus::Submitted);
+        let checked = deployment.program.validate(execution_config)
+            .and_then(|_| self.state.registry.check(&deployment));
+        if let Err(e) = checked {
+            self.program_statuses.insert(program_id, ProgramStatus::Rejected { reason: e.to_string() });
+            return Err(e);
+        }
+
+        self.program_statuses.insert(program_id, ProgramStatus::Validated);
+        self.pending_program_names.insert(name);
+        self.pending_programs.push_back(deployment);
         Ok(())
     }
 
-    pub fn deploy_program(&mut self, program: BendProgram) -> Result<(), HVMError> {
-        let program_id = program.id().to_string();
-        self.deployed_programs.insert(program_id, program);
+    /// Queues a signed ownership transfer or freeze for the next batch. It
+    /// is checked against the registry now and takes effect when that batch
+    /// is applied.
+    pub fn submit_registry_change(&mut self, change: RegistryChange) -> Result<(), HVMError> {
+        let name = change.name().to_string();
+        if self.pending_program_names.contains(&name) {
+            return Err(HVMError::Sequencer(format!("A change to program {} is already pending", name)));
+        }
+        change.apply(&mut self.state.registry.clone())?;
+        self.pending_program_names.insert(name);
+        self.pending_registry_changes.push_back(change);
         Ok(())
     }
 
+    pub fn transfer_program_ownership(&mut self, name: &str, new_owner: VerifyingKey, signature: &Signature) -> Result<(), HVMError> {
+        self.submit_registry_change(RegistryChange::Transfer { name: name.to_string(), new_owner, signature: *signature })
+    }
+
+    pub fn freeze_program(&mut self, name: &str, signature: &Signature) -> Result<(), HVMError> {
+        self.submit_registry_change(RegistryChange::Freeze { name: name.to_string(), signature: *signature })
+    }
+
+    /// Resolves a program id or a `name@requirement` reference to a deployed
+    /// program.
+    pub fn resolve_program(&self, reference: &str) -> Result<&BendProgram, HVMError> {
+        if let Some(program) = self.state.programs.get(reference) {
+            return Ok(program);
+        }
+        let version = self.state.registry.resolve_reference(reference)?;
+        self.state.programs.get(&version.program_id)
+            .ok_or_else(|| HVMError::Sequencer(format!("Program not found: {}", version.program_id)))
+    }
+
+    /// The registry as of the last applied batch.
+    pub fn registry(&self) -> &ProgramRegistry {
+        &self.state.registry
+    }
+
     pub fn create_batch(&mut self, force: bool) -> Result<Option<Batch>, HVMError> {
-        if self.pending_transactions.is_empty() && self.pending_programs.is_empty() {
+        if self.pending_transactions.is_empty() && self.pending_programs.is_empty()
+            && self.pending_deposits.is_empty() && self.pending_withdrawals.is_empty() && self.pending_forced.is_empty()
+            && self.pending_fee_charges.is_empty() && self.pending_registry_changes.is_empty() {
             return Ok(None);
         }
     
@@ -69,13 +315,14 @@ impl Sequencer {
             return Ok(None);
         }
     
+        let forced_inclusion = self.take_forced_transactions();
         let mut batch_transactions = Vec::new();
         let mut batch_programs = Vec::new();
 
-        while let Some(tx) = self.pending_transactions.pop_front() {
-            batch_transactions.push(tx);
-            if batch_transactions.len() >= self.config.max_batch_size {
-                break;
+        while batch_transactions.len() + forced_inclusion.transactions.len() < self.config.max_batch_size {
+            match self.pending_transactions.pop_front() {
+                Some(tx) => batch_transactions.push(tx),
+                None => break,
             }
         }
 
@@ -86,37 +333,476 @@ impl Sequencer {
             }
         }
     
-        let batch = Batch::new(batch_transactions, batch_programs);
+        let batch_deposits = self.pending_deposits.drain(..).collect();
+        let batch_withdrawals = self.pending_withdrawals.drain(..).collect();
+        let batch = Batch::new(
+            self.state.nonce(),
+            forced_inclusion,
+            batch_transactions,
+            batch_programs,
+            batch_deposits,
+            batch_withdrawals,
+            self.fee_market.base_fee(),
+        ).with_fee_charges(self.pending_fee_charges.drain(..).collect())
+            .with_registry_changes(self.pending_registry_changes.drain(..).collect());
+        for deployment in batch.programs() {
+            self.program_statuses.insert(deployment.program.id().to_string(), ProgramStatus::Included { batch_number: batch.batch_number() });
+        }
+        self.pre_batch_state = Some((batch.batch_id(), self.state.clone()));
         self.last_batch_time = now;
         Ok(Some(batch))
     }
 
+    /// Forced transactions for the next batch: as many as fit, and every one
+    /// that would be overdue after it regardless of the batch size.
+    fn take_forced_transactions(&mut self) -> ForcedInclusion {
+        let batch_number = self.state.nonce();
+        let inclusion_batches = self.config.forced_inclusion_batches;
+        let queue_start = self.pending_forced.front().map_or(self.next_forced_index, |forced| forced.index);
+        let due = self.pending_forced.iter()
+            .take_while(|forced| forced.deadline(inclusion_batches) <= batch_number)
+            .count();
+        let count = due.max(self.pending_forced.len().min(self.config.max_batch_size));
+        let transactions = self.pending_forced.drain(..count).collect();
+        ForcedInclusion { queue_start, transactions }
+    }
+
+    /// The L1 forced inclusion queue as read by this sequencer.
+    pub fn forced_queue(&self) -> &ForcedQueue {
+        &self.forced_queue
+    }
+
+    /// Checks batches against `forced_queue` rather than against the forced
+    /// transactions submitted to this sequencer, such as for one that audits
+    /// another node.
+    pub fn with_forced_queue(mut self, forced_queue: ForcedQueue) -> Self {
+        self.forced_queue = forced_queue;
+        self
+    }
+
+    /// Forced inclusion inputs of the proof of `batch`, the next batch to
+    /// apply. See [`ForcedQueue::check`].
+    pub fn forced_inclusion_check(&self, batch: &Batch) -> Result<ForcedInclusionCheck, HVMError> {
+        self.forced_queue.check(batch, self.state.forced_queue_index(), self.config.forced_inclusion_batches)
+    }
+
     pub fn apply_proof(&mut self, proof: Proof, batch: &Batch) -> Result<(), HVMError> {
         println!("Applying proof in sequencer: {:?}", proof);
-        let result = self.state.apply_proof(&proof);
+        let state = self.transition(&proof, batch)?;
+        self.commit(state, batch, proof);
         println!("State after applying proof: {:?}", self.state);
-        
+        Ok(())
+    }
+
+    /// Applies a batch produced elsewhere, such as by the node a snapshot
+    /// came from. Its proof is not checked, so its transactions are executed
+    /// again under `execution_config`, which has to be the producer's, and
+    /// must give the receipts and storage writes it carries. It also has to
+    /// follow the last applied batch and lead to the state root in its
+    /// header.
+    pub fn replay_batch(&mut self, proof: Proof, batch: &Batch, header: &BatchHeader, execution_config: &ExecutionConfig) -> Result<(), HVMError> {
+        let mut executed = batch.unexecuted();
+        execute_batch(self.deployed_programs(), &mut executed, &self.state, execution_config);
+        if executed.receipts() != batch.receipts() || executed.storage_changes() != batch.storage_changes() {
+            return Err(HVMError::Sequencer(format!(
+                "Batch {} does not match the result of executing it", batch.batch_number()
+            )));
+        }
+        self.apply_replayed(proof, batch, header)
+    }
+
+    /// Applies a batch that was already executed again and checked against
+    /// its receipts and storage writes, as by an
+    /// [`Auditor`](crate::verifier::Auditor).
+    pub(crate) fn apply_replayed(&mut self, proof: Proof, batch: &Batch, header: &BatchHeader) -> Result<(), HVMError> {
+        if batch.batch_number() != self.state.nonce() {
+            return Err(HVMError::Sequencer(format!(
+                "Batch {} does not follow the last applied batch, expected batch {}", batch.batch_number(), self.state.nonce()
+            )));
+        }
+        let expected = BatchHeader { state_root: header.state_root, ..batch.header() };
+        if expected != *header {
+            return Err(HVMError::Sequencer(format!("Header does not match batch {}", batch.batch_number())));
+        }
+        let state = self.transition(&proof, batch)?;
+        if state.root() != header.state_root {
+            return Err(HVMError::Sequencer(format!(
+                "Batch {} does not lead to the state root in its header", batch.batch_number()
+            )));
+        }
+        self.commit(state, batch, proof);
+        Ok(())
+    }
+
+    /// State after `batch`, computed on a copy so that a batch that cannot
+    /// be applied leaves the current state untouched. This is the only way
+    /// the state changes, so replaying the same batches leads to the same
+    /// state.
+    fn transition(&self, proof: &Proof, batch: &Batch) -> Result<State, HVMError> {
+        let mut state = self.state.clone();
+        state.apply_proof(proof)?;
+        for deposit in batch.deposits() {
+            state.credit_deposit(deposit);
+        }
+        let forced = batch.forced_inclusion();
+        state.forced_queue_index = forced.queue_start + forced.transactions.len() as u64;
+        for withdrawal in batch.withdrawals() {
+            withdrawal.verify_signature()?;
+            state.use_withdrawal_nonce(&withdrawal.account, withdrawal.nonce)?;
+            state.debit(&withdrawal.account, withdrawal.amount)?;
+        }
+        for (program_id, changes) in batch.storage_changes() {
+            state.apply_storage_changes(program_id, changes);
+        }
+        // Forced transactions are ordered by the L1 queue instead, so they
+        // neither need nor use up a nonce.
+        for (index, tx) in batch.transactions().iter().enumerate() {
+            if !batch.is_forced(index) {
+                tx.verify_signature()?;
+                state.use_nonce(&tx.sender, tx.nonce)?;
+            }
+        }
+        for (receipt, tx) in batch.receipts().iter().zip(batch.transactions()) {
+            state.debit(&tx.sender, receipt.fee)?;
+        }
+        let mut fees = batch.total_fees();
+        for charge in batch.fee_charges() {
+            state.debit(&charge.account, charge.amount)?;
+            fees = fees.saturating_add(charge.amount);
+        }
+        state.credit(self.fee_market.fee_account(), fees);
+        for deployment in batch.programs() {
+            let program = &deployment.program;
+            let status = match state.registry.register(deployment) {
+                Ok(_) => {
+                    state.programs.insert(program.id().to_string(), program.clone());
+                    ProgramStatus::Deployed { batch_number: batch.batch_number() }
+                }
+                Err(e) => ProgramStatus::Rejected { reason: e.to_string() },
+            };
+            state.program_statuses.insert(program.id().to_string(), status);
+        }
+        for change in batch.registry_changes() {
+            // Changes are checked when submitted and only one per name is
+            // pending, so one that no longer applies was superseded.
+            if let Err(e) = change.apply(&mut state.registry) {
+                warn!("Skipping registry change to {}: {}", change.name(), e);
+            }
+        }
+        Ok(state)
+    }
+
+    fn commit(&mut self, state: State, batch: &Batch, proof: Proof) {
+        self.history.record(batch.batch_number(), &self.state, &state);
+        self.state = state;
+        self.pre_batch_state = None;
+        self.next_deposit_nonce = self.next_deposit_nonce.max(self.state.deposit_nonce());
+        self.next_forced_index = self.next_forced_index.max(self.state.forced_queue_index());
+        for withdrawal in batch.withdrawals() {
+            self.release_funds(&withdrawal.account, withdrawal.amount);
+        }
+        for charge in batch.fee_charges() {
+            self.release_funds(&charge.account, charge.amount);
+        }
+        if !batch.withdrawals().is_empty() {
+            for (index, withdrawal) in batch.withdrawals().iter().enumerate() {
+                self.withdrawal_locations.insert(withdrawal.hash(), (batch.batch_number(), index as u32));
+            }
+            self.applied_withdrawals.insert(batch.batch_number(), batch.withdrawals().to_vec());
+        }
+        for deployment in batch.programs() {
+            let program = &deployment.program;
+            self.pending_program_names.remove(&program.metadata.name);
+            self.program_statuses.remove(program.id());
+            if matches!(self.state.program_statuses.get(program.id()), Some(ProgramStatus::Deployed { .. })) {
+                self.processed_programs.push(program.clone());
+            }
+        }
+        for change in batch.registry_changes() {
+            self.pending_program_names.remove(change.name());
+        }
+        self.fee_market.update(batch.gas_used());
+        for receipt in batch.receipts() {
+            self.failed_attempts.remove(&receipt.transaction_hash);
+            self.receipts.insert(receipt.transaction_hash.clone(), receipt.clone());
+        }
         for tx in batch.transactions() {
             self.processed_transactions.push(tx.clone());
         }
-        
-        result
+        let header = BatchHeader { state_root: self.state.root(), ..batch.header() };
+        self.headers.insert(header.batch_number, header.clone());
+        self.applied_batches.insert(header.batch_number, StoredBatch { header, batch: batch.clone(), proof });
     }
 
-    pub fn execute_program(&self, program_id: &str, inputs: Vec<u8>) -> Result<Vec<u8>, HVMError> {
-        let program = self.deployed_programs.get(program_id)
+    /// Batches applied by this sequencer, in order. A sequencer started from
+    /// a snapshot only has the batches applied after it.
+    pub fn applied_batches(&self) -> impl Iterator<Item = &StoredBatch> {
+        self.applied_batches.values()
+    }
+
+    pub fn applied_batch(&self, batch_number: u64) -> Option<&StoredBatch> {
+        self.applied_batches.get(&batch_number)
+    }
+
+    /// State right after batch `batch_number` was applied, if it was not
+    /// pruned. See [`HistoryConfig`].
+    pub fn state_at(&self, batch_number: u64) -> Result<State, HVMError> {
+        self.history.state_at(batch_number)
+    }
+
+    pub fn history(&self) -> &StateHistory {
+        &self.history
+    }
+
+    /// Header of an applied batch, with the state root it led to.
+    pub fn batch_header(&self, batch_number: u64) -> Option<&BatchHeader> {
+        self.headers.get(&batch_number)
+    }
+
+    /// Writes the current state to a snapshot file. See
+    /// [`write_snapshot`](zk_rollup::write_snapshot).
+    pub fn export_snapshot(&self, path: &Path, entries_per_chunk: usize) -> Result<SnapshotManifest, HVMError> {
+        zk_rollup::write_snapshot(&self.state, path, entries_per_chunk)
+    }
+
+    /// A sequencer that starts from a snapshot of the state after the batch
+    /// with `header`, from which later batches can be replayed.
+    pub fn from_snapshot(path: &Path, header: &BatchHeader, config: SequencerConfig, fee_config: FeeConfig) -> Result<Self, HVMError> {
+        let (manifest, state) = zk_rollup::read_snapshot(path)?;
+        if manifest.state_root != header.state_root || manifest.batch_count != header.batch_number + 1 {
+            return Err(HVMError::Sequencer(format!(
+                "Snapshot does not match the header of batch {}", header.batch_number
+            )));
+        }
+        let mut sequencer = Self::with_fee_config(state, config, fee_config.clone());
+        sequencer.fee_market = FeeMarket::resume(fee_config, header.gas_price, header.gas_used);
+        sequencer.history.record(header.batch_number, &sequencer.state, &sequencer.state);
+        sequencer.headers.insert(header.batch_number, header.clone());
+        Ok(sequencer)
+    }
+
+    /// Rolls back a batch that could not be proven, verified or applied.
+    ///
+    /// The state is restored to what it was when the batch was created, and
+    /// everything the batch took from the queues is put back. Forced
+    /// transactions are always requeued; other transactions are requeued
+    /// until they have been in `max_batch_attempts` failed batches, after
+    /// which they are rejected with a receipt giving `reason`.
+    pub fn revert_batch(&mut self, batch: &Batch, reason: &str) -> &BatchFailure {
+        match self.pre_batch_state.take() {
+            Some((batch_id, state)) if batch_id == batch.batch_id() => self.state = state,
+            snapshot => self.pre_batch_state = snapshot,
+        }
+        self.requeue_batch(batch);
+
+        let mut requeued = Vec::new();
+        let mut rejected = Vec::new();
+        let mut rejected_nonces = BTreeMap::new();
+        for (index, tx) in batch.transactions().iter().enumerate().rev() {
+            let hash = tx.hash();
+            if batch.is_forced(index) {
+                requeued.push(hash);
+                continue;
+            }
+            let attempts = self.failed_attempts.entry(hash.clone()).or_insert(0);
+            *attempts += 1;
+            if *attempts < self.config.max_batch_attempts {
+                self.pending_transactions.push_front(tx.clone());
+                requeued.push(hash);
+                continue;
+            }
+            self.failed_attempts.remove(&hash);
+            rejected_nonces.insert(tx.sender.clone(), tx.nonce);
+            self.receipts.insert(hash.clone(), Receipt {
+                transaction_hash: hash.clone(),
+                status: ReceiptStatus::Rejected { reason: reason.to_string() },
+                gas_used: 0,
+                fee: 0,
+                return_data: Vec::new(),
+                events: Vec::new(),
+                batch_number: batch.batch_number(),
+                index: index as u32,
+            });
+            rejected.push(hash);
+        }
+        requeued.reverse();
+        rejected.reverse();
+        // Later transactions of a sender whose transaction was rejected could
+        // never be applied in nonce order, so they are dropped with it.
+        for (sender, nonce) in rejected_nonces {
+            let (dropped, kept): (VecDeque<Transaction>, VecDeque<Transaction>) = std::mem::take(&mut self.pending_transactions)
+                .into_iter()
+                .partition(|tx| tx.sender == sender && tx.nonce > nonce);
+            self.pending_transactions = kept;
+            for tx in dropped {
+                let hash = tx.hash();
+                self.failed_attempts.remove(&hash);
+                requeued.retain(|requeued| *requeued != hash);
+                rejected.push(hash);
+            }
+            self.next_nonces.insert(sender, nonce);
+        }
+
+        error!(
+            "Batch {} (number {}) was reverted: {}. Requeued {} transactions, rejected {}",
+            batch.batch_id(), batch.batch_number(), reason, requeued.len(), rejected.len()
+        );
+        self.batch_failures.push(BatchFailure {
+            batch_id: batch.batch_id(),
+            batch_number: batch.batch_number(),
+            reason: reason.to_string(),
+            requeued,
+            rejected,
+        });
+        &self.batch_failures[self.batch_failures.len() - 1]
+    }
+
+    /// Batches that were reverted, oldest first.
+    pub fn batch_failures(&self) -> &[BatchFailure] {
+        &self.batch_failures
+    }
+
+    /// Puts the programs of a batch whose proof was not applied back at the
+    /// front of the queue, in their original order.
+    pub fn requeue_programs(&mut self, batch: &Batch) {
+        for deployment in batch.programs().iter().rev() {
+            self.program_statuses.insert(deployment.program.id().to_string(), ProgramStatus::Validated);
+            self.pending_programs.push_front(deployment.clone());
+        }
+    }
+
+    /// Puts the registry changes of a batch whose proof was not applied back
+    /// at the front of the queue. Their names stay pending.
+    pub fn requeue_registry_changes(&mut self, batch: &Batch) {
+        for change in batch.registry_changes().iter().rev() {
+            self.pending_registry_changes.push_front(change.clone());
+        }
+    }
+
+    /// Puts the deposits of a batch whose proof was not applied back at the
+    /// front of the queue, so they are still credited in order.
+    pub fn requeue_deposits(&mut self, batch: &Batch) {
+        for deposit in batch.deposits().iter().rev() {
+            self.pending_deposits.push_front(deposit.clone());
+        }
+    }
+
+    /// Puts the withdrawals of a batch whose proof was not applied back at the
+    /// front of the queue. Their amounts and nonces stay reserved.
+    pub fn requeue_withdrawals(&mut self, batch: &Batch) {
+        for withdrawal in batch.withdrawals().iter().rev() {
+            self.pending_withdrawals.push_front(withdrawal.clone());
+        }
+    }
+
+    /// Requeues everything of a batch whose proof was not applied that must
+    /// still make it into a later one.
+    pub fn requeue_batch(&mut self, batch: &Batch) {
+        self.requeue_programs(batch);
+        self.requeue_registry_changes(batch);
+        self.requeue_deposits(batch);
+        self.requeue_withdrawals(batch);
+        self.requeue_forced_transactions(batch);
+        self.requeue_fee_charges(batch);
+    }
+
+    /// Puts the fee charges of a batch whose proof was not applied back at
+    /// the front of the queue. They stay reserved.
+    pub fn requeue_fee_charges(&mut self, batch: &Batch) {
+        for charge in batch.fee_charges().iter().rev() {
+            self.pending_fee_charges.push_front(charge.clone());
+        }
+    }
+
+    /// Puts the forced transactions of a batch whose proof was not applied
+    /// back at the front of the queue. Their deadlines do not move.
+    pub fn requeue_forced_transactions(&mut self, batch: &Batch) {
+        for forced in batch.forced_inclusion().transactions.iter().rev() {
+            self.pending_forced.push_front(forced.clone());
+        }
+    }
+
+    /// Proof that an applied withdrawal is in its batch's withdrawals tree.
+    pub fn withdrawal_proof(&self, withdrawal_hash: &str) -> Result<WithdrawalProof, HVMError> {
+        let (batch_number, index) = self.withdrawal_locations.get(withdrawal_hash)
+            .ok_or_else(|| HVMError::Sequencer(format!("No applied withdrawal {}", withdrawal_hash)))?;
+        self.applied_withdrawals.get(batch_number)
+            .and_then(|withdrawals| withdrawal::withdrawal_proof(*batch_number, withdrawals, *index))
+            .ok_or_else(|| HVMError::Sequencer(format!("Withdrawals of batch {} are missing", batch_number)))
+    }
+
+    pub fn program_status(&self, program_id: &str) -> Option<&ProgramStatus> {
+        self.program_statuses.get(program_id)
+            .or_else(|| self.state.program_statuses.get(program_id))
+    }
+
+    pub fn deployed_programs(&self) -> impl Iterator<Item = &BendProgram> {
+        self.state.programs.values()
+    }
+
+    /// Executes a deployed program without committing its writes.
+    pub fn execute_program(&self, program_id: &str, inputs: Vec<u8>, execution_config: &ExecutionConfig) -> Result<ExecutionResult, HVMError> {
+        let program = self.state.programs.get(program_id)
             .ok_or_else(|| HVMError::Sequencer(format!("Program not found: {}", program_id)))?;
         
-        let result = program.execute(inputs)?;
-        Ok(result
-            .iter()
-            .flat_map(|fr| {
-                let mut bytes = Vec::new();
-                fr.serialize_uncompressed(&mut bytes)
-                    .unwrap_or_else(|_| bytes.clear());
-                bytes
-            })
-            .collect())
+        program.execute_in(inputs, &Arc::new(self.call_context()), execution_config)
+    }
+
+    /// Receipt of an applied transaction, by [`Transaction::hash`].
+    pub fn receipt(&self, transaction_hash: &str) -> Option<&Receipt> {
+        self.receipts.get(transaction_hash)
+    }
+
+    pub fn fee_market(&self) -> &FeeMarket {
+        &self.fee_market
+    }
+
+    /// Charges an account a fee outside of any transaction. The amount must
+    /// already be reserved; it stays reserved until the next batch debits it.
+    pub fn charge_fee(&mut self, account: &str, amount: u64) {
+        if amount > 0 {
+            self.pending_fee_charges.push_back(FeeCharge { account: account.to_string(), amount });
+        }
+    }
+
+    /// Balance of an account less what is reserved from it.
+    pub fn available_balance(&self, account: &str) -> u64 {
+        let reserved = self.reserved.get(account).copied().unwrap_or(0);
+        self.state.account_balance(account).saturating_sub(reserved)
+    }
+
+    /// Holds back `amount` of an account's balance to cover the most a
+    /// transaction or execution can cost, or a withdrawal.
+    pub fn reserve_funds(&mut self, account: &str, amount: u64) -> Result<(), HVMError> {
+        if self.available_balance(account) < amount {
+            return Err(HVMError::InsufficientBalance());
+        }
+        if amount > 0 {
+            *self.reserved.entry(account.to_string()).or_insert(0) += amount;
+        }
+        Ok(())
+    }
+
+    /// Releases part of a reservation.
+    pub fn release_funds(&mut self, account: &str, amount: u64) {
+        if let Some(reserved) = self.reserved.get_mut(account) {
+            *reserved = reserved.saturating_sub(amount);
+            if *reserved == 0 {
+                self.reserved.remove(account);
+            }
+        }
+    }
+
+    /// Deployed programs and committed storage, for executions that may call
+    /// other programs.
+    pub fn call_context(&self) -> CallContext {
+        let mut context = CallContext::with_programs(self.state.programs.values(), self.state.program_storage.clone());
+        context.set_registry(self.state.registry.clone());
+        context
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
     }
 
     pub fn get_current_state(&self) -> State {
@@ -127,10 +813,26 @@ impl Sequencer {
         self.pending_transactions.len()
     }
 
+    pub fn pending_deposits_count(&self) -> usize {
+        self.pending_deposits.len()
+    }
+
+    pub fn pending_forced_count(&self) -> usize {
+        self.pending_forced.len()
+    }
+
+    pub fn pending_withdrawals_count(&self) -> usize {
+        self.pending_withdrawals.len()
+    }
+
     pub fn pending_programs_count(&self) -> usize {
         self.pending_programs.len()
     }
 
+    pub fn pending_registry_changes_count(&self) -> usize {
+        self.pending_registry_changes.len()
+    }
+
     pub fn processed_transactions_count(&self) -> usize {
         self.processed_transactions.len()
     }
@@ -143,7 +845,7 @@ impl Sequencer {
         &self.pending_transactions
     }
 
-    pub fn get_pending_programs(&self) -> &VecDeque<BendProgram> {
+    pub fn get_pending_programs(&self) -> &VecDeque<SignedDeployment> {
         &self.pending_programs
     }
 
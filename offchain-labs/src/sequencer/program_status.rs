@@ -0,0 +1,13 @@
+use serde::{Serialize, Deserialize};
+
+/// Where a submitted program is in its lifecycle. Programs move from
+/// submitted to validated on admission, are included in a batch, and are
+/// deployed once that batch's proof has been applied.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProgramStatus {
+    Submitted,
+    Validated,
+    Included { batch_number: u64 },
+    Deployed { batch_number: u64 },
+    Rejected { reason: String },
+}
@@ -0,0 +1,90 @@
+use crate::bend::Event;
+use crate::zk_rollup::merkle_root;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+/// Outcome of a direct program execution, whose writes are not kept and
+/// which nobody is charged for.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionReceipt {
+    pub program_id: String,
+    pub outputs: Vec<u8>,
+    pub gas_used: u64,
+    /// Base fee when the program ran. A transaction using as much gas would
+    /// pay `gas_used * gas_price`.
+    pub gas_price: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    Success,
+    /// The transaction used all the gas its fee limit paid for.
+    OutOfGas,
+    /// The program was not found or its execution failed.
+    Failed { reason: String },
+    /// The transaction was in too many batches that could not be applied
+    /// and was dropped without being executed.
+    Rejected { reason: String },
+}
+
+/// Outcome of a transaction included in a batch. Only successful
+/// transactions have return data, events and storage writes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub transaction_hash: String,
+    pub status: ReceiptStatus,
+    pub gas_used: u64,
+    pub fee: u64,
+    pub return_data: Vec<u8>,
+    pub events: Vec<Event>,
+    /// Number of the batch the transaction was executed in, which
+    /// [`Sequencer::applied_batch`](super::Sequencer::applied_batch) looks
+    /// up. For a rejected transaction, the number of the last batch it was
+    /// in.
+    pub batch_number: u64,
+    /// Position of the transaction within its batch.
+    pub index: u32,
+}
+
+impl Receipt {
+    /// Leaf hash of the receipt in its batch's receipts root.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update([0u8]);
+        update_bytes(&mut hasher, self.transaction_hash.as_bytes());
+        match &self.status {
+            ReceiptStatus::Success => hasher.update([0u8]),
+            ReceiptStatus::OutOfGas => hasher.update([1u8]),
+            ReceiptStatus::Failed { reason } => {
+                hasher.update([2u8]);
+                update_bytes(&mut hasher, reason.as_bytes());
+            }
+            ReceiptStatus::Rejected { reason } => {
+                hasher.update([3u8]);
+                update_bytes(&mut hasher, reason.as_bytes());
+            }
+        }
+        hasher.update(self.gas_used.to_le_bytes());
+        hasher.update(self.fee.to_le_bytes());
+        update_bytes(&mut hasher, &self.return_data);
+        hasher.update((self.events.len() as u32).to_le_bytes());
+        for event in &self.events {
+            update_bytes(&mut hasher, event.program_id.as_bytes());
+            update_bytes(&mut hasher, &event.topic);
+            update_bytes(&mut hasher, &event.data);
+        }
+        hasher.update(self.batch_number.to_le_bytes());
+        hasher.update(self.index.to_le_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Merkle root over the hashes of `receipts`, in order.
+pub fn receipts_root(receipts: &[Receipt]) -> [u8; 32] {
+    merkle_root(receipts.iter().map(Receipt::hash).collect())
+}
+
+fn update_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u32).to_le_bytes());
+    hasher.update(bytes);
+}
@@ -1,16 +1,87 @@
+use crate::crypto::{Signature, SigningKey, VerifyingKey};
+use crate::error::HVMError;
 use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+const TRANSACTION_DOMAIN: &[u8] = b"hvm-transaction";
+
+/// Fee limit of transactions that only want to be bounded by the execution
+/// gas limit.
+pub const NO_FEE_LIMIT: u64 = u64::MAX;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Transaction {
     pub sender: String,
     pub recipient: String,
     pub amount: Vec<u8>,
+    /// Number of transactions the sender had applied before this one.
+    /// Forced transactions are ordered by the L1 queue and do not use it.
     pub nonce: u64,
     pub program_id: String,
+    /// Most the sender is willing to pay in fees. Execution stops once the
+    /// gas it has paid for is used up.
+    #[serde(default = "no_fee_limit")]
+    pub fee_limit: u64,
+    /// The sender's signature. Every transaction needs one but forced
+    /// transactions, whose sender the L1 vouches for.
+    #[serde(default)]
+    pub signature: Option<TransactionSignature>,
+}
+
+/// Signature over a transaction by the key its sender is named after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionSignature {
+    pub key: VerifyingKey,
+    pub signature: Signature,
 }
 
 impl Transaction {
     pub fn new(sender: String, recipient: String, amount: Vec<u8>, nonce: u64, program_id: String) -> Self {
-        Self { sender, recipient, amount, nonce, program_id }
+        Self::with_fee_limit(sender, recipient, amount, nonce, program_id, NO_FEE_LIMIT)
     }
-}
\ No newline at end of file
+
+    pub fn with_fee_limit(sender: String, recipient: String, amount: Vec<u8>, nonce: u64, program_id: String, fee_limit: u64) -> Self {
+        Self { sender, recipient, amount, nonce, program_id, fee_limit, signature: None }
+    }
+
+    /// Signs the transaction as the account of `key`, which becomes its
+    /// sender.
+    pub fn sign(mut self, key: &SigningKey) -> Self {
+        self.sender = key.verifying_key().to_string();
+        let signature = key.sign(&self.signing_message());
+        self.signature = Some(TransactionSignature { key: key.verifying_key(), signature });
+        self
+    }
+
+    /// Checks that the transaction is signed by the owner of its sender
+    /// account.
+    pub fn verify_signature(&self) -> Result<(), HVMError> {
+        let signature = self.signature.as_ref()
+            .ok_or_else(|| HVMError::Signature(format!("Transaction {} is not signed", self.hash())))?;
+        if signature.key.to_string() != self.sender {
+            return Err(HVMError::Signature(format!("Account {} is not owned by the signing key", self.sender)));
+        }
+        signature.key.verify(&self.signing_message(), &signature.signature)
+    }
+
+    fn signing_message(&self) -> Vec<u8> {
+        [TRANSACTION_DOMAIN, self.hash().as_bytes()].concat()
+    }
+
+    /// Hex SHA-256 over the transaction's fields, under which its receipt is
+    /// stored.
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for field in [self.sender.as_bytes(), self.recipient.as_bytes(), &self.amount, self.program_id.as_bytes()] {
+            hasher.update((field.len() as u32).to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.update(self.nonce.to_le_bytes());
+        hasher.update(self.fee_limit.to_le_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+fn no_fee_limit() -> u64 {
+    NO_FEE_LIMIT
+}
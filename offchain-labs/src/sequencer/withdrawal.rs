@@ -0,0 +1,124 @@
+use crate::crypto::{Signature, SigningKey, VerifyingKey};
+use crate::error::HVMError;
+use crate::zk_rollup::{merkle_proof, merkle_root, MerkleProof};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+const WITHDRAWAL_DOMAIN: &[u8] = b"hvm-withdrawal";
+
+/// Request to move funds from a rollup account to an L1 account, signed by
+/// the key the account is named after.
+///
+/// The amount is reserved from the account when the sequencer accepts the
+/// withdrawal and debited when its batch is applied. The withdrawal is then
+/// a leaf of the batch's withdrawals tree and can be claimed on the L1 with
+/// a [`WithdrawalProof`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub account: String,
+    pub l1_recipient: String,
+    pub amount: u64,
+    /// Number of withdrawals the account had applied before this one.
+    pub nonce: u64,
+    pub owner: VerifyingKey,
+    pub signature: Signature,
+}
+
+impl Withdrawal {
+    /// A withdrawal from the account of `key`, signed by it.
+    pub fn new(key: &SigningKey, l1_recipient: String, amount: u64, nonce: u64) -> Self {
+        let owner = key.verifying_key();
+        let account = owner.to_string();
+        let signature = key.sign(&withdrawal_message(&account, &l1_recipient, amount, nonce));
+        Self { account, l1_recipient, amount, nonce, owner, signature }
+    }
+
+    /// Checks that the withdrawal is signed by the owner of its account.
+    pub fn verify_signature(&self) -> Result<(), HVMError> {
+        if self.owner.to_string() != self.account {
+            return Err(HVMError::Signature(format!("Account {} is not owned by the signing key", self.account)));
+        }
+        self.owner.verify(&withdrawal_message(&self.account, &self.l1_recipient, self.amount, self.nonce), &self.signature)
+    }
+
+    /// Hex SHA-256 over the withdrawal's fields, under which its proof can be
+    /// looked up.
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for field in [self.account.as_bytes(), self.l1_recipient.as_bytes()] {
+            hasher.update((field.len() as u32).to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.update(self.amount.to_le_bytes());
+        hasher.update(self.nonce.to_le_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Leaf of the withdrawal at `index` in the tree of batch
+    /// `batch_number`. The position is part of the leaf so each one can only
+    /// be claimed once.
+    pub fn leaf_hash(&self, batch_number: u64, index: u32) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update([0u8]);
+        hasher.update(batch_number.to_le_bytes());
+        hasher.update(index.to_le_bytes());
+        hasher.update((self.l1_recipient.len() as u32).to_le_bytes());
+        hasher.update(self.l1_recipient.as_bytes());
+        hasher.update(self.amount.to_le_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Everything the L1 needs to pay out a withdrawal against the withdrawals
+/// root of a batch header.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WithdrawalProof {
+    pub withdrawal: Withdrawal,
+    pub batch_number: u64,
+    pub index: u32,
+    pub withdrawals_root: [u8; 32],
+    pub proof: MerkleProof,
+}
+
+impl WithdrawalProof {
+    pub fn verify(&self) -> bool {
+        self.proof.root(self.withdrawal.leaf_hash(self.batch_number, self.index)) == self.withdrawals_root
+    }
+}
+
+/// Root of the withdrawals tree of batch `batch_number`.
+pub fn withdrawals_root(batch_number: u64, withdrawals: &[Withdrawal]) -> [u8; 32] {
+    merkle_root(leaves(batch_number, withdrawals))
+}
+
+/// Proof for the withdrawal at `index` of batch `batch_number`.
+pub fn withdrawal_proof(batch_number: u64, withdrawals: &[Withdrawal], index: u32) -> Option<WithdrawalProof> {
+    let withdrawal = withdrawals.get(index as usize)?.clone();
+    let leaves = leaves(batch_number, withdrawals);
+    Some(WithdrawalProof {
+        withdrawal,
+        batch_number,
+        index,
+        withdrawals_root: merkle_root(leaves.clone()),
+        proof: merkle_proof(leaves, index as usize)?,
+    })
+}
+
+fn leaves(batch_number: u64, withdrawals: &[Withdrawal]) -> Vec<[u8; 32]> {
+    withdrawals.iter()
+        .enumerate()
+        .map(|(index, withdrawal)| withdrawal.leaf_hash(batch_number, index as u32))
+        .collect()
+}
+
+/// Message the owner of `account` signs to withdraw from it.
+pub fn withdrawal_message(account: &str, l1_recipient: &str, amount: u64, nonce: u64) -> Vec<u8> {
+    let mut message = WITHDRAWAL_DOMAIN.to_vec();
+    for field in [account.as_bytes(), l1_recipient.as_bytes()] {
+        message.extend((field.len() as u32).to_le_bytes());
+        message.extend(field);
+    }
+    message.extend(amount.to_le_bytes());
+    message.extend(nonce.to_le_bytes());
+    message
+}
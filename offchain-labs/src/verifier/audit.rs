@@ -0,0 +1,108 @@
+use super::ZKVerifier;
+use crate::config::ExecutionConfig;
+use crate::prover::execute_batch;
+use crate::sequencer::{Sequencer, StoredBatch};
+
+/// How an audited batch differs from what the auditor computes for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The batch does not follow the last audited one.
+    OutOfOrder { expected: u64 },
+    /// The proof does not verify against the batch's public inputs.
+    InvalidProof { reason: String },
+    /// The batch does not consume the L1 forced inclusion queue as it
+    /// should, or leaves a forced transaction in it past its deadline.
+    ForcedInclusion { reason: String },
+    /// Executing the batch again gives a different receipt at `index`.
+    Receipt { index: usize },
+    /// Executing the batch again gives different storage writes.
+    StorageChanges,
+    /// The batch cannot be applied, or does not lead to the state root in
+    /// its header.
+    StateTransition { reason: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Batches that matched, from the first one audited.
+    pub batches_checked: u64,
+    /// Number of the first batch that diverged and how. Auditing stops at
+    /// that batch.
+    pub divergence: Option<(u64, Divergence)>,
+}
+
+/// Re-executes applied batches through its own sequencer to check an
+/// operator without trusting its proofs alone.
+///
+/// Each batch has to follow the previous one, include what the L1 forced
+/// inclusion queue requires of it and, if a verifier is given, verify. Its
+/// transactions have to produce the same receipts and storage writes when
+/// they run again, and the batch has to lead to the state root in its
+/// header.
+pub struct Auditor {
+    sequencer: Sequencer,
+    verifier: Option<ZKVerifier>,
+    execution_config: ExecutionConfig,
+}
+
+impl Auditor {
+    /// An auditor whose first batch starts from the state of `sequencer`,
+    /// either a new one or one started from a snapshot. `execution_config`
+    /// has to be the operator's, as gas limits decide receipts.
+    pub fn new(sequencer: Sequencer, verifier: Option<ZKVerifier>, execution_config: ExecutionConfig) -> Self {
+        Self { sequencer, verifier, execution_config }
+    }
+
+    pub fn audit<'a>(&mut self, batches: impl IntoIterator<Item = &'a StoredBatch>) -> AuditReport {
+        let mut batches_checked = 0;
+        for stored in batches {
+            if let Err(divergence) = self.audit_batch(stored) {
+                return AuditReport {
+                    batches_checked,
+                    divergence: Some((stored.batch.batch_number(), divergence)),
+                };
+            }
+            batches_checked += 1;
+        }
+        AuditReport { batches_checked, divergence: None }
+    }
+
+    /// Checks a single batch and applies it if it matches.
+    pub fn audit_batch(&mut self, stored: &StoredBatch) -> Result<(), Divergence> {
+        let expected = self.sequencer.state().nonce();
+        if stored.batch.batch_number() != expected {
+            return Err(Divergence::OutOfOrder { expected });
+        }
+        let forced_inclusion = self.sequencer.forced_inclusion_check(&stored.batch)
+            .map_err(|e| Divergence::ForcedInclusion { reason: e.to_string() })?;
+        if let Some(verifier) = &self.verifier {
+            match verifier.verify_proof(&stored.proof, &stored.batch.public_inputs(forced_inclusion)) {
+                Ok(true) => {}
+                Ok(false) => return Err(Divergence::InvalidProof { reason: "Proof does not verify".to_string() }),
+                Err(e) => return Err(Divergence::InvalidProof { reason: e.to_string() }),
+            }
+        }
+        if !forced_inclusion.is_satisfied() {
+            return Err(Divergence::ForcedInclusion { reason: format!(
+                "Forced transaction due at batch {} is left in the queue", forced_inclusion.next_deadline
+            ) });
+        }
+
+        let mut batch = stored.batch.unexecuted();
+        execute_batch(self.sequencer.deployed_programs(), &mut batch, self.sequencer.state(), &self.execution_config);
+        let (receipts, expected_receipts) = (batch.receipts(), stored.batch.receipts());
+        if let Some(index) = (0..receipts.len().max(expected_receipts.len())).find(|&i| receipts.get(i) != expected_receipts.get(i)) {
+            return Err(Divergence::Receipt { index });
+        }
+        if batch.storage_changes() != stored.batch.storage_changes() {
+            return Err(Divergence::StorageChanges);
+        }
+        self.sequencer.apply_replayed(stored.proof.clone(), &batch, &stored.header)
+            .map_err(|e| Divergence::StateTransition { reason: e.to_string() })
+    }
+
+    /// The sequencer the audited batches were applied to.
+    pub fn sequencer(&self) -> &Sequencer {
+        &self.sequencer
+    }
+}
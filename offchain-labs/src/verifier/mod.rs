@@ -8,6 +8,11 @@ use ark_ec::AffineRepr;
 use ark_ff::PrimeField;
 use std::ops::AddAssign;
 
+pub mod audit;
+
+pub use audit::{AuditReport, Auditor, Divergence};
+
+#[derive(Clone)]
 pub struct ZKVerifier {
     verifying_key: PreparedVerifyingKey<Bn254>,
 }
@@ -0,0 +1,204 @@
+use super::{State, StateChanges};
+use crate::bend::BendProgram;
+use crate::bend::registry::RegistryEntry;
+use crate::config::HistoryConfig;
+use crate::error::HVMError;
+use crate::sequencer::ProgramStatus;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// States after applied batches, by batch number, pruned according to a
+/// [`HistoryConfig`].
+///
+/// Only the oldest state kept is held in full. Every later one is kept as
+/// the changes that lead to it from the one kept before it, and states are
+/// rebuilt from those when asked for.
+#[derive(Clone, Debug, Default)]
+pub struct StateHistory {
+    /// Number of the oldest batch kept and the state after it.
+    base: Option<(u64, State)>,
+    /// Changes from the state kept before each later batch kept, by batch
+    /// number.
+    diffs: BTreeMap<u64, StateDiff>,
+    /// Number of the latest batch recorded.
+    latest: Option<u64>,
+    config: HistoryConfig,
+}
+
+impl StateHistory {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self { base: None, diffs: BTreeMap::new(), latest: None, config }
+    }
+
+    /// Records `state`, the state after batch `batch_number`, which has to
+    /// follow the latest recorded one, and prunes what the config no longer
+    /// keeps. `previous` is the state it was reached from, which has to be
+    /// the latest one recorded, if any.
+    pub fn record(&mut self, batch_number: u64, previous: &State, state: &State) {
+        if self.base.is_none() {
+            self.base = Some((batch_number, state.clone()));
+        } else {
+            self.diffs.insert(batch_number, StateDiff::between(previous, state));
+        }
+        self.latest = Some(batch_number);
+        self.prune();
+    }
+
+    /// Changes the retention policy. States already pruned stay pruned.
+    pub fn set_config(&mut self, config: HistoryConfig) {
+        self.config = config;
+        self.prune();
+    }
+
+    /// The state right after batch `batch_number` was applied.
+    pub fn state_at(&self, batch_number: u64) -> Result<State, HVMError> {
+        if self.latest.is_none_or(|latest| batch_number > latest) {
+            return Err(HVMError::Sequencer(format!("Batch {} has not been applied", batch_number)));
+        }
+        let (base_number, base) = self.base.as_ref().ok_or(HVMError::StatePruned(batch_number))?;
+        if batch_number != *base_number && !self.diffs.contains_key(&batch_number) {
+            return Err(HVMError::StatePruned(batch_number));
+        }
+        let mut state = base.clone();
+        for diff in self.diffs.range(..=batch_number).map(|(_, diff)| diff) {
+            diff.apply(&mut state);
+        }
+        Ok(state)
+    }
+
+    /// Numbers of the batches whose states are kept, in order.
+    pub fn versions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.base.iter().map(|(batch_number, _)| *batch_number).chain(self.diffs.keys().copied())
+    }
+
+    fn prune(&mut self) {
+        let Some(latest) = self.latest else {
+            return;
+        };
+        if self.config.archive {
+            return;
+        }
+        let recent_start = latest.saturating_sub(self.config.keep_recent.max(1) - 1);
+        let checkpoint_interval = self.config.checkpoint_interval;
+        let pruned: Vec<u64> = self.versions()
+            .filter(|&batch_number| batch_number < recent_start && (checkpoint_interval == 0 || batch_number % checkpoint_interval != 0))
+            .collect();
+        for batch_number in pruned {
+            self.remove(batch_number);
+        }
+    }
+
+    /// Drops the state after `batch_number`, folding its changes into the
+    /// next state kept. The latest state is never dropped.
+    fn remove(&mut self, batch_number: u64) {
+        let Some(next) = self.diffs.range(batch_number + 1..).map(|(&next, _)| next).next() else {
+            return;
+        };
+        match &mut self.base {
+            Some((base_number, base)) if *base_number == batch_number => {
+                if let Some(diff) = self.diffs.remove(&next) {
+                    diff.apply(base);
+                    *base_number = next;
+                }
+            }
+            _ => {
+                if let Some(diff) = self.diffs.remove(&batch_number) {
+                    let later = self.diffs.remove(&next).unwrap_or_default();
+                    self.diffs.insert(next, diff.then(later));
+                }
+            }
+        }
+    }
+}
+
+/// Changes from one state to another: the counters after them, and the new
+/// value of every entry that changed, `None` if it was removed.
+#[derive(Clone, Debug, Default)]
+struct StateDiff {
+    counters: (u64, u64, u64, u64),
+    accounts: BTreeMap<String, Option<u64>>,
+    nonces: BTreeMap<String, Option<u64>>,
+    withdrawal_nonces: BTreeMap<String, Option<u64>>,
+    storage: StateChanges,
+    registry: BTreeMap<String, RegistryEntry>,
+    programs: BTreeMap<String, Option<BendProgram>>,
+    program_statuses: BTreeMap<String, Option<ProgramStatus>>,
+}
+
+impl StateDiff {
+    fn between(before: &State, after: &State) -> Self {
+        let program_ids: BTreeSet<&String> = before.program_storage.keys().chain(after.program_storage.keys()).collect();
+        let storage = program_ids.into_iter()
+            .filter_map(|program_id| {
+                let (before, after) = (before.program_storage(program_id), after.program_storage(program_id));
+                let keys: BTreeSet<&[u8]> = before.into_iter().chain(after).flat_map(|storage| storage.iter().map(|(key, _)| key)).collect();
+                let changes: BTreeMap<Vec<u8>, Option<Vec<u8>>> = keys.into_iter()
+                    .map(|key| (key, after.and_then(|storage| storage.get(key))))
+                    .filter(|(key, value)| before.and_then(|storage| storage.get(key)) != *value)
+                    .map(|(key, value)| (key.to_vec(), value.map(<[u8]>::to_vec)))
+                    .collect();
+                (!changes.is_empty()).then(|| (program_id.clone(), changes))
+            })
+            .collect();
+        Self {
+            counters: (after.balance, after.nonce, after.deposit_nonce, after.forced_queue_index),
+            accounts: map_diff(&before.accounts, &after.accounts),
+            nonces: map_diff(&before.nonces, &after.nonces),
+            withdrawal_nonces: map_diff(&before.withdrawal_nonces, &after.withdrawal_nonces),
+            storage,
+            // Registry entries are only ever added or changed.
+            registry: after.registry.entries()
+                .filter(|(name, entry)| before.registry.entry(name) != Some(*entry))
+                .map(|(name, entry)| (name.to_string(), entry.clone()))
+                .collect(),
+            programs: map_diff(&before.programs, &after.programs),
+            program_statuses: map_diff(&before.program_statuses, &after.program_statuses),
+        }
+    }
+
+    fn apply(&self, state: &mut State) {
+        (state.balance, state.nonce, state.deposit_nonce, state.forced_queue_index) = self.counters;
+        apply_map(&mut state.accounts, &self.accounts);
+        apply_map(&mut state.nonces, &self.nonces);
+        apply_map(&mut state.withdrawal_nonces, &self.withdrawal_nonces);
+        for (program_id, changes) in &self.storage {
+            state.apply_storage_changes(program_id, changes);
+        }
+        for (name, entry) in &self.registry {
+            state.registry.restore_entry(name.clone(), entry.clone());
+        }
+        apply_map(&mut state.programs, &self.programs);
+        apply_map(&mut state.program_statuses, &self.program_statuses);
+    }
+
+    /// The changes of `self` followed by those of `later`.
+    fn then(mut self, later: StateDiff) -> Self {
+        self.counters = later.counters;
+        self.accounts.extend(later.accounts);
+        self.nonces.extend(later.nonces);
+        self.withdrawal_nonces.extend(later.withdrawal_nonces);
+        for (program_id, changes) in later.storage {
+            self.storage.entry(program_id).or_default().extend(changes);
+        }
+        self.registry.extend(later.registry);
+        self.programs.extend(later.programs);
+        self.program_statuses.extend(later.program_statuses);
+        self
+    }
+}
+
+fn map_diff<V: Clone + PartialEq>(before: &BTreeMap<String, V>, after: &BTreeMap<String, V>) -> BTreeMap<String, Option<V>> {
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    keys.into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| (key.clone(), after.get(key).cloned()))
+        .collect()
+}
+
+fn apply_map<V: Clone>(map: &mut BTreeMap<String, V>, changes: &BTreeMap<String, Option<V>>) {
+    for (key, value) in changes {
+        match value {
+            Some(value) => map.insert(key.clone(), value.clone()),
+            None => map.remove(key),
+        };
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+/// Merkle root over leaf hashes, which callers compute with a `0` prefix.
+///
+/// Inner nodes are hashed with a `1` prefix, and an odd node at the end of a
+/// level is carried up unchanged. The root of no leaves is all zeroes.
+pub fn merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    if level.is_empty() {
+        return [0u8; 32];
+    }
+
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// Path from the leaf at `index` to the root of [`merkle_root`]`(leaves)`,
+/// or `None` if there is no such leaf.
+pub fn merkle_proof(mut level: Vec<[u8; 32]>, mut index: usize) -> Option<MerkleProof> {
+    if index >= level.len() {
+        return None;
+    }
+
+    let mut siblings = Vec::new();
+    while level.len() > 1 {
+        if index % 2 == 1 {
+            siblings.push(MerkleSibling::Left(level[index - 1]));
+        } else if let Some(right) = level.get(index + 1) {
+            siblings.push(MerkleSibling::Right(*right));
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(MerkleProof { siblings })
+}
+
+/// Siblings on the path from a leaf to the root, bottom up. Levels where the
+/// node was carried up without a sibling are skipped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub siblings: Vec<MerkleSibling>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MerkleSibling {
+    Left([u8; 32]),
+    Right([u8; 32]),
+}
+
+impl MerkleProof {
+    /// Root the proof leads to from `leaf`.
+    pub fn root(&self, leaf: [u8; 32]) -> [u8; 32] {
+        self.siblings.iter().fold(leaf, |node, sibling| match sibling {
+            MerkleSibling::Left(left) => hash_pair(left, &node),
+            MerkleSibling::Right(right) => hash_pair(&node, right),
+        })
+    }
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level.chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_pair(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
@@ -1,8 +1,18 @@
+mod history;
+mod merkle;
+mod program_storage;
 mod proof;
+mod snapshot;
 mod state;
+mod state_proof;
 
+pub use history::StateHistory;
+pub use merkle::{merkle_proof, merkle_root, MerkleProof, MerkleSibling};
+pub use program_storage::{ProgramStorage, StateChanges, StorageChanges};
 pub use proof::Proof;
+pub use snapshot::{read_snapshot, write_snapshot, SnapshotChunk, SnapshotEntry, SnapshotManifest, DEFAULT_ENTRIES_PER_CHUNK, SNAPSHOT_VERSION};
 pub use state::State;
+pub use state_proof::{StateLeaf, StateProof, StorageProof};
 
 use crate::error::HVMError;
 
@@ -0,0 +1,78 @@
+use super::merkle::{merkle_proof, merkle_root, MerkleProof};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::BTreeMap;
+
+/// Pending writes of a program call. `None` removes the key.
+pub type StorageChanges = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Pending writes across programs, keyed by program id.
+pub type StateChanges = BTreeMap<String, StorageChanges>;
+
+/// Key-value storage namespace of a single deployed program.
+#[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ProgramStorage {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl ProgramStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries.get(key).map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries.iter().map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.entries.insert(key, value);
+    }
+
+    pub fn apply(&mut self, changes: &StorageChanges) {
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.entries.insert(key.clone(), value.clone()),
+                None => self.entries.remove(key),
+            };
+        }
+    }
+
+    /// Merkle root over the entries in key order. See [`merkle_root`].
+    pub fn root(&self) -> [u8; 32] {
+        merkle_root(self.leaves())
+    }
+
+    /// Proof of the entry at `key` against [`root`](Self::root), or `None`
+    /// if there is no such entry.
+    pub fn proof(&self, key: &[u8]) -> Option<MerkleProof> {
+        let index = self.entries.keys().position(|k| k.as_slice() == key)?;
+        merkle_proof(self.leaves(), index)
+    }
+
+    fn leaves(&self) -> Vec<[u8; 32]> {
+        self.entries.iter().map(|(key, value)| entry_hash(key, value)).collect()
+    }
+}
+
+/// Leaf hash of a storage entry in [`ProgramStorage::root`].
+pub fn entry_hash(key: &[u8], value: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update((key.len() as u32).to_le_bytes());
+    hasher.update(key);
+    hasher.update((value.len() as u32).to_le_bytes());
+    hasher.update(value);
+    hasher.finalize().into()
+}
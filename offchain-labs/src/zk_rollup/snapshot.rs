@@ -0,0 +1,193 @@
+use super::State;
+use crate::bend::BendProgram;
+use crate::bend::registry::RegistryEntry;
+use crate::error::HVMError;
+use crate::sequencer::ProgramStatus;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Version of the snapshot format written by [`write_snapshot`].
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+/// Entries per chunk when no other size is asked for.
+pub const DEFAULT_ENTRIES_PER_CHUNK: usize = 4096;
+
+/// First line of a snapshot file. Every following line is a
+/// [`SnapshotChunk`] whose SHA-256 is listed here, so each chunk can be
+/// checked before it is loaded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub version: u32,
+    /// Batches applied to the state, which is the number of the first batch
+    /// to replay on top of it.
+    pub batch_count: u64,
+    pub state_root: [u8; 32],
+    pub chunk_hashes: Vec<[u8; 32]>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotEntry {
+    Counters { balance: u64, nonce: u64, deposit_nonce: u64, forced_queue_index: u64 },
+    Account { account: String, balance: u64 },
+    Nonce { account: String, nonce: u64 },
+    WithdrawalNonce { account: String, nonce: u64 },
+    Storage { program_id: String, key: Vec<u8>, value: Vec<u8> },
+    Registry { name: String, entry: RegistryEntry },
+    Program { program: BendProgram },
+    ProgramStatus { program_id: String, status: ProgramStatus },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    pub index: u32,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+/// Writes `state` to `path` in chunks of at most `entries_per_chunk`
+/// entries.
+///
+/// The chunks are serialized twice, once to hash them for the manifest and
+/// once to write them, so the state is never held in memory in serialized
+/// form. The file is written next to `path` and renamed into place.
+pub fn write_snapshot(state: &State, path: &Path, entries_per_chunk: usize) -> Result<SnapshotManifest, HVMError> {
+    let chunk_hashes = chunks(state, entries_per_chunk)
+        .map(|chunk| Ok(Sha256::digest(serde_json::to_vec(&chunk)?).into()))
+        .collect::<Result<Vec<[u8; 32]>, HVMError>>()?;
+    let manifest = SnapshotManifest {
+        version: SNAPSHOT_VERSION,
+        batch_count: state.nonce(),
+        state_root: state.root(),
+        chunk_hashes,
+    };
+
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, &manifest)?;
+    writer.write_all(b"\n")?;
+    for chunk in chunks(state, entries_per_chunk) {
+        serde_json::to_writer(&mut writer, &chunk)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(manifest)
+}
+
+/// Reads a snapshot written by [`write_snapshot`], checking every chunk
+/// against the manifest and the rebuilt state against its root.
+pub fn read_snapshot(path: &Path) -> Result<(SnapshotManifest, State), HVMError> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let manifest: SnapshotManifest = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(HVMError::ZKRollup("Snapshot is empty".to_string())),
+    };
+    if manifest.version != SNAPSHOT_VERSION {
+        return Err(HVMError::ZKRollup(format!(
+            "Unsupported snapshot version {}, expected {}", manifest.version, SNAPSHOT_VERSION
+        )));
+    }
+
+    let mut state = State::default();
+    let mut count = 0;
+    for (index, line) in lines.enumerate() {
+        let line = line?;
+        let expected = manifest.chunk_hashes.get(index)
+            .ok_or_else(|| HVMError::ZKRollup(format!("Snapshot has more than {} chunks", manifest.chunk_hashes.len())))?;
+        if <[u8; 32]>::from(Sha256::digest(line.as_bytes())) != *expected {
+            return Err(HVMError::ZKRollup(format!("Snapshot chunk {} does not match its hash", index)));
+        }
+        let chunk: SnapshotChunk = serde_json::from_str(&line)?;
+        if chunk.index as usize != index {
+            return Err(HVMError::ZKRollup(format!("Snapshot chunk {} is out of order", chunk.index)));
+        }
+        for entry in chunk.entries {
+            load_entry(&mut state, entry);
+        }
+        count += 1;
+    }
+    if count != manifest.chunk_hashes.len() {
+        return Err(HVMError::ZKRollup(format!(
+            "Snapshot has {} of {} chunks", count, manifest.chunk_hashes.len()
+        )));
+    }
+    if state.nonce() != manifest.batch_count || state.root() != manifest.state_root {
+        return Err(HVMError::ZKRollup("Snapshot does not match its state root".to_string()));
+    }
+    Ok((manifest, state))
+}
+
+fn chunks(state: &State, entries_per_chunk: usize) -> impl Iterator<Item = SnapshotChunk> + '_ {
+    let entries_per_chunk = entries_per_chunk.max(1);
+    let mut entries = entries(state).peekable();
+    let mut index = 0;
+    std::iter::from_fn(move || {
+        entries.peek()?;
+        let chunk = SnapshotChunk { index, entries: entries.by_ref().take(entries_per_chunk).collect() };
+        index += 1;
+        Some(chunk)
+    })
+}
+
+fn entries(state: &State) -> impl Iterator<Item = SnapshotEntry> + '_ {
+    let counters = SnapshotEntry::Counters {
+        balance: state.balance,
+        nonce: state.nonce,
+        deposit_nonce: state.deposit_nonce,
+        forced_queue_index: state.forced_queue_index,
+    };
+    let accounts = state.accounts.iter()
+        .map(|(account, balance)| SnapshotEntry::Account { account: account.clone(), balance: *balance });
+    let nonces = state.nonces.iter()
+        .map(|(account, nonce)| SnapshotEntry::Nonce { account: account.clone(), nonce: *nonce });
+    let withdrawal_nonces = state.withdrawal_nonces.iter()
+        .map(|(account, nonce)| SnapshotEntry::WithdrawalNonce { account: account.clone(), nonce: *nonce });
+    let storage = state.program_storage.iter().flat_map(|(program_id, storage)| {
+        storage.iter().map(move |(key, value)| SnapshotEntry::Storage {
+            program_id: program_id.clone(),
+            key: key.to_vec(),
+            value: value.to_vec(),
+        })
+    });
+    let registry = state.registry.entries()
+        .map(|(name, entry)| SnapshotEntry::Registry { name: name.to_string(), entry: entry.clone() });
+    let programs = state.programs.values()
+        .map(|program| SnapshotEntry::Program { program: program.clone() });
+    let statuses = state.program_statuses.iter()
+        .map(|(program_id, status)| SnapshotEntry::ProgramStatus { program_id: program_id.clone(), status: status.clone() });
+    std::iter::once(counters).chain(accounts).chain(nonces).chain(withdrawal_nonces).chain(storage).chain(registry).chain(programs).chain(statuses)
+}
+
+fn load_entry(state: &mut State, entry: SnapshotEntry) {
+    match entry {
+        SnapshotEntry::Counters { balance, nonce, deposit_nonce, forced_queue_index } => {
+            state.balance = balance;
+            state.nonce = nonce;
+            state.deposit_nonce = deposit_nonce;
+            state.forced_queue_index = forced_queue_index;
+        }
+        SnapshotEntry::Account { account, balance } => {
+            state.accounts.insert(account, balance);
+        }
+        SnapshotEntry::Nonce { account, nonce } => {
+            state.nonces.insert(account, nonce);
+        }
+        SnapshotEntry::WithdrawalNonce { account, nonce } => {
+            state.withdrawal_nonces.insert(account, nonce);
+        }
+        SnapshotEntry::Storage { program_id, key, value } => {
+            state.program_storage.entry(program_id).or_default().insert(key, value);
+        }
+        SnapshotEntry::Registry { name, entry } => {
+            state.registry.restore_entry(name, entry);
+        }
+        SnapshotEntry::Program { program } => {
+            state.programs.insert(program.id().to_string(), program);
+        }
+        SnapshotEntry::ProgramStatus { program_id, status } => {
+            state.program_statuses.insert(program_id, status);
+        }
+    }
+}
@@ -1,11 +1,40 @@
-use super::Proof;
+use super::{merkle_proof, merkle_root, Proof, ProgramStorage, StateLeaf, StateProof, StorageChanges, StorageProof};
+use crate::bend::{BendProgram, ProgramRegistry};
 use crate::error::HVMError;
+use crate::sequencer::{Deposit, ProgramStatus};
 use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct State {
     pub balance: u64,
     pub nonce: u64,
+    pub program_storage: BTreeMap<String, ProgramStorage>,
+    /// Balances of state accounts, such as the sequencer's fee account.
+    #[serde(default)]
+    pub accounts: BTreeMap<String, u64>,
+    /// Nonce of the next transaction of each account that has sent one.
+    #[serde(default)]
+    pub nonces: BTreeMap<String, u64>,
+    /// Nonce of the next withdrawal of each account that has made one.
+    #[serde(default)]
+    pub withdrawal_nonces: BTreeMap<String, u64>,
+    /// Number of L1 deposits credited so far, which is the nonce of the next.
+    #[serde(default)]
+    pub deposit_nonce: u64,
+    /// L1 queue index of the next forced transaction to include.
+    #[serde(default)]
+    pub forced_queue_index: u64,
+    /// Owners and version history of program names.
+    #[serde(default)]
+    pub registry: ProgramRegistry,
+    /// Programs deployed by applied batches, by program id.
+    #[serde(default)]
+    pub programs: BTreeMap<String, BendProgram>,
+    /// Whether each program in an applied batch was deployed or rejected,
+    /// by program id.
+    #[serde(default)]
+    pub program_statuses: BTreeMap<String, ProgramStatus>,
 }
 
 impl State {
@@ -27,4 +56,163 @@ impl State {
     pub fn nonce(&self) -> u64 {
         self.nonce
     }
-}
\ No newline at end of file
+
+    pub fn account_balance(&self, account: &str) -> u64 {
+        self.accounts.get(account).copied().unwrap_or(0)
+    }
+
+    pub fn credit(&mut self, account: &str, amount: u64) {
+        let balance = self.accounts.entry(account.to_string()).or_insert(0);
+        *balance = balance.saturating_add(amount);
+    }
+
+    pub fn debit(&mut self, account: &str, amount: u64) -> Result<(), HVMError> {
+        match self.accounts.get_mut(account) {
+            Some(balance) if *balance >= amount => {
+                *balance -= amount;
+                Ok(())
+            }
+            _ if amount == 0 => Ok(()),
+            _ => Err(HVMError::InsufficientBalance()),
+        }
+    }
+
+    /// Nonce the next transaction of `account` has to have.
+    pub fn account_nonce(&self, account: &str) -> u64 {
+        self.nonces.get(account).copied().unwrap_or(0)
+    }
+
+    /// Uses up the next nonce of `account`, which has to be `nonce`, so that
+    /// a signed transaction can only be applied once.
+    pub fn use_nonce(&mut self, account: &str, nonce: u64) -> Result<(), HVMError> {
+        use_next(&mut self.nonces, account, nonce, "Transaction")
+    }
+
+    /// Nonce the next withdrawal from `account` has to have.
+    pub fn withdrawal_nonce(&self, account: &str) -> u64 {
+        self.withdrawal_nonces.get(account).copied().unwrap_or(0)
+    }
+
+    /// Uses up the next withdrawal nonce of `account`, which has to be
+    /// `nonce`, so that a signed withdrawal can only be applied once.
+    pub fn use_withdrawal_nonce(&mut self, account: &str, nonce: u64) -> Result<(), HVMError> {
+        use_next(&mut self.withdrawal_nonces, account, nonce, "Withdrawal")
+    }
+
+    /// Accounts with a balance or a nonce, in key order.
+    pub fn account_names(&self) -> BTreeSet<&String> {
+        self.accounts.keys().chain(self.nonces.keys()).chain(self.withdrawal_nonces.keys()).collect()
+    }
+
+    pub fn deposit_nonce(&self) -> u64 {
+        self.deposit_nonce
+    }
+
+    /// Credits a deposit if it is the next one expected. Returns whether it
+    /// was credited.
+    pub fn credit_deposit(&mut self, deposit: &Deposit) -> bool {
+        if deposit.nonce != self.deposit_nonce {
+            return false;
+        }
+        self.credit(&deposit.account, deposit.amount);
+        self.deposit_nonce += 1;
+        true
+    }
+
+    pub fn forced_queue_index(&self) -> u64 {
+        self.forced_queue_index
+    }
+
+    pub fn program_storage(&self, program_id: &str) -> Option<&ProgramStorage> {
+        self.program_storage.get(program_id)
+    }
+
+    pub fn storage_root(&self, program_id: &str) -> [u8; 32] {
+        self.program_storage(program_id)
+            .map(ProgramStorage::root)
+            .unwrap_or_default()
+    }
+
+    /// Merkle root committing to the whole state: the counters first, then
+    /// every account's balance and nonces, every program's storage root, every registry
+    /// entry, every deployed program and every program's status, each in key
+    /// order.
+    pub fn root(&self) -> [u8; 32] {
+        merkle_root(self.leaves().iter().map(StateLeaf::hash).collect())
+    }
+
+    fn leaves(&self) -> Vec<StateLeaf> {
+        let counters = StateLeaf::Counters {
+            balance: self.balance,
+            nonce: self.nonce,
+            deposit_nonce: self.deposit_nonce,
+            forced_queue_index: self.forced_queue_index,
+        };
+        let accounts = self.account_names().into_iter().map(|account| StateLeaf::Account {
+            account: account.clone(),
+            balance: self.account_balance(account),
+            nonce: self.account_nonce(account),
+            withdrawal_nonce: self.withdrawal_nonce(account),
+        });
+        let storage = self.program_storage.iter()
+            .map(|(program_id, storage)| StateLeaf::Storage { program_id: program_id.clone(), storage_root: storage.root() });
+        let registry = self.registry.entries()
+            .map(|(name, entry)| StateLeaf::Registry { name: name.to_string(), entry_hash: entry.hash() });
+        let programs = self.programs.iter()
+            .map(|(program_id, program)| StateLeaf::Program { program_id: program_id.clone(), program_hash: program.hash() });
+        let statuses = self.program_statuses.iter()
+            .map(|(program_id, status)| StateLeaf::ProgramStatus { program_id: program_id.clone(), status: status.clone() });
+        std::iter::once(counters).chain(accounts).chain(storage).chain(registry).chain(programs).chain(statuses).collect()
+    }
+
+    fn leaf_proof(&self, index: usize) -> Option<StateProof> {
+        let leaves = self.leaves();
+        let leaf = leaves.get(index)?.clone();
+        let proof = merkle_proof(leaves.iter().map(StateLeaf::hash).collect(), index)?;
+        Some(StateProof { state_root: self.root(), leaf, proof })
+    }
+
+    /// Proof of the counters, which hold the state and deposit nonces.
+    pub fn counters_proof(&self) -> StateProof {
+        self.leaf_proof(0).expect("the counters are always the first leaf")
+    }
+
+    /// Proof of an account's balance and nonces, or `None` if the account
+    /// has never been credited, sent a transaction or withdrawn.
+    pub fn account_proof(&self, account: &str) -> Option<StateProof> {
+        let index = self.account_names().into_iter().position(|a| a == account)?;
+        self.leaf_proof(1 + index)
+    }
+
+    /// Proof of the entry at `key` in a program's storage, or `None` if
+    /// there is no such entry.
+    pub fn storage_proof(&self, program_id: &str, key: &[u8]) -> Option<StorageProof> {
+        let index = self.program_storage.keys().position(|id| id == program_id)?;
+        let storage = &self.program_storage[program_id];
+        Some(StorageProof {
+            program: self.leaf_proof(1 + self.account_names().len() + index)?,
+            key: key.to_vec(),
+            value: storage.get(key)?.to_vec(),
+            proof: storage.proof(key)?,
+        })
+    }
+
+    pub fn apply_storage_changes(&mut self, program_id: &str, changes: &StorageChanges) {
+        let storage = self.program_storage.entry(program_id.to_string()).or_default();
+        storage.apply(changes);
+        if storage.is_empty() {
+            self.program_storage.remove(program_id);
+        }
+    }
+}
+
+fn use_next(nonces: &mut BTreeMap<String, u64>, account: &str, nonce: u64, kind: &str) -> Result<(), HVMError> {
+    let expected = nonces.get(account).copied().unwrap_or(0);
+    if nonce != expected {
+        return Err(HVMError::ZKRollup(format!(
+            "{} nonce {} of account {} is not the next one, expected {}", kind, nonce, account, expected
+        )));
+    }
+    nonces.insert(account.to_string(), expected + 1);
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use super::program_storage::entry_hash;
+use super::MerkleProof;
+use crate::sequencer::ProgramStatus;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+/// A leaf of the tree behind [`State::root`](super::State::root).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StateLeaf {
+    Counters { balance: u64, nonce: u64, deposit_nonce: u64, forced_queue_index: u64 },
+    Account { account: String, balance: u64, nonce: u64, withdrawal_nonce: u64 },
+    Storage { program_id: String, storage_root: [u8; 32] },
+    Registry { name: String, entry_hash: [u8; 32] },
+    Program { program_id: String, program_hash: [u8; 32] },
+    ProgramStatus { program_id: String, status: ProgramStatus },
+}
+
+impl StateLeaf {
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        match self {
+            StateLeaf::Counters { balance, nonce, deposit_nonce, forced_queue_index } => {
+                hasher.update([0u8, 0u8]);
+                for value in [balance, nonce, deposit_nonce, forced_queue_index] {
+                    hasher.update(value.to_le_bytes());
+                }
+            }
+            StateLeaf::Account { account, balance, nonce, withdrawal_nonce } => {
+                hasher.update([0u8, 1u8]);
+                hasher.update((account.len() as u32).to_le_bytes());
+                hasher.update(account.as_bytes());
+                hasher.update(balance.to_le_bytes());
+                hasher.update(nonce.to_le_bytes());
+                hasher.update(withdrawal_nonce.to_le_bytes());
+            }
+            StateLeaf::Storage { program_id, storage_root } => {
+                hasher.update([0u8, 2u8]);
+                hasher.update((program_id.len() as u32).to_le_bytes());
+                hasher.update(program_id.as_bytes());
+                hasher.update(storage_root);
+            }
+            StateLeaf::Registry { name, entry_hash } => {
+                hasher.update([0u8, 3u8]);
+                hasher.update((name.len() as u32).to_le_bytes());
+                hasher.update(name.as_bytes());
+                hasher.update(entry_hash);
+            }
+            StateLeaf::Program { program_id, program_hash } => {
+                hasher.update([0u8, 4u8]);
+                hasher.update((program_id.len() as u32).to_le_bytes());
+                hasher.update(program_id.as_bytes());
+                hasher.update(program_hash);
+            }
+            StateLeaf::ProgramStatus { program_id, status } => {
+                hasher.update([0u8, 5u8]);
+                hasher.update((program_id.len() as u32).to_le_bytes());
+                hasher.update(program_id.as_bytes());
+                match status {
+                    ProgramStatus::Submitted => hasher.update([0u8]),
+                    ProgramStatus::Validated => hasher.update([1u8]),
+                    ProgramStatus::Included { batch_number } => {
+                        hasher.update([2u8]);
+                        hasher.update(batch_number.to_le_bytes());
+                    }
+                    ProgramStatus::Deployed { batch_number } => {
+                        hasher.update([3u8]);
+                        hasher.update(batch_number.to_le_bytes());
+                    }
+                    ProgramStatus::Rejected { reason } => {
+                        hasher.update([4u8]);
+                        hasher.update((reason.len() as u32).to_le_bytes());
+                        hasher.update(reason.as_bytes());
+                    }
+                }
+            }
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// Proof that `leaf` is part of the state with root `state_root`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateProof {
+    pub state_root: [u8; 32],
+    pub leaf: StateLeaf,
+    pub proof: MerkleProof,
+}
+
+impl StateProof {
+    pub fn verify(&self) -> bool {
+        self.proof.root(self.leaf.hash()) == self.state_root
+    }
+}
+
+/// Proof of a single program storage entry: the entry against the
+/// program's storage root, and that root against the state root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageProof {
+    pub program: StateProof,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub proof: MerkleProof,
+}
+
+impl StorageProof {
+    pub fn verify(&self) -> bool {
+        match &self.program.leaf {
+            StateLeaf::Storage { storage_root, .. } => {
+                self.program.verify() && self.proof.root(entry_hash(&self.key, &self.value)) == *storage_root
+            }
+            _ => false,
+        }
+    }
+}
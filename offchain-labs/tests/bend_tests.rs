@@ -0,0 +1,888 @@
+use ark_ff::PrimeField;
+use ark_std::rand::thread_rng;
+use offchain_labs::bend::{BendProgram, CallContext, Event, ExecutionBackend, ProgramMetadata, ProgramRegistry};
+use offchain_labs::bend::registry::{freeze_message, transfer_message, SignedDeployment};
+use offchain_labs::bend::compiler::{self, SourceLanguage};
+use offchain_labs::bend::{host, metering, optimizer};
+use offchain_labs::bend::trace::{ExecutionTrace, TraceEvent};
+use offchain_labs::config::{CompilerConfig, ExecutionConfig};
+use offchain_labs::crypto::SigningKey;
+use offchain_labs::error::HVMError;
+use offchain_labs::zk_rollup::{ProgramStorage, StorageChanges};
+use std::sync::Arc;
+
+fn create_program(wat: &str) -> BendProgram {
+    BendProgram::new(
+        wat::parse_str(wat).unwrap(),
+        ProgramMetadata {
+            name: "Test Program".to_string(),
+            version: "1.0.0".to_string(),
+            description: "Bend program for test".to_string(),
+            source: None,
+        },
+        "Test Author".to_string(),
+    )
+}
+
+fn create_abi_program(body: &str) -> BendProgram {
+    create_program(&format!("(module {} {})", body, ALLOCATOR))
+}
+
+fn with_version(program: &BendProgram, version: &str, author: &str) -> BendProgram {
+    BendProgram::new(
+        program.bytecode.clone(),
+        ProgramMetadata { version: version.to_string(), ..program.metadata.clone() },
+        author.to_string(),
+    )
+}
+
+const ALLOCATOR: &str = r#"
+    (global $heap (mut i32) (i32.const 1024))
+    (func (export "alloc") (param $len i32) (result i32)
+        (global.get $heap)
+        (global.set $heap (i32.add (global.get $heap) (local.get $len))))
+"#;
+
+const COUNTER_PROGRAM: &str = r#"
+    (memory (export "memory") 1)
+    (func (export "run") (param i32 i32) (result i32 i32)
+        (local $i i32)
+        (block $done
+            (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (i32.const 100)))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+        (i32.store (i32.const 0) (local.get $i))
+        (i32.const 0)
+        (i32.const 32))
+"#;
+
+#[test]
+fn test_execution_gas_is_deterministic() {
+    let program = create_abi_program(COUNTER_PROGRAM);
+    let config = ExecutionConfig::default();
+
+    let first = program.execute(Vec::new(), &config).unwrap();
+    let second = program.execute(Vec::new(), &config).unwrap();
+
+    assert!(first.gas_used > 0, "Gas used should be positive");
+    assert_eq!(first.gas_used, second.gas_used, "Gas used should not vary between runs");
+    assert_eq!(first.outputs, second.outputs);
+    assert_eq!(first.outputs.len(), 1);
+}
+
+#[test]
+fn test_execution_out_of_gas() {
+    let program = create_abi_program(COUNTER_PROGRAM);
+    let used = program.execute(Vec::new(), &ExecutionConfig::default()).unwrap().gas_used;
+
+    let config = ExecutionConfig { gas_limit: used - 1, ..ExecutionConfig::default() };
+    match program.execute(Vec::new(), &config) {
+        Err(HVMError::OutOfGas(limit)) => assert_eq!(limit, used - 1),
+        other => panic!("Expected out of gas, got {:?}", other),
+    }
+
+    let config = ExecutionConfig { gas_limit: used, ..ExecutionConfig::default() };
+    assert_eq!(program.execute(Vec::new(), &config).unwrap().gas_used, used);
+}
+
+#[test]
+fn test_validation_accepts_deterministic_module() {
+    let program = create_abi_program(COUNTER_PROGRAM);
+    assert!(program.validate(&ExecutionConfig::default()).is_ok());
+}
+
+#[test]
+fn test_validation_rejects_floats() {
+    let program = create_abi_program(r#"
+        (memory (export "memory") 1)
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (drop (f64.add (f64.const 0.1) (f64.const 0.2)))
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+    assert!(matches!(program.validate(&ExecutionConfig::default()), Err(HVMError::Validation(_))));
+}
+
+#[test]
+fn test_validation_rejects_disallowed_imports() {
+    let program = create_abi_program(r#"
+        (import "wasi_snapshot_preview1" "clock_time_get" (func $clock (param i32 i64 i32) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+    assert!(matches!(program.validate(&ExecutionConfig::default()), Err(HVMError::Validation(_))));
+}
+
+#[test]
+fn test_validation_rejects_oversized_memory_and_too_many_functions() {
+    let config = ExecutionConfig { max_memory_pages: 4, max_functions: 2, ..ExecutionConfig::default() };
+
+    let large_memory = create_abi_program(r#"
+        (memory (export "memory") 1 64)
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+    assert!(matches!(large_memory.validate(&config), Err(HVMError::Validation(_))));
+
+    let many_functions = create_abi_program(r#"
+        (memory (export "memory") 1)
+        (func $a)
+        (func $b)
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (call $a)
+            (call $b)
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+    assert!(matches!(many_functions.validate(&config), Err(HVMError::Validation(_))));
+}
+
+#[test]
+fn test_execution_stack_depth_limit() {
+    let program = create_abi_program(r#"
+        (memory (export "memory") 1)
+        (func $recurse (param $n i32)
+            (if (local.get $n)
+                (then (call $recurse (i32.sub (local.get $n) (i32.const 1))))))
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (call $recurse (i32.const 50))
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+
+    assert!(program.execute(Vec::new(), &ExecutionConfig::default()).is_ok());
+
+    let config = ExecutionConfig { max_stack_depth: 10, ..ExecutionConfig::default() };
+    match program.execute(Vec::new(), &config) {
+        Err(HVMError::StackOverflow(limit)) => assert_eq!(limit, 10),
+        other => panic!("Expected stack overflow, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_execution_memory_growth_limit() {
+    let program = create_abi_program(r#"
+        (memory (export "memory") 1)
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (i32.store (i32.const 0) (memory.grow (i32.const 8)))
+            (i32.const 0)
+            (i32.const 32))
+    "#);
+
+    let config = ExecutionConfig { max_memory_pages: 4, ..ExecutionConfig::default() };
+    let result = program.execute(Vec::new(), &config).unwrap();
+    assert_eq!(result.memory_usage, 0, "Memory should not grow past the limit");
+    assert_eq!(result.outputs[0], ark_bn254::Fr::from(u32::MAX as u64), "memory.grow should return -1 inside the guest");
+}
+
+const ECHO_PROGRAM: &str = r#"
+    (memory (export "memory") 1)
+    (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+        (local.get $ptr)
+        (local.get $len))
+"#;
+
+#[test]
+fn test_execution_passes_inputs_through_abi() {
+    let program = create_abi_program(ECHO_PROGRAM);
+    assert!(program.validate(&ExecutionConfig::default()).is_ok());
+
+    let mut inputs = vec![0u8; 64];
+    inputs[0] = 7;
+    inputs[32] = 9;
+    let result = program.execute(inputs, &ExecutionConfig::default()).unwrap();
+    assert_eq!(result.outputs, vec![ark_bn254::Fr::from(7u64), ark_bn254::Fr::from(9u64)]);
+}
+
+#[test]
+fn test_execution_rejects_malformed_outputs() {
+    let program = create_abi_program(ECHO_PROGRAM);
+    match program.execute(vec![1, 2, 3], &ExecutionConfig::default()) {
+        Err(HVMError::Abi(_)) => {}
+        other => panic!("Expected ABI error for a partial field element, got {:?}", other),
+    }
+
+    let out_of_bounds = create_abi_program(r#"
+        (memory (export "memory") 1)
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (i32.const 65504)
+            (i32.const 64))
+    "#);
+    match out_of_bounds.execute(Vec::new(), &ExecutionConfig::default()) {
+        Err(HVMError::Abi(_)) => {}
+        other => panic!("Expected ABI error for an out of bounds output, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_validation_rejects_abi_mismatch() {
+    let wrong_signature = create_abi_program(r#"
+        (memory (export "memory") 1)
+        (func (export "run") (result i32 i32)
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+    assert!(matches!(wrong_signature.validate(&ExecutionConfig::default()), Err(HVMError::Abi(_))));
+    assert!(matches!(wrong_signature.execute(Vec::new(), &ExecutionConfig::default()), Err(HVMError::Abi(_))));
+
+    let missing_alloc = create_program(r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "run") (param i32 i32) (result i32 i32)
+                (i32.const 0)
+                (i32.const 0)))
+    "#);
+    assert!(matches!(missing_alloc.validate(&ExecutionConfig::default()), Err(HVMError::Abi(_))));
+}
+
+#[test]
+fn test_execution_uses_module_cache() {
+    let cache_dir = std::env::temp_dir().join(format!("hvm_module_cache_{}", std::process::id()));
+    let config = ExecutionConfig { cache_dir: Some(cache_dir.clone()), ..ExecutionConfig::default() };
+    let program = create_abi_program(COUNTER_PROGRAM);
+
+    let uncached = program.execute(Vec::new(), &ExecutionConfig::default()).unwrap();
+    let first = program.execute(Vec::new(), &config).unwrap();
+    let artifacts = std::fs::read_dir(&cache_dir).unwrap().count();
+    assert_eq!(artifacts, 1, "Compiled module should be written to the cache");
+
+    let second = program.execute(Vec::new(), &config).unwrap();
+    assert_eq!(first.outputs, uncached.outputs);
+    assert_eq!(first.gas_used, uncached.gas_used);
+    assert_eq!(second.outputs, first.outputs);
+    assert_eq!(second.gas_used, first.gas_used);
+
+    for entry in std::fs::read_dir(&cache_dir).unwrap() {
+        std::fs::write(entry.unwrap().path(), b"corrupted").unwrap();
+    }
+    let recompiled = program.execute(Vec::new(), &config).unwrap();
+    assert_eq!(recompiled.gas_used, first.gas_used, "Corrupted artifacts should be recompiled");
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+const BRANCHING_PROGRAM: &str = r#"
+    (memory (export "memory") 1)
+    (func $classify (param $n i32) (result i32)
+        (if (result i32) (i32.and (local.get $n) (i32.const 1))
+            (then (i32.mul (local.get $n) (i32.const 3)))
+            (else (i32.div_u (local.get $n) (i32.const 2)))))
+    (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+        (local $i i32)
+        (local $acc i32)
+        (local.set $acc (i32.load (local.get $ptr)))
+        (block $done
+            (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (i32.const 10)))
+                (local.set $acc (call $classify (local.get $acc)))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+        (i64.store offset=8 (i32.const 0) (i64.extend_i32_u (local.get $acc)))
+        (i32.const 8)
+        (i32.const 32))
+"#;
+
+#[test]
+fn test_traced_execution_matches_untraced() {
+    let program = create_abi_program(BRANCHING_PROGRAM);
+    let config = ExecutionConfig::default();
+    let mut inputs = vec![0u8; 32];
+    inputs[0] = 27;
+
+    let untraced = program.execute(inputs.clone(), &config).unwrap();
+    let (traced, trace) = program.execute_traced(inputs, &Arc::new(CallContext::new()), &config);
+    let traced = traced.unwrap();
+
+    assert_eq!(traced.outputs, untraced.outputs);
+    assert_eq!(traced.gas_used, untraced.gas_used, "Tracing should not change gas usage");
+    assert!(!trace.is_empty());
+
+    let events: Vec<TraceEvent> = trace.events().collect();
+    assert_eq!(events.len(), trace.len());
+    assert!(events.contains(&TraceEvent::MemoryRead { address: 1024, value: 27, width: 4 }));
+    let written = untraced.outputs[0].into_bigint().0[0];
+    assert!(events.contains(&TraceEvent::MemoryWrite { address: 8, value: written, width: 8 }));
+
+    let decoded = ExecutionTrace::from_bytes(trace.as_bytes().to_vec()).unwrap();
+    assert_eq!(decoded, trace);
+    assert!(ExecutionTrace::from_bytes(vec![0x02, 0x80]).is_err());
+}
+
+#[test]
+fn test_traced_execution_reports_failures() {
+    let program = create_abi_program(BRANCHING_PROGRAM);
+    let inputs = vec![0u8; 32];
+    let used = program.execute(inputs.clone(), &ExecutionConfig::default()).unwrap().gas_used;
+
+    let config = ExecutionConfig { gas_limit: used - 1, ..ExecutionConfig::default() };
+    let (result, trace) = program.execute_traced(inputs.clone(), &Arc::new(CallContext::new()), &config);
+    assert!(matches!(program.execute(inputs, &config), Err(HVMError::OutOfGas(_))));
+    assert!(matches!(result, Err(HVMError::OutOfGas(_))));
+    assert!(!trace.is_empty(), "Trace should cover execution up to the failure");
+
+    let recursive = create_abi_program(r#"
+        (memory (export "memory") 1)
+        (func $recurse (param $n i32)
+            (if (local.get $n)
+                (then (call $recurse (i32.sub (local.get $n) (i32.const 1))))))
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (call $recurse (i32.const 10))
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+    let config = ExecutionConfig { max_stack_depth: 11, ..ExecutionConfig::default() };
+    assert!(recursive.execute_traced(Vec::new(), &Arc::new(CallContext::new()), &config).0.is_ok());
+    let config = ExecutionConfig { max_stack_depth: 10, ..ExecutionConfig::default() };
+    assert!(matches!(recursive.execute(Vec::new(), &config), Err(HVMError::StackOverflow(10))));
+    assert!(matches!(recursive.execute_traced(Vec::new(), &Arc::new(CallContext::new()), &config).0, Err(HVMError::StackOverflow(10))));
+}
+
+const STORAGE_COUNTER_PROGRAM: &str = r#"
+    (import "hvm" "storage_read" (func $read (param i32 i32 i32 i32) (result i32)))
+    (import "hvm" "storage_write" (func $write (param i32 i32 i32 i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 0) "count")
+    (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+        (local $count i32)
+        (if (i32.ge_s (call $read (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 4)) (i32.const 0))
+            (then (local.set $count (i32.load (i32.const 16)))))
+        (local.set $count (i32.add (local.get $count) (i32.const 1)))
+        (i32.store (i32.const 16) (local.get $count))
+        (call $write (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 4))
+        (if (i32.and (i32.gt_u (local.get $len) (i32.const 0)) (i32.load8_u (local.get $ptr)))
+            (then unreachable))
+        (i32.store (i32.const 32) (local.get $count))
+        (i32.const 32)
+        (i32.const 32))
+"#;
+
+#[test]
+fn test_execution_persists_storage_writes() {
+    let program = create_abi_program(STORAGE_COUNTER_PROGRAM);
+    let config = ExecutionConfig::default();
+    assert!(program.validate(&config).is_ok());
+
+    let mut context = CallContext::new();
+    for expected in 1u64..=2 {
+        let result = program.execute_in(Vec::new(), &Arc::new(context.clone()), &config).unwrap();
+        assert_eq!(result.outputs[0], ark_bn254::Fr::from(expected));
+        context.apply_storage_changes(&result.storage_changes);
+    }
+    let storage = context.storage(program.id()).cloned().unwrap();
+    assert_eq!(storage.get(b"count"), Some(&[2u8, 0, 0, 0][..]));
+
+    let context = Arc::new(context);
+    let trapped = program.execute_in(vec![1], &context, &config);
+    assert!(trapped.is_err(), "Call should trap after writing");
+    assert_eq!(storage.get(b"count"), Some(&[2u8, 0, 0, 0][..]), "Writes of a trapped call should be discarded");
+
+    let untraced = program.execute_in(Vec::new(), &context, &config).unwrap();
+    let (traced, trace) = program.execute_traced(Vec::new(), &context, &config);
+    let traced = traced.unwrap();
+    assert_eq!(traced.outputs, untraced.outputs);
+    assert_eq!(traced.storage_changes, untraced.storage_changes);
+    assert_eq!(traced.gas_used, untraced.gas_used, "Host calls should cost the same with tracing");
+    assert_eq!(trace.events().filter(|event| matches!(event, TraceEvent::HostCall { .. })).count(), 2);
+}
+
+/// Writes a 16 KiB value under as many keys as the first input byte says.
+const HEAVY_WRITER_PROGRAM: &str = r#"
+    (import "hvm" "storage_write" (func $write (param i32 i32 i32 i32)))
+    (memory (export "memory") 1)
+    (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+        (local $n i32)
+        (local.set $n (i32.load8_u (local.get $ptr)))
+        (block $done
+            (loop $next
+                (br_if $done (i32.eqz (local.get $n)))
+                (call $write (local.get $n) (i32.const 4) (i32.const 2048) (i32.const 16384))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                (br $next)))
+        (i32.const 0)
+        (i32.const 0))
+"#;
+
+#[test]
+fn test_storage_writes_are_charged_per_byte() {
+    let program = create_abi_program(HEAVY_WRITER_PROGRAM);
+    let config = ExecutionConfig::default();
+    let context = Arc::new(CallContext::new());
+
+    let one = program.execute_in(vec![1], &context, &config).unwrap();
+    let two = program.execute_in(vec![2], &context, &config).unwrap();
+    let write_cost = two.gas_used - one.gas_used;
+    assert!(write_cost > 16 * 1024 * 10, "A write should be charged for its bytes, not just the call: {}", write_cost);
+
+    let writes = (config.gas_limit / write_cost + 1) as u8;
+    assert!(matches!(program.execute_in(vec![writes], &context, &config), Err(HVMError::OutOfGas(_))));
+    let (traced, _) = program.execute_traced(vec![writes], &context, &config);
+    assert!(matches!(traced, Err(HVMError::OutOfGas(_))), "Traced executions should run out of gas the same way");
+}
+
+/// Calls the program whose id follows the first input byte, passing the whole
+/// input on, and returns one more than the callee's output or zero if the
+/// call failed.
+const FORWARDING_PROGRAM: &str = r#"
+    (import "hvm" "call" (func $call (param i32 i32 i32 i32 i64 i32 i32) (result i32)))
+    (memory (export "memory") 1)
+    (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+        (if (i32.lt_s
+                (call $call
+                    (i32.add (local.get $ptr) (i32.const 1)) (i32.sub (local.get $len) (i32.const 1))
+                    (local.get $ptr) (local.get $len)
+                    (i64.const 100000)
+                    (i32.const 0) (i32.const 32))
+                (i32.const 0))
+            (then (i32.store (i32.const 0) (i32.const 0)))
+            (else (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))))
+        (i32.const 0)
+        (i32.const 32))
+"#;
+
+fn call_input(flag: u8, program: &BendProgram) -> Vec<u8> {
+    let mut input = vec![flag];
+    input.extend_from_slice(program.id().as_bytes());
+    input
+}
+
+#[test]
+fn test_cross_program_call() {
+    let counter = create_abi_program(STORAGE_COUNTER_PROGRAM);
+    let forwarder = create_abi_program(FORWARDING_PROGRAM);
+    let config = ExecutionConfig::default();
+    assert!(forwarder.validate(&config).is_ok());
+
+    let mut context = CallContext::new();
+    context.add_program(counter.clone());
+    context.add_program(forwarder.clone());
+    let context = Arc::new(context);
+
+    let direct = counter.execute_in(Vec::new(), &context, &config).unwrap();
+    let result = forwarder.execute_in(call_input(0, &counter), &context, &config).unwrap();
+    assert_eq!(result.outputs[0], ark_bn254::Fr::from(2u64));
+    assert!(result.gas_used > direct.gas_used, "Callee gas should be charged to the caller");
+    assert_eq!(result.storage_changes.get(counter.id()), direct.storage_changes.get(counter.id()));
+    assert!(!result.storage_changes.contains_key(forwarder.id()));
+
+    let (traced, _) = forwarder.execute_traced(call_input(0, &counter), &context, &config);
+    let traced = traced.unwrap();
+    assert_eq!(traced.gas_used, result.gas_used);
+    assert_eq!(traced.storage_changes, result.storage_changes);
+}
+
+#[test]
+fn test_failed_cross_program_call_reverts_writes() {
+    let counter = create_abi_program(STORAGE_COUNTER_PROGRAM);
+    let forwarder = create_abi_program(FORWARDING_PROGRAM);
+    let config = ExecutionConfig::default();
+
+    let mut context = CallContext::new();
+    context.add_program(counter.clone());
+    context.add_program(forwarder.clone());
+    let context = Arc::new(context);
+
+    let result = forwarder.execute_in(call_input(1, &counter), &context, &config).unwrap();
+    assert_eq!(result.outputs[0], ark_bn254::Fr::from(0u64));
+    assert!(result.storage_changes.is_empty(), "Writes of a failed callee should be discarded");
+    assert!(result.gas_used >= 100_000, "A failed callee should consume its whole allowance");
+
+    let unknown = create_abi_program(ECHO_PROGRAM);
+    let result = forwarder.execute_in(call_input(0, &unknown), &context, &config).unwrap();
+    assert_eq!(result.outputs[0], ark_bn254::Fr::from(0u64));
+}
+
+#[test]
+fn test_cross_program_call_depth_limit() {
+    let forwarder = create_abi_program(FORWARDING_PROGRAM);
+    let mut context = CallContext::new();
+    context.add_program(forwarder.clone());
+    let context = Arc::new(context);
+
+    for max_call_depth in [0, 3] {
+        let config = ExecutionConfig { max_call_depth, ..ExecutionConfig::default() };
+        let result = forwarder.execute_in(call_input(0, &forwarder), &context, &config).unwrap();
+        assert_eq!(result.outputs[0], ark_bn254::Fr::from(max_call_depth as u64));
+    }
+}
+
+/// Emits a `ping` event carrying the input, then traps if the first input
+/// byte is set.
+const EMITTING_PROGRAM: &str = r#"
+    (import "hvm" "emit" (func $emit (param i32 i32 i32 i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 0) "ping")
+    (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+        (call $emit (i32.const 0) (i32.const 4) (local.get $ptr) (local.get $len))
+        (if (i32.and (i32.gt_u (local.get $len) (i32.const 0)) (i32.load8_u (local.get $ptr)))
+            (then unreachable))
+        (i32.const 0)
+        (i32.const 0))
+"#;
+
+#[test]
+fn test_emitted_events() {
+    let emitter = create_abi_program(EMITTING_PROGRAM);
+    let forwarder = create_abi_program(FORWARDING_PROGRAM);
+    let config = ExecutionConfig::default();
+    assert!(emitter.validate(&config).is_ok());
+
+    let mut context = CallContext::new();
+    context.add_program(emitter.clone());
+    context.add_program(forwarder.clone());
+    let context = Arc::new(context);
+
+    let result = emitter.execute_in(vec![0, 7], &context, &config).unwrap();
+    let event = Event { program_id: emitter.id().to_string(), topic: b"ping".to_vec(), data: vec![0, 7] };
+    assert_eq!(result.events, vec![event]);
+    assert!(emitter.execute_in(vec![1], &context, &config).is_err());
+
+    let (traced, _) = emitter.execute_traced(vec![0, 7], &context, &config);
+    let traced = traced.unwrap();
+    assert_eq!(traced.events, result.events);
+    assert_eq!(traced.gas_used, result.gas_used);
+    let long = emitter.execute_in(vec![0; 1002], &context, &config).unwrap();
+    assert!(
+        long.gas_used - result.gas_used >= 1000 * metering::storage_byte_cost(host::EMIT),
+        "An event should be charged for its bytes, not just the call"
+    );
+
+    let input = call_input(0, &emitter);
+    let result = forwarder.execute_in(input.clone(), &context, &config).unwrap();
+    assert_eq!(result.events.len(), 1);
+    assert_eq!(result.events[0].program_id, emitter.id(), "Events should name the program that emitted them");
+    assert_eq!(result.events[0].data, input);
+    let result = forwarder.execute_in(call_input(1, &emitter), &context, &config).unwrap();
+    assert!(result.events.is_empty(), "Events of a failed callee should be discarded");
+}
+
+#[test]
+fn test_registry_tracks_version_history() {
+    let alice = SigningKey::generate(&mut thread_rng());
+    let mallory = SigningKey::generate(&mut thread_rng());
+    let first = with_version(&create_abi_program(ECHO_PROGRAM), "1.0.0", "Alice");
+    let second = with_version(&create_abi_program(STORAGE_COUNTER_PROGRAM), "1.1.0", "Alice");
+    let mut registry = ProgramRegistry::new();
+
+    registry.register(&SignedDeployment::new(first.clone(), &alice, 0)).unwrap();
+    let nonce = registry.nonce("Test Program");
+    assert!(matches!(registry.register(&SignedDeployment::new(second.clone(), &mallory, nonce)), Err(HVMError::Registry(_))));
+    assert!(matches!(registry.register(&SignedDeployment::new(with_version(&second, "0.9.0", "Alice"), &alice, nonce)), Err(HVMError::Registry(_))));
+    assert!(matches!(registry.register(&SignedDeployment::new(with_version(&second, "latest", "Alice"), &alice, nonce)), Err(HVMError::Registry(_))));
+    registry.register(&SignedDeployment::new(second.clone(), &alice, nonce)).unwrap();
+
+    let history: Vec<String> = registry.versions("Test Program").unwrap().iter()
+        .map(|entry| entry.version.to_string())
+        .collect();
+    assert_eq!(history, ["1.0.0", "1.1.0"]);
+    assert_eq!(registry.resolve_reference("Test Program").unwrap().program_id, second.id());
+    assert_eq!(registry.resolve_reference("Test Program@=1.0.0").unwrap().program_id, first.id());
+    assert_eq!(registry.resolve_reference("Test Program@^1.0").unwrap().program_id, second.id());
+    assert!(registry.resolve_reference("Test Program@^2").is_err());
+    assert!(registry.resolve_reference("Other Program").is_err());
+}
+
+#[test]
+fn test_registry_requires_owner_signatures() {
+    let alice = SigningKey::generate(&mut thread_rng());
+    let bob = SigningKey::generate(&mut thread_rng());
+    let program = with_version(&create_abi_program(ECHO_PROGRAM), "1.0.0", "Alice");
+    let mut registry = ProgramRegistry::new();
+
+    let mut forged = SignedDeployment::new(program.clone(), &bob, 0);
+    forged.owner = alice.verifying_key();
+    forged.program.author = alice.verifying_key().to_string();
+    assert!(matches!(registry.register(&forged), Err(HVMError::Signature(_))));
+    let mut misattributed = SignedDeployment::new(program.clone(), &alice, 0);
+    misattributed.program.author = "Alice".to_string();
+    assert!(matches!(registry.register(&misattributed), Err(HVMError::Registry(_))), "The author should be the signing key");
+    let tampered = SignedDeployment {
+        program: with_version(&program, "1.0.1", "Alice"),
+        ..SignedDeployment::new(program.clone(), &alice, 0)
+    };
+    assert!(registry.register(&tampered).is_err());
+    registry.register(&SignedDeployment::new(program.clone(), &alice, 0)).unwrap();
+
+    let name = "Test Program";
+    let transfer = transfer_message(name, &bob.verifying_key(), registry.nonce(name));
+    assert!(registry.transfer_ownership(name, bob.verifying_key(), &bob.sign(&transfer)).is_err());
+    registry.transfer_ownership(name, bob.verifying_key(), &alice.sign(&transfer)).unwrap();
+    assert_eq!(registry.entry(name).unwrap().owner, bob.verifying_key());
+    assert!(registry.transfer_ownership(name, bob.verifying_key(), &alice.sign(&transfer)).is_err(), "Signatures should not be replayable");
+
+    let upgrade = with_version(&create_abi_program(COUNTER_PROGRAM), "1.1.0", "Alice");
+    assert!(registry.register(&SignedDeployment::new(upgrade.clone(), &alice, registry.nonce(name))).is_err());
+
+    registry.freeze(name, &bob.sign(&freeze_message(name, registry.nonce(name)))).unwrap();
+    assert!(registry.entry(name).unwrap().frozen);
+    assert!(registry.register(&SignedDeployment::new(upgrade, &bob, registry.nonce(name))).is_err());
+    let transfer = transfer_message(name, &alice.verifying_key(), registry.nonce(name));
+    assert!(registry.transfer_ownership(name, alice.verifying_key(), &bob.sign(&transfer)).is_err());
+}
+
+#[test]
+fn test_cross_program_call_by_pinned_version() {
+    let counter = with_version(&create_abi_program(STORAGE_COUNTER_PROGRAM), "1.0.0", "Alice");
+    let upgraded = with_version(&create_abi_program(ECHO_PROGRAM), "2.0.0", "Alice");
+    let forwarder = create_abi_program(FORWARDING_PROGRAM);
+    let alice = SigningKey::generate(&mut thread_rng());
+    let mut registry = ProgramRegistry::new();
+    registry.register(&SignedDeployment::new(counter.clone(), &alice, 0)).unwrap();
+    registry.register(&SignedDeployment::new(upgraded.clone(), &alice, 1)).unwrap();
+
+    let mut context = CallContext::new();
+    context.add_program(counter.clone());
+    context.add_program(upgraded);
+    context.add_program(forwarder.clone());
+    context.set_registry(registry);
+    let context = Arc::new(context);
+
+    let result = forwarder.execute_in(b"\0Test Program@^1".to_vec(), &context, &ExecutionConfig::default()).unwrap();
+    assert_eq!(result.outputs[0], ark_bn254::Fr::from(2u64));
+    assert!(result.storage_changes.contains_key(counter.id()));
+}
+
+#[test]
+fn test_optimizer_keeps_table_functions_and_branches() {
+    let program = create_abi_program(r#"
+        (import "hvm" "storage_remove" (func $remove (param i32 i32)))
+        (memory (export "memory") 1)
+        (type $select (func (param i64) (result i64)))
+        (table 2 funcref)
+        (elem (i32.const 0) $double $negate)
+        (func $double (param $x i64) (result i64)
+            (i64.mul (local.get $x) (i64.add (i64.const 1) (i64.const 1))))
+        (func $negate (param $x i64) (result i64)
+            (i64.sub (i64.const 0) (local.get $x)))
+        (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+            (if (i32.eqz (i32.load8_u (local.get $ptr)))
+                (then
+                    (i64.store (i32.const 0) (call_indirect (type $select) (i64.const 21) (i32.const 0)))
+                    (br 0)
+                    (i64.store (i32.const 0) (i64.const 99)))
+                (else
+                    (i64.store (i32.const 0) (call_indirect (type $select) (i64.const 21) (i32.const 1)))))
+            (i32.const 0)
+            (i32.const 32))
+    "#);
+    let config = ExecutionConfig::default();
+    let (bytecode, stats) = optimizer::optimize(&program.bytecode).unwrap();
+    let optimized = BendProgram::new(bytecode, program.metadata.clone(), program.author.clone());
+
+    assert_eq!(stats.removed_functions, 0, "Functions in element segments should be kept");
+    assert_eq!(stats.removed_imports, 1);
+    assert_eq!(stats.folded_constants, 1);
+    assert_eq!(stats.removed_instructions, 3);
+    assert!(optimized.validate(&config).is_ok());
+    for inputs in [vec![0], vec![1]] {
+        let expected = program.execute(inputs.clone(), &config).unwrap();
+        let actual = optimized.execute(inputs, &config).unwrap();
+        assert_eq!(expected.outputs, actual.outputs);
+        assert!(actual.gas_used <= expected.gas_used);
+    }
+    assert!(optimizer::optimize(&[0, 97, 115, 109]).is_err());
+}
+
+#[test]
+fn test_validation_rejects_mismatched_host_function() {
+    let program = create_abi_program(r#"
+        (import "hvm" "storage_write" (func $write (param i32 i32)))
+        (memory (export "memory") 1)
+        (func (export "run") (param i32 i32) (result i32 i32)
+            (i32.const 0)
+            (i32.const 0))
+    "#);
+    assert!(matches!(program.validate(&ExecutionConfig::default()), Err(HVMError::Validation(_))));
+}
+
+#[test]
+fn test_storage_root_is_order_independent() {
+    assert_eq!(ProgramStorage::new().root(), [0u8; 32]);
+
+    let mut first = StorageChanges::new();
+    first.insert(b"a".to_vec(), Some(b"1".to_vec()));
+    first.insert(b"b".to_vec(), Some(b"2".to_vec()));
+    let mut second = StorageChanges::new();
+    second.insert(b"c".to_vec(), Some(b"3".to_vec()));
+    second.insert(b"a".to_vec(), None);
+
+    let mut forward = ProgramStorage::new();
+    forward.apply(&first);
+    let root_before = forward.root();
+    forward.apply(&second);
+    assert_ne!(forward.root(), root_before);
+
+    let mut direct = StorageChanges::new();
+    direct.insert(b"c".to_vec(), Some(b"3".to_vec()));
+    direct.insert(b"b".to_vec(), Some(b"2".to_vec()));
+    let mut other = ProgramStorage::new();
+    other.apply(&direct);
+    assert_eq!(forward.root(), other.root());
+    assert_eq!(forward.len(), 2);
+}
+
+/// Writes stand-ins for the `bend` and `hvm` binaries. The HVM compiler emits
+/// whatever module is stored in `main.wasm` next to it, and rejects nets
+/// without a `@main` definition.
+#[cfg(unix)]
+fn fake_toolchain(dir: &std::path::Path, bytecode: &[u8]) -> CompilerConfig {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("main.wasm"), bytecode).unwrap();
+    let scripts = [
+        ("bend", "--version) echo 'bend 0.2.37' ;;\n  gen-hvm) sed 's/^def main/@main/' \"$2\" ;;"),
+        ("hvm", &format!(
+            "--version) echo 'hvm 2.0.22' ;;\n  gen-wasm) grep -q '^@main' \"$2\" || {{ echo 'missing @main' >&2; exit 1; }}; cat '{}' ;;",
+            dir.join("main.wasm").display(),
+        )),
+    ];
+    for (name, cases) in scripts {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\ncase \"$1\" in\n  {}\n  *) exit 2 ;;\nesac\n", cases)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    CompilerConfig { bend_path: dir.join("bend"), hvm_path: dir.join("hvm") }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_program_from_source_is_reproducible() {
+    let dir = std::env::temp_dir().join(format!("hvm_toolchain_{}", std::process::id()));
+    let expected = create_abi_program(COUNTER_PROGRAM);
+    let config = fake_toolchain(&dir, &expected.bytecode);
+    let metadata = ProgramMetadata { source: None, ..expected.metadata.clone() };
+    let source = "def main():\n  return 100\n";
+
+    let program = BendProgram::from_source(SourceLanguage::Bend, source, metadata.clone(), "Test Author".to_string(), &config).unwrap();
+    assert_eq!(program.id(), expected.id());
+    let info = program.metadata.source.clone().unwrap();
+    assert_eq!(info.language, SourceLanguage::Bend);
+    assert_eq!(info.source_hash, compiler::hash_source(source));
+    assert_eq!(info.compiler, "bend 0.2.37; hvm 2.0.22");
+    assert!(program.verify_source(source, &config).is_ok());
+    assert!(matches!(program.verify_source("def main():\n  return 1\n", &config), Err(HVMError::Compilation(_))));
+
+    let net = BendProgram::from_source(SourceLanguage::Hvm, "@main = 100\n", metadata.clone(), "Test Author".to_string(), &config).unwrap();
+    assert_eq!(net.id(), expected.id());
+    assert_eq!(net.metadata.source.as_ref().unwrap().compiler, "hvm 2.0.22");
+    assert_eq!(SourceLanguage::from_path(std::path::Path::new("main.hvm")).unwrap(), SourceLanguage::Hvm);
+    assert!(SourceLanguage::from_path(std::path::Path::new("main.wasm")).is_err());
+
+    let error = BendProgram::from_source(SourceLanguage::Hvm, "@other = 1\n", metadata, "Test Author".to_string(), &config).unwrap_err();
+    assert!(error.to_string().contains("missing @main"));
+
+    // A toolchain that produces different bytecode cannot reproduce the program.
+    std::fs::write(dir.join("main.wasm"), create_abi_program(FORWARDING_PROGRAM).bytecode).unwrap();
+    assert!(matches!(program.verify_source(source, &config), Err(HVMError::Compilation(_))));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Counts its input down to zero through a switch, adding one per step.
+const COUNTDOWN_NET: &str = r#"
+    @main = (n r) & @loop ~ (n r)
+    @loop = (n r) & n ~ ?((0 @loop_succ) r)
+    // Reached with the predecessor of the input.
+    @loop_succ = (p out)
+        & @loop ~ (p q)
+        & q ~ $([+1] out)
+"#;
+
+fn create_net_program(net: &str) -> BendProgram {
+    let program = create_program("(module)");
+    BendProgram::from_net(net, program.metadata, program.author)
+}
+
+#[test]
+fn test_hvm_backend_evaluates_nets() {
+    let config = ExecutionConfig::default();
+    let countdown = create_net_program(COUNTDOWN_NET);
+    assert_eq!(countdown.backend, ExecutionBackend::Hvm);
+    assert!(countdown.validate(&config).is_ok());
+
+    let result = countdown.execute(vec![10, 0, 0], &config).unwrap();
+    assert_eq!(result.outputs, vec![ark_bn254::Fr::from(10u64)]);
+    assert!(result.storage_changes.is_empty());
+    let again = countdown.execute(vec![10, 0, 0], &config).unwrap();
+    assert_eq!(again.gas_used, result.gas_used, "Interaction counts should be deterministic");
+    let longer = countdown.execute(vec![20], &config).unwrap();
+    assert_eq!(longer.outputs, vec![ark_bn254::Fr::from(20u64)]);
+    assert!(longer.gas_used > result.gas_used);
+
+    let config = ExecutionConfig { gas_limit: result.gas_used - 1, ..ExecutionConfig::default() };
+    assert!(matches!(countdown.execute(vec![10], &config), Err(HVMError::OutOfGas(_))));
+    let config = ExecutionConfig { gas_limit: result.gas_used, ..ExecutionConfig::default() };
+    assert!(countdown.execute(vec![10], &config).is_ok());
+
+    let config = ExecutionConfig::default();
+    let square = create_net_program("@main = r & 5 ~ {a b} & a ~ $([*] $(b r))");
+    assert_eq!(square.execute(Vec::new(), &config).unwrap().outputs, vec![ark_bn254::Fr::from(25u64)]);
+    let tuple = create_net_program("@main = (1 (* 0x10))");
+    assert_eq!(tuple.execute(Vec::new(), &config).unwrap().outputs, vec![ark_bn254::Fr::from(1u64), ark_bn254::Fr::from(16u64)]);
+
+    let lambda = create_net_program("@main = (a a)");
+    assert!(matches!(lambda.execute(Vec::new(), &config), Err(HVMError::Execution(_))));
+    let (traced, _) = countdown.execute_traced(Vec::new(), &Arc::new(CallContext::new()), &config);
+    assert!(traced.is_err());
+}
+
+/// Writes a key and emits an event, then calls the program whose id is the
+/// input and returns the call's result code.
+const WRITE_THEN_CALL_PROGRAM: &str = r#"
+    (import "hvm" "storage_write" (func $write (param i32 i32 i32 i32)))
+    (import "hvm" "emit" (func $emit (param i32 i32 i32 i32)))
+    (import "hvm" "call" (func $call (param i32 i32 i32 i32 i64 i32 i32) (result i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 0) "before")
+    (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+        (call $write (i32.const 0) (i32.const 6) (i32.const 0) (i32.const 6))
+        (call $emit (i32.const 0) (i32.const 6) (i32.const 0) (i32.const 0))
+        (i32.store (i32.const 64)
+            (call $call (local.get $ptr) (local.get $len) (i32.const 0) (i32.const 0) (i64.const 100000) (i32.const 128) (i32.const 32)))
+        (i32.const 64)
+        (i32.const 32))
+"#;
+
+#[test]
+fn test_wasm_to_hvm_call_keeps_caller_writes() {
+    let caller = create_abi_program(WRITE_THEN_CALL_PROGRAM);
+    let callee = create_net_program("@main = 7");
+    let mut context = CallContext::new();
+    context.add_program(caller.clone());
+    context.add_program(callee.clone());
+
+    let result = caller.execute_in(callee.id().as_bytes().to_vec(), &Arc::new(context), &ExecutionConfig::default()).unwrap();
+    assert_eq!(result.outputs[0], ark_bn254::Fr::from(32u64), "The net should run and return one output element");
+    let changes = result.storage_changes.get(caller.id()).expect("The caller's writes should survive the call");
+    assert_eq!(changes.get(&b"before"[..]), Some(&Some(b"before".to_vec())));
+    assert_eq!(result.events.len(), 1, "The caller's events should survive the call");
+}
+
+#[test]
+fn test_hvm_backend_validation() {
+    let config = ExecutionConfig::default();
+    for (net, reason) in [
+        ("@other = *", "missing @main"),
+        ("@main = @missing", "undefined reference"),
+        ("@main = (a b)", "variables used once"),
+        ("@main = (a a) & a ~ *", "variable used three times"),
+        ("@main = (1 2", "unclosed node"),
+        ("@main = 0x1000000", "number out of range"),
+        ("@main = 1.5", "float"),
+        ("@main = * @main = *", "duplicate definition"),
+    ] {
+        assert!(
+            matches!(create_net_program(net).validate(&config), Err(HVMError::Validation(_))),
+            "Net with {} should be rejected", reason
+        );
+    }
+
+    let config = ExecutionConfig { max_functions: 2, ..ExecutionConfig::default() };
+    assert!(create_net_program(COUNTDOWN_NET).validate(&config).is_err());
+    let wasm = create_abi_program(COUNTER_PROGRAM);
+    assert_eq!(wasm.backend, ExecutionBackend::Wasm);
+    assert_ne!(BendProgram::from_net("@main = *", wasm.metadata.clone(), wasm.author.clone()).id(), wasm.id());
+}
@@ -0,0 +1,56 @@
+use offchain_labs::OffchainLabs;
+use offchain_labs::config::FeeConfig;
+use offchain_labs::bend::{BendProgram, ProgramMetadata, registry::SignedDeployment};
+use offchain_labs::crypto::SigningKey;
+use offchain_labs::sequencer::{Deposit, Receipt};
+use offchain_labs::zk_rollup::State;
+
+/// Deploys a program for the test transactions to call and returns its id.
+pub fn deploy_test_program(hvm: &mut OffchainLabs) -> String {
+    let wat = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func (export "run") (param i32 i32) (result i32 i32)
+                (i32.const 7)
+                (i32.const 0)))
+    "#;
+    let metadata = ProgramMetadata {
+        name: "Test Program".to_string(),
+        version: "1.0.0".to_string(),
+        description: "Program called by test transactions".to_string(),
+        source: None,
+    };
+    let program = BendProgram::new(wat::parse_str(wat).unwrap(), metadata, "Alice".to_string());
+    let key = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    hvm.deploy_program(SignedDeployment::new(program.clone(), &key, 0)).unwrap();
+    program.id().to_string()
+}
+
+/// Keys of Alice, Bob and Charlie, whose accounts each get a deposit.
+pub fn fund_users(hvm: &mut OffchainLabs) -> [SigningKey; 3] {
+    let users = [(); 3].map(|_| SigningKey::generate(&mut ark_std::rand::thread_rng()));
+    for (nonce, user) in users.iter().enumerate() {
+        hvm.process_deposit(Deposit::new(nonce as u64, account(user), 100_000_000, 0)).unwrap();
+    }
+    users
+}
+
+pub fn account(key: &SigningKey) -> String {
+    key.verifying_key().to_string()
+}
+
+/// Checks that between `initial` and `last` each of `senders` paid the fee
+/// of its transaction's receipt and nothing else, and that the fees were
+/// credited to the fee account.
+pub fn assert_fees_charged(initial: &State, last: &State, senders: &[SigningKey], receipts: &[Receipt]) {
+    for (sender, receipt) in senders.iter().zip(receipts) {
+        let account = account(sender);
+        assert!(receipt.fee > 0, "The transaction of {} was not charged", account);
+        assert_eq!(last.account_balance(&account), initial.account_balance(&account) - receipt.fee, "{} should only pay its fee", account);
+    }
+    let fee_account = &FeeConfig::default().fee_account;
+    let fees: u64 = receipts.iter().map(|receipt| receipt.fee).sum();
+    assert_eq!(last.account_balance(fee_account), initial.account_balance(fee_account) + fees, "The fees should be credited to the fee account");
+}
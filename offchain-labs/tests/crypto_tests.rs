@@ -0,0 +1,34 @@
+use ark_std::rand::thread_rng;
+use offchain_labs::crypto::{Signature, SigningKey, VerifyingKey};
+
+#[test]
+fn test_signature_roundtrip() {
+    let key = SigningKey::generate(&mut thread_rng());
+    let verifying_key = key.verifying_key();
+    let signature = key.sign(b"message");
+
+    assert!(verifying_key.verify(b"message", &signature).is_ok());
+    assert!(verifying_key.verify(b"other message", &signature).is_err());
+    assert!(SigningKey::generate(&mut thread_rng()).verifying_key().verify(b"message", &signature).is_err());
+    assert!(verifying_key.verify(b"message", &key.sign(b"message")).is_ok());
+
+    let restored = SigningKey::from_bytes(&key.to_bytes()).unwrap();
+    assert_eq!(restored.verifying_key(), verifying_key);
+    assert_eq!(VerifyingKey::from_bytes(&verifying_key.to_bytes()).unwrap(), verifying_key);
+    assert_eq!(Signature::from_bytes(&signature.to_bytes()).unwrap(), signature);
+}
+
+#[test]
+fn test_signature_rejects_malformed_encodings() {
+    let key = SigningKey::generate(&mut thread_rng());
+    let signature = key.sign(b"message");
+
+    let mut bytes = signature.to_bytes();
+    bytes[32..].fill(0xff);
+    assert!(Signature::from_bytes(&bytes).is_err(), "Non-canonical scalars should be rejected");
+    assert!(VerifyingKey::from_bytes(&[0u8; 32]).is_err());
+    assert!(VerifyingKey::from_bytes(&[0xffu8; 32]).is_err(), "Invalid points should be rejected");
+
+    let json = serde_json::to_string(&key.verifying_key()).unwrap();
+    assert_eq!(serde_json::from_str::<VerifyingKey>(&json).unwrap(), key.verifying_key());
+}
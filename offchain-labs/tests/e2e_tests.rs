@@ -1,8 +1,11 @@
 use offchain_labs::{Config, OffchainLabs};
-use offchain_labs::sequencer::Transaction;
-use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig};
+use offchain_labs::sequencer::{ReceiptStatus, Transaction};
+use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig, ExecutionConfig, CompilerConfig, FeeConfig, HistoryConfig, RpcConfig};
 use std::path::PathBuf;
 
+mod common;
+use common::{account, assert_fees_charged, deploy_test_program, fund_users};
+
 #[tokio::test]
 async fn test_end_to_end_workflow() {
     let config = Config {
@@ -21,21 +24,34 @@ async fn test_end_to_end_workflow() {
             batch_interval_seconds: 10,
             max_batch_size: 50,
             max_programs_per_batch: 25,
+            forced_inclusion_batches: 10,
+            max_batch_attempts: 3,
         },
+        execution_config: ExecutionConfig::default(),
+        compiler_config: CompilerConfig::default(),
+        fee_config: FeeConfig::default(),
+        history_config: HistoryConfig::default(),
+        rpc_config: RpcConfig::default(),
     };
 
     let mut hvm = OffchainLabs::new(config).unwrap();
+
+    let [alice, bob, charlie] = fund_users(&mut hvm);
+    let program_id = deploy_test_program(&mut hvm);
     let transactions = vec![
-        Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string()),
-        Transaction::new("Bob".to_string(), "Charlie".to_string(), vec![50], 2, "test_program".to_string()),
-        Transaction::new("Charlie".to_string(), "Alice".to_string(), vec![25], 3, "test_program".to_string()),
+        Transaction::new(account(&alice), account(&bob), vec![100], 0, program_id.clone()).sign(&alice),
+        Transaction::new(account(&bob), account(&charlie), vec![50], 0, program_id.clone()).sign(&bob),
+        Transaction::new(account(&charlie), account(&alice), vec![25], 0, program_id.clone()).sign(&charlie),
     ];
 
+    let initial_state = hvm.get_current_state().unwrap();
+    let mut receipts = Vec::new();
     for (i, tx) in transactions.into_iter().enumerate() {
         let result = hvm.process_transaction(tx);
         assert!(result.is_ok(), "Failed to process transaction {}: {:?}", i, result.err());
-        let is_valid = result.unwrap();
-        assert!(is_valid, "Transaction {} was invalid", i);
+        let receipt = result.unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Success, "Transaction {} failed", i);
+        receipts.push(receipt);
         
         let current_state = hvm.get_current_state().unwrap();
         println!("State after transaction {}: {:?}", i, current_state);
@@ -43,6 +59,6 @@ async fn test_end_to_end_workflow() {
 
     let final_state = hvm.get_current_state().unwrap();
     println!("Final state: {:?}", final_state);
-    assert_eq!(final_state.balance(), 768, "Unexpected final balance");
-    assert_eq!(final_state.nonce(), 3, "Unexpected final nonce");
+    assert_fees_charged(&initial_state, &final_state, &[alice, bob, charlie], &receipts);
+    assert_eq!(final_state.nonce(), initial_state.nonce() + 3, "Unexpected final nonce");
 }
\ No newline at end of file
@@ -1,8 +1,11 @@
 use offchain_labs::{Config, OffchainLabs};
-use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig};
-use offchain_labs::sequencer::Transaction;
+use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig, ExecutionConfig, CompilerConfig, FeeConfig, HistoryConfig, RpcConfig};
+use offchain_labs::sequencer::{ReceiptStatus, Transaction};
 use std::path::PathBuf;
 
+mod common;
+use common::{account, assert_fees_charged, deploy_test_program, fund_users};
+
 fn create_test_config() -> Config {
     Config {
         zk_params_path: PathBuf::from("test_params.json"),
@@ -20,7 +23,14 @@ fn create_test_config() -> Config {
             batch_interval_seconds: 10,
             max_batch_size: 50,
             max_programs_per_batch: 25,
+            forced_inclusion_batches: 10,
+            max_batch_attempts: 3,
         },
+        execution_config: ExecutionConfig::default(),
+        compiler_config: CompilerConfig::default(),
+        fee_config: FeeConfig::default(),
+        history_config: HistoryConfig::default(),
+        rpc_config: RpcConfig::default(),
     }
 }
 
@@ -31,28 +41,81 @@ fn test_offchain_labs_initialization() {
     assert!(hvm.is_ok());
 }
 
+#[test]
+fn test_config_without_later_sections_loads_with_defaults() {
+    let config: Config = serde_json::from_str(r#"{
+        "zk_params_path": "zk_params.json",
+        "state_db_path": "state.db",
+        "prover_config": { "proving_key_path": "proving_key.bin", "max_batch_size": 100 },
+        "verifier_config": { "verification_key_path": "verification_key.bin" },
+        "sequencer_config": {
+            "max_pending_transactions": 1000,
+            "max_pending_programs": 100,
+            "batch_interval_seconds": 60,
+            "max_batch_size": 100,
+            "max_programs_per_batch": 10
+        }
+    }"#).unwrap();
+    let defaults = Config::default();
+    assert_eq!(config.sequencer_config.forced_inclusion_batches, defaults.sequencer_config.forced_inclusion_batches);
+    assert_eq!(config.sequencer_config.max_batch_attempts, defaults.sequencer_config.max_batch_attempts);
+    assert_eq!(config.execution_config.gas_limit, ExecutionConfig::default().gas_limit);
+    assert_eq!(config.compiler_config.bend_path, CompilerConfig::default().bend_path);
+    assert_eq!(config.fee_config.fee_account, FeeConfig::default().fee_account);
+    assert_eq!(config.history_config.keep_recent, HistoryConfig::default().keep_recent);
+    assert_eq!(config.rpc_config.listen_address, RpcConfig::default().listen_address);
+}
+
 #[test]
 fn test_transaction_processing() {
     let config = create_test_config();
     let mut hvm = OffchainLabs::new(config).unwrap();
-    let transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string());
+    let [alice, bob, _] = fund_users(&mut hvm);
+    let program_id = deploy_test_program(&mut hvm);
+    let transaction = Transaction::new(account(&alice), account(&bob), vec![100], 0, program_id.clone()).sign(&alice);
     let result = hvm.process_transaction(transaction);
     assert!(result.is_ok());
+    assert_eq!(result.unwrap().status, ReceiptStatus::Success);
+}
+
+#[test]
+fn test_executing_a_program_charges_nothing() {
+    let config = create_test_config();
+    let mut hvm = OffchainLabs::new(config).unwrap();
+    let [alice, _, _] = fund_users(&mut hvm);
+    let program_id = deploy_test_program(&mut hvm);
+    let root = hvm.get_current_state().unwrap().root();
+
+    let receipt = hvm.execute_program("Test Program@^1", Vec::new(), 1_000_000).unwrap();
+    assert_eq!(receipt.program_id, program_id);
+    assert!(receipt.gas_used > 0);
+    assert_eq!(receipt.outputs, hvm.simulate_program(&program_id, Vec::new()).unwrap().outputs);
+    assert!(hvm.execute_program(&program_id, Vec::new(), 0).is_err(), "A fee limit below the base fee pays for no gas");
+    assert_eq!(hvm.get_balance(&account(&alice)), 100_000_000);
+    assert_eq!(hvm.pending_transactions_count(), 0);
+    assert_eq!(hvm.get_current_state().unwrap().root(), root);
 }
 
 #[test]
 fn test_multiple_transactions() {
     let config = create_test_config();
     let mut hvm = OffchainLabs::new(config).unwrap();
+    let [alice, bob, charlie] = fund_users(&mut hvm);
+    let program_id = deploy_test_program(&mut hvm);
     let transactions = vec![
-        Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string()),
-        Transaction::new("Bob".to_string(), "Charlie".to_string(), vec![50], 1, "test_program".to_string()),
-        Transaction::new("Charlie".to_string(), "Alice".to_string(), vec![25], 1, "test_program".to_string()),
+        Transaction::new(account(&alice), account(&bob), vec![100], 0, program_id.clone()).sign(&alice),
+        Transaction::new(account(&bob), account(&charlie), vec![50], 0, program_id.clone()).sign(&bob),
+        Transaction::new(account(&charlie), account(&alice), vec![25], 0, program_id.clone()).sign(&charlie),
     ];
 
+    let initial_state = hvm.get_current_state().unwrap();
+    let mut receipts = Vec::new();
     for (i, tx) in transactions.into_iter().enumerate() {
         let result = hvm.process_transaction(tx);
         assert!(result.is_ok(), "Failed to process transaction {}", i);
+        let receipt = result.unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Success, "Transaction {} failed", i);
+        receipts.push(receipt);
     }
 
     assert_eq!(hvm.processed_transactions_count(), 3, "Expected 3 processed transactions");
@@ -60,8 +123,8 @@ fn test_multiple_transactions() {
 
     let final_state = hvm.get_current_state().unwrap();
     println!("Final state: {:?}", final_state);
-    assert_eq!(final_state.balance(), 768, "Unexpected final balance");
-    assert_eq!(final_state.nonce(), 3, "Unexpected final nonce");
+    assert_fees_charged(&initial_state, &final_state, &[alice, bob, charlie], &receipts);
+    assert_eq!(final_state.nonce(), initial_state.nonce() + 3, "Unexpected final nonce");
 
     println!("Processed transactions: {:?}", hvm.get_processed_transactions());
     println!("Pending transactions: {:?}", hvm.get_pending_transactions());
@@ -71,14 +134,17 @@ fn test_multiple_transactions() {
 fn test_zk_snark_proof_generation_and_verification() {
     let config = create_test_config();
     let mut hvm = OffchainLabs::new(config).unwrap();
+    let [alice, bob, _] = fund_users(&mut hvm);
+    let program_id = deploy_test_program(&mut hvm);
     
-    let transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string());
+    let transaction = Transaction::new(account(&alice), account(&bob), vec![100], 0, program_id.clone()).sign(&alice);
     let result = hvm.process_transaction(transaction);
     assert!(result.is_ok());
     
-    let is_valid = result.unwrap();
-    assert!(is_valid, "Proof verification failed");
+    let receipt = result.unwrap();
+    assert_eq!(receipt.status, ReceiptStatus::Success, "Transaction failed");
+    assert_eq!(hvm.receipt(&receipt.transaction_hash), Some(&receipt));
     
     assert_eq!(hvm.processed_transactions_count(), 1, "Expected 1 processed transaction");
     assert_eq!(hvm.pending_transactions_count(), 0, "Expected 0 pending transactions");
-}
\ No newline at end of file
+}
@@ -1,9 +1,12 @@
 use offchain_labs::{Config, OffchainLabs};
-use offchain_labs::sequencer::Transaction;
-use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig};
+use offchain_labs::sequencer::{ReceiptStatus, Transaction};
+use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig, ExecutionConfig, CompilerConfig, FeeConfig, HistoryConfig, RpcConfig};
 use offchain_labs::bend::BendProgram;
 use std::path::PathBuf;
 
+mod common;
+use common::{account, assert_fees_charged, deploy_test_program, fund_users};
+
 fn create_test_config() -> Config {
     Config {
         zk_params_path: PathBuf::from("test_params.json"),
@@ -21,7 +24,14 @@ fn create_test_config() -> Config {
             batch_interval_seconds: 10,
             max_batch_size: 50,
             max_programs_per_batch: 25,
+            forced_inclusion_batches: 10,
+            max_batch_attempts: 3,
         },
+        execution_config: ExecutionConfig::default(),
+        compiler_config: CompilerConfig::default(),
+        fee_config: FeeConfig::default(),
+        history_config: HistoryConfig::default(),
+        rpc_config: RpcConfig::default(),
     }
 }
 
@@ -29,23 +39,28 @@ fn create_test_config() -> Config {
 async fn test_prover_generate_proof() {
     let config = create_test_config();
     let mut hvm = OffchainLabs::new(config).unwrap();
+    let [alice, bob, charlie] = fund_users(&mut hvm);
+    let program_id = deploy_test_program(&mut hvm);
 
     let transactions = vec![
-        Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string()),
-        Transaction::new("Bob".to_string(), "Charlie".to_string(), vec![50], 2, "test_program".to_string()),
-        Transaction::new("Charlie".to_string(), "Alice".to_string(), vec![25], 3, "test_program".to_string()),
+        Transaction::new(account(&alice), account(&bob), vec![100], 0, program_id.clone()).sign(&alice),
+        Transaction::new(account(&bob), account(&charlie), vec![50], 0, program_id.clone()).sign(&bob),
+        Transaction::new(account(&charlie), account(&alice), vec![25], 0, program_id.clone()).sign(&charlie),
     ];
 
+    let initial_state = hvm.get_current_state().unwrap();
+    let mut receipts = Vec::new();
     for (i, tx) in transactions.into_iter().enumerate() {
         let result = hvm.process_transaction(tx);
         assert!(result.is_ok(), "Failed to process transaction {}: {:?}", i, result.err());
-        let is_valid = result.unwrap();
-        assert!(is_valid, "Transaction {} was invalid", i);
+        let receipt = result.unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Success, "Transaction {} failed", i);
+        receipts.push(receipt);
     }
 
     let final_state = hvm.get_current_state().unwrap();
-    assert_eq!(final_state.balance(), 768, "Unexpected final balance");
-    assert_eq!(final_state.nonce(), 3, "Unexpected final nonce");
+    assert_fees_charged(&initial_state, &final_state, &[alice, bob, charlie], &receipts);
+    assert_eq!(final_state.nonce(), initial_state.nonce() + 3, "Unexpected final nonce");
 }
 
 #[test]
@@ -53,12 +68,23 @@ fn test_prover_resource_estimation() {
     let config = create_test_config();
     let hvm = OffchainLabs::new(config).unwrap();
 
+    let bytecode = wat::parse_str(r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func (export "run") (param i32 i32) (result i32 i32)
+                (drop (memory.grow (i32.const 1)))
+                (i32.const 0)
+                (i32.const 32)))
+    "#).unwrap();
     let program = BendProgram::new(
-        vec![1, 2, 3, 4],
+        bytecode,
         offchain_labs::bend::ProgramMetadata {
             name: "Test Program".to_string(),
             version: "1.0.0".to_string(),
             description: "Bend program for test".to_string(),
+            source: None,
         },
         "Test Author".to_string(),
     );
@@ -66,8 +92,11 @@ fn test_prover_resource_estimation() {
     let usage = hvm.estimate_program_resources(&program);
     assert!(usage.is_ok(), "Failed to estimate resource usage");
     let usage = usage.unwrap();
-    assert!(usage.cpu_cycles > 0, "CPU cycles should be positive");
+    assert!(usage.gas_used > 0, "Gas used should be positive");
     assert!(usage.memory_usage > 0, "Memory usage should be positive");
+
+    let again = hvm.estimate_program_resources(&program).unwrap();
+    assert_eq!(usage.gas_used, again.gas_used, "Gas metering should be deterministic");
 }
 
 #[test]
@@ -75,18 +104,55 @@ fn test_prover_program_optimization() {
     let config = create_test_config();
     let hvm = OffchainLabs::new(config).unwrap();
 
+    let bytecode = wat::parse_str(r#"
+        (module
+            (@custom "build-info" "compiled by bend")
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func $unused (export "debug_dump") (result i32)
+                (call $dead))
+            (func $dead (result i32)
+                (i32.const 7))
+            (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+                (i32.store (i32.const 0)
+                    (i32.add
+                        (i32.load8_u (local.get $ptr))
+                        (i32.mul (i32.const 6) (i32.add (i32.const 3) (i32.const 4)))))
+                (return (i32.const 0) (i32.const 32))
+                (drop (i32.const 1))))
+    "#).unwrap();
     let program = BendProgram::new(
-        vec![1, 2, 3, 4],
+        bytecode,
         offchain_labs::bend::ProgramMetadata {
             name: "Test Program".to_string(),
             version: "1.0.0".to_string(),
             description: "Bend program for test".to_string(),
+            source: None,
         },
         "Test Author".to_string(),
     );
 
-    let optimized = hvm.optimize_program(&program);
-    assert!(optimized.is_ok(), "Failed to optimize program");
-    let optimized = optimized.unwrap();
-    assert_ne!(program.id(), optimized.id(), "Optimized program should have a different ID");
-}
\ No newline at end of file
+    let samples = vec![vec![0], vec![5], vec![255]];
+    let optimized = hvm.optimize_program(&program, &samples);
+    assert!(optimized.is_ok(), "Failed to optimize program: {:?}", optimized.err());
+    let report = optimized.unwrap();
+    assert_ne!(program.id(), report.program.id(), "Optimized program should have a different ID");
+    assert!(report.optimized_size < report.original_size, "Optimized program should be smaller");
+    assert!(report.optimized_gas < report.original_gas, "Optimized program should use less gas");
+    assert_eq!(report.stats.removed_functions, 2);
+    assert_eq!(report.stats.removed_exports, 1);
+    assert!(report.stats.removed_custom_sections >= 1);
+    assert_eq!(report.stats.folded_constants, 2);
+    assert!(report.stats.removed_instructions > 0);
+    assert!(report.program.validate(&ExecutionConfig::default()).is_ok());
+
+    for inputs in samples {
+        let expected = program.execute(inputs.clone(), &ExecutionConfig::default()).unwrap();
+        let actual = report.program.execute(inputs, &ExecutionConfig::default()).unwrap();
+        assert_eq!(expected.outputs, actual.outputs);
+    }
+
+    let invalid = BendProgram::new(vec![1, 2, 3, 4], program.metadata.clone(), program.author.clone());
+    assert!(hvm.optimize_program(&invalid, &[]).is_err(), "Invalid bytecode should not be optimized");
+}
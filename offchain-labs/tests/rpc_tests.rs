@@ -0,0 +1,93 @@
+use jsonrpsee::core::client::{ClientT, Error, Subscription, SubscriptionClientT};
+use jsonrpsee::http_client::HttpClientBuilder;
+use jsonrpsee::rpc_params;
+use jsonrpsee::ws_client::WsClientBuilder;
+use jsonrpsee::types::error::METHOD_NOT_FOUND_CODE;
+use offchain_labs::{Config, OffchainLabs};
+use offchain_labs::config::RpcConfig;
+use offchain_labs::crypto::SigningKey;
+use offchain_labs::rpc::{self, bridge_message, AccountInfo, RollupRpc, INSUFFICIENT_BALANCE, NODE_ERROR, UNAUTHORIZED};
+use offchain_labs::sequencer::{BatchHeader, Deposit, Receipt, Transaction};
+
+fn error_code(result: Result<serde_json::Value, Error>) -> i32 {
+    match result {
+        Err(Error::Call(e)) => e.code(),
+        other => panic!("Expected a call error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_rpc_serves_http_and_websocket_clients() {
+    let rpc_config = RpcConfig { listen_address: "127.0.0.1:0".to_string(), ..RpcConfig::default() };
+    let hvm = OffchainLabs::new(Config::default()).unwrap();
+    let (address, handle) = rpc::serve(RollupRpc::new(hvm, rpc_config.subscription_buffer), &rpc_config).await.unwrap();
+
+    let http = HttpClientBuilder::default().build(format!("http://{}", address)).unwrap();
+    let account: AccountInfo = http.request("rollup_getAccount", rpc_params!["Alice"]).await.unwrap();
+    assert_eq!(account, AccountInfo { account: "Alice".to_string(), balance: 0, nonce: 0, withdrawal_nonce: 0 });
+    let pending: Vec<Transaction> = http.request("rollup_pendingTransactions", rpc_params![]).await.unwrap();
+    assert!(pending.is_empty());
+    let receipt: Option<Receipt> = http.request("rollup_getReceipt", rpc_params!["unknown"]).await.unwrap();
+    assert!(receipt.is_none());
+    let header: Option<BatchHeader> = http.request("rollup_getBatchHeader", rpc_params![0]).await.unwrap();
+    assert!(header.is_none());
+
+    let alice = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let tx = Transaction::new(String::new(), "Bob".to_string(), vec![100], 0, "test_program".to_string()).sign(&alice);
+    assert_eq!(error_code(http.request("rollup_sendTransaction", rpc_params![tx]).await), INSUFFICIENT_BALANCE);
+    let deposit = Deposit::new(0, alice.verifying_key().to_string(), 1_000, 1);
+    let signature = alice.sign(&bridge_message("rollup_submitDeposit", &deposit).unwrap());
+    assert_eq!(
+        error_code(http.request("rollup_submitDeposit", rpc_params![deposit, signature]).await),
+        METHOD_NOT_FOUND_CODE,
+        "Bridge methods are only served with a relayer key",
+    );
+    assert_eq!(error_code(http.request("rollup_getAccountProof", rpc_params!["Alice", 0]).await), NODE_ERROR);
+
+    let ws = WsClientBuilder::default().build(format!("ws://{}", address)).await.unwrap();
+    let account: AccountInfo = ws.request("rollup_getAccount", rpc_params!["Bob"]).await.unwrap();
+    assert_eq!(account.balance, 0);
+    let batches: Subscription<BatchHeader> = ws
+        .subscribe("rollup_subscribeBatches", rpc_params![], "rollup_unsubscribeBatches")
+        .await
+        .unwrap();
+    batches.unsubscribe().await.unwrap();
+
+    handle.stop().unwrap();
+    handle.stopped().await;
+}
+
+#[tokio::test]
+async fn test_rpc_bridge_methods_require_the_relayer_signature() {
+    let relayer = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let alice = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let rpc_config = RpcConfig {
+        listen_address: "127.0.0.1:0".to_string(),
+        relayer_key: Some(relayer.verifying_key().to_string()),
+        ..RpcConfig::default()
+    };
+    let hvm = OffchainLabs::new(Config::default()).unwrap();
+    let (address, handle) = rpc::serve(RollupRpc::new(hvm, rpc_config.subscription_buffer), &rpc_config).await.unwrap();
+    let http = HttpClientBuilder::default().build(format!("http://{}", address)).unwrap();
+
+    let account = alice.verifying_key().to_string();
+    let deposit = Deposit::new(0, account.clone(), 1_000, 1);
+    let forged = alice.sign(&bridge_message("rollup_submitDeposit", &deposit).unwrap());
+    assert_eq!(error_code(http.request("rollup_submitDeposit", rpc_params![deposit.clone(), forged]).await), UNAUTHORIZED);
+    let replayed = relayer.sign(&bridge_message("rollup_submitDeposit", &Deposit { amount: 1, ..deposit.clone() }).unwrap());
+    assert_eq!(error_code(http.request("rollup_submitDeposit", rpc_params![deposit.clone(), replayed]).await), UNAUTHORIZED);
+    let signature = relayer.sign(&bridge_message("rollup_submitDeposit", &deposit).unwrap());
+    let credited: bool = http.request("rollup_submitDeposit", rpc_params![deposit, signature]).await.unwrap();
+    assert!(credited);
+    let info: AccountInfo = http.request("rollup_getAccount", rpc_params![account.clone()]).await.unwrap();
+    assert_eq!(info.balance, 1_000);
+
+    let unsigned = Transaction::new(account.clone(), "Bob".to_string(), vec![100], 0, "test_program".to_string());
+    assert_eq!(error_code(http.request("rollup_sendTransaction", rpc_params![unsigned]).await), UNAUTHORIZED);
+    let mut stolen = Transaction::new(String::new(), "Bob".to_string(), vec![100], 0, "test_program".to_string()).sign(&relayer);
+    stolen.sender = account;
+    assert_eq!(error_code(http.request("rollup_sendTransaction", rpc_params![stolen]).await), UNAUTHORIZED);
+
+    handle.stop().unwrap();
+    handle.stopped().await;
+}
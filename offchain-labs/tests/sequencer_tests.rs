@@ -1,8 +1,14 @@
 #[warn(unused_imports)]
+use ark_bn254::Fr;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
 use offchain_labs::{
-    config::SequencerConfig,
-    sequencer::{Sequencer, Transaction},
-    zk_rollup::{State, Proof},
+    bend::{BendCircuit, BendProgram, ForcedInclusionCheck, ProgramMetadata, registry::{freeze_message, transfer_message, SignedDeployment}},
+    crypto::SigningKey,
+    config::{ExecutionConfig, FeeConfig, HistoryConfig, SequencerConfig},
+    error::HVMError,
+    prover::execute_batch,
+    sequencer::{fees, Batch, BatchHeader, Deposit, FeeMarket, ForcedInclusion, ForcedTransaction, ProgramStatus, ReceiptStatus, Sequencer, Transaction, Withdrawal},
+    zk_rollup::{merkle_proof, merkle_root, read_snapshot, State, StateLeaf, Proof, StorageChanges, SNAPSHOT_VERSION},
 };
 
 fn create_test_sequencer() -> Sequencer {
@@ -12,26 +18,65 @@ fn create_test_sequencer() -> Sequencer {
         batch_interval_seconds: 1,
         max_batch_size: 3,
         max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 3,
     };
     Sequencer::new(State::default(), config)
 }
 
+fn new_key() -> SigningKey {
+    SigningKey::generate(&mut ark_std::rand::thread_rng())
+}
+
+fn signed_transaction(key: &SigningKey, recipient: &str, inputs: Vec<u8>, nonce: u64) -> Transaction {
+    Transaction::new(key.verifying_key().to_string(), recipient.to_string(), inputs, nonce, "test_program".to_string()).sign(key)
+}
+
 #[test]
 fn test_process_transaction() {
     let mut sequencer = create_test_sequencer();
     let tx = Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string());
-    assert!(sequencer.process_transaction(tx).is_ok());
+    assert!(sequencer.process_transaction(tx.clone()).is_err(), "Unsigned transactions should be rejected");
+    let mut stolen = signed_transaction(&new_key(), "Bob", vec![100], 0);
+    stolen.sender = "Alice".to_string();
+    assert!(sequencer.process_transaction(stolen).is_err(), "The signer should be the sender");
+    assert!(sequencer.process_transaction(signed_transaction(&new_key(), "Bob", vec![100], 0)).is_ok());
     assert_eq!(sequencer.pending_transactions_count(), 1);
 }
 
+#[test]
+fn test_signed_transactions_are_applied_once() {
+    let mut sequencer = create_test_sequencer();
+    let alice = new_key();
+    let account = alice.verifying_key().to_string();
+    let tx = signed_transaction(&alice, "Bob", vec![100], 0);
+    assert!(sequencer.process_transaction(signed_transaction(&alice, "Bob", vec![100], 1)).is_err(), "Nonces should not skip ahead");
+    sequencer.process_transaction(tx.clone()).unwrap();
+    assert!(sequencer.process_transaction(tx.clone()).is_err(), "A queued transaction should not be accepted again");
+    assert_eq!(sequencer.next_nonce(&account), 1);
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert_eq!(sequencer.state().account_nonce(&account), 1);
+    assert!(sequencer.process_transaction(tx.clone()).is_err(), "An applied transaction should not be accepted again");
+    let account_proof = sequencer.state().account_proof(&account).unwrap();
+    assert!(account_proof.verify());
+    assert_eq!(account_proof.leaf, StateLeaf::Account { account: account.clone(), balance: 0, nonce: 1, withdrawal_nonce: 0 });
+
+    let replayed = Batch::new(1, ForcedInclusion { queue_start: 0, transactions: Vec::new() }, vec![tx], Vec::new(), Vec::new(), Vec::new(), 1);
+    assert!(sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &replayed).is_err(), "A batch should not apply a transaction again");
+    assert_eq!(sequencer.state().account_nonce(&account), 1);
+    sequencer.process_transaction(signed_transaction(&alice, "Bob", vec![100], 1)).unwrap();
+}
+
 #[test]
 fn test_max_pending_transactions() {
     let mut sequencer = create_test_sequencer();
     for i in 0..5 {
-        let tx = Transaction::new(format!("Sender{}", i), format!("Recipient{}", i), vec![100], 1, "test_program".to_string());
+        let tx = signed_transaction(&new_key(), &format!("Recipient{}", i), vec![100], 0);
         assert!(sequencer.process_transaction(tx).is_ok());
     }
-    let tx = Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string());
+    let tx = signed_transaction(&new_key(), "Bob", vec![100], 0);
     assert!(sequencer.process_transaction(tx).is_err());
 }
 
@@ -40,7 +85,7 @@ fn test_create_batch() {
     let mut sequencer = create_test_sequencer();
 
     for i in 0..4 {
-        let tx = Transaction::new(format!("Sender{}", i), format!("Recipient{}", i), vec![100], 1, "test_program".to_string());
+        let tx = signed_transaction(&new_key(), &format!("Recipient{}", i), vec![100], 0);
         sequencer.process_transaction(tx).unwrap();
     }
 
@@ -68,7 +113,7 @@ fn test_apply_proof() {
     let initial_state = sequencer.get_current_state();
     
     for i in 0..3 {
-        let tx = Transaction::new(format!("Sender{}", i), format!("Recipient{}", i), vec![100], 1, "test_program".to_string());
+        let tx = signed_transaction(&new_key(), &format!("Recipient{}", i), vec![100], 0);
         sequencer.process_transaction(tx).unwrap();
     }
     let batch = sequencer.create_batch(true).unwrap().unwrap();
@@ -81,4 +126,749 @@ fn test_apply_proof() {
     assert!(new_state.nonce() > initial_state.nonce());
     assert_eq!(sequencer.processed_transactions_count(), 3);
     assert_eq!(sequencer.pending_transactions_count(), 0);
-}
\ No newline at end of file
+}
+#[test]
+fn test_apply_proof_commits_storage_changes() {
+    let mut sequencer = create_test_sequencer();
+    sequencer.process_transaction(signed_transaction(&new_key(), "Bob", vec![100], 0)).unwrap();
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+
+    let mut changes = StorageChanges::new();
+    changes.insert(b"key".to_vec(), Some(b"value".to_vec()));
+    batch.record_storage_changes("test_program", changes);
+    assert_eq!(sequencer.get_current_state().storage_root("test_program"), [0u8; 32]);
+
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+
+    let state = sequencer.get_current_state();
+    let storage = state.program_storage("test_program").unwrap();
+    assert_eq!(storage.get(b"key"), Some(&b"value"[..]));
+    assert_eq!(state.storage_root("test_program"), storage.root());
+    assert_ne!(state.storage_root("test_program"), [0u8; 32]);
+}
+
+fn create_versioned_program(variant: i32, version: &str, author: &str) -> BendProgram {
+    let wat = format!(r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func (export "run") (param i32 i32) (result i32 i32)
+                (i32.const {})
+                (i32.const 0)))
+    "#, variant);
+    BendProgram::new(
+        wat::parse_str(wat).unwrap(),
+        ProgramMetadata {
+            name: "Token".to_string(),
+            version: version.to_string(),
+            description: "Versioned program".to_string(),
+            source: None,
+        },
+        author.to_string(),
+    )
+}
+
+fn deploy(sequencer: &mut Sequencer, deployment: SignedDeployment) -> Result<(), HVMError> {
+    let program_id = deployment.program.id().to_string();
+    sequencer.submit_program(deployment, &ExecutionConfig::default())?;
+    let batch = sequencer.create_batch(true)?.unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch)?;
+    match sequencer.program_status(&program_id) {
+        Some(ProgramStatus::Deployed { .. }) => Ok(()),
+        status => Err(HVMError::Sequencer(format!("Program was not deployed: {:?}", status))),
+    }
+}
+
+#[test]
+fn test_deploy_program_upgrades() {
+    let mut sequencer = create_test_sequencer();
+    let alice = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let bob = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let first = create_versioned_program(1, "1.0.0", "Alice");
+    let second = create_versioned_program(2, "1.2.0", "Alice");
+
+    assert!(deploy(&mut sequencer, SignedDeployment::new(first.clone(), &alice, 0)).is_ok());
+    let nonce = sequencer.registry().nonce("Token");
+    assert!(deploy(&mut sequencer, SignedDeployment::new(create_versioned_program(3, "2.0.0", "Bob"), &bob, nonce)).is_err());
+    assert!(deploy(&mut sequencer, SignedDeployment::new(create_versioned_program(3, "1.0.0", "Alice"), &alice, nonce)).is_err());
+    assert!(deploy(&mut sequencer, SignedDeployment::new(second.clone(), &alice, nonce)).is_ok());
+
+    assert_eq!(sequencer.resolve_program("Token").unwrap().id(), second.id());
+    assert_eq!(sequencer.resolve_program("Token@~1.0").unwrap().id(), first.id());
+    assert_eq!(sequencer.resolve_program(first.id()).unwrap().id(), first.id());
+    assert_eq!(sequencer.registry().versions("Token").unwrap().len(), 2);
+}
+
+#[test]
+fn test_program_lifecycle() {
+    let mut sequencer = create_test_sequencer();
+    let config = ExecutionConfig::default();
+    let alice = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let program = create_versioned_program(1, "1.0.0", "Alice");
+    let program_id = program.id().to_string();
+
+    let invalid = BendProgram::new(vec![1], program.metadata.clone(), "Alice".to_string());
+    assert!(sequencer.submit_program(SignedDeployment::new(invalid.clone(), &alice, 0), &config).is_err());
+    assert!(matches!(sequencer.program_status(invalid.id()), Some(ProgramStatus::Rejected { .. })));
+    assert_eq!(sequencer.pending_programs_count(), 0);
+
+    sequencer.submit_program(SignedDeployment::new(program.clone(), &alice, 0), &config).unwrap();
+    assert_eq!(sequencer.program_status(&program_id), Some(&ProgramStatus::Validated));
+    assert!(sequencer.submit_program(SignedDeployment::new(program.clone(), &alice, 0), &config).is_err());
+    let other_version = create_versioned_program(2, "1.1.0", "Alice");
+    assert!(sequencer.submit_program(SignedDeployment::new(other_version, &alice, 0), &config).is_err(), "Only one deployment per name can be pending");
+    assert!(sequencer.resolve_program(&program_id).is_err(), "Submitted programs should not be callable");
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.programs().len(), 1);
+    assert_eq!(sequencer.program_status(&program_id), Some(&ProgramStatus::Included { batch_number: batch.batch_number() }));
+
+    sequencer.requeue_programs(&batch);
+    assert_eq!(sequencer.program_status(&program_id), Some(&ProgramStatus::Validated));
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert_eq!(sequencer.program_status(&program_id), Some(&ProgramStatus::Deployed { batch_number: batch.batch_number() }));
+    assert_eq!(sequencer.processed_programs_count(), 1);
+    assert!(sequencer.resolve_program("Token").is_ok());
+}
+
+#[test]
+fn test_registry_changes_apply_with_their_batch() {
+    let mut sequencer = create_test_sequencer();
+    let alice = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let bob = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    deploy(&mut sequencer, SignedDeployment::new(create_versioned_program(1, "1.0.0", "Alice"), &alice, 0)).unwrap();
+
+    let nonce = sequencer.registry().nonce("Token");
+    let transfer = alice.sign(&transfer_message("Token", &bob.verifying_key(), nonce));
+    assert!(sequencer.transfer_program_ownership("Token", bob.verifying_key(), &bob.sign(&transfer_message("Token", &bob.verifying_key(), nonce))).is_err());
+    sequencer.transfer_program_ownership("Token", bob.verifying_key(), &transfer).unwrap();
+    assert!(sequencer.freeze_program("Token", &bob.sign(&freeze_message("Token", nonce + 1))).is_err(), "Only one change per name can be pending");
+    assert_eq!(sequencer.registry().entry("Token").unwrap().owner, alice.verifying_key());
+    let root = sequencer.state().root();
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.registry_changes().len(), 1);
+    sequencer.revert_batch(&batch, "proof failed");
+    assert_eq!(sequencer.pending_registry_changes_count(), 1);
+    assert_eq!(sequencer.state().root(), root);
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert_eq!(sequencer.registry().entry("Token").unwrap().owner, bob.verifying_key());
+    assert_ne!(sequencer.state().root(), root);
+    assert_eq!(sequencer.state().registry, *sequencer.registry());
+
+    sequencer.freeze_program("Token", &bob.sign(&freeze_message("Token", nonce + 1))).unwrap();
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert!(sequencer.registry().entry("Token").unwrap().frozen);
+
+    let path = std::env::temp_dir().join(format!("hvm_registry_snapshot_{}.jsonl", std::process::id()));
+    sequencer.export_snapshot(&path, 4).unwrap();
+    let (_, restored) = read_snapshot(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(restored.registry, *sequencer.registry());
+    assert_eq!(restored.root(), sequencer.state().root());
+}
+
+#[test]
+fn test_base_fee_tracks_batch_fullness() {
+    let config = FeeConfig {
+        initial_base_fee: 100,
+        min_base_fee: 10,
+        target_batch_gas: 1000,
+        base_fee_change_denominator: 8,
+        fee_account: "sequencer".to_string(),
+    };
+    let mut market = FeeMarket::new(config);
+    market.update(2000);
+    assert_eq!(market.base_fee(), 112);
+    market.update(1000);
+    assert_eq!(market.base_fee(), 112, "A batch at the target should not move the base fee");
+    market.update(0);
+    assert_eq!(market.base_fee(), 98);
+    for _ in 0..100 {
+        market.update(0);
+    }
+    assert_eq!(market.base_fee(), 10);
+    market.update(1001);
+    assert_eq!(market.base_fee(), 11, "Fuller batches should always raise the base fee");
+
+    assert_eq!(fees::gas_limit(1000, 10, 50), 50);
+    assert_eq!(fees::gas_limit(105, 10, 50), 10);
+    assert_eq!(fees::max_fee(105, 10, 50), 100);
+}
+
+#[test]
+fn test_batch_fees_are_credited_to_fee_account() {
+    let fee_config = FeeConfig { initial_base_fee: 3, target_batch_gas: 100, ..FeeConfig::default() };
+    let config = SequencerConfig {
+        max_pending_transactions: 5,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 3,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 3,
+    };
+    let mut sequencer = Sequencer::with_fee_config(State::default(), config, fee_config);
+
+    let alice = new_key();
+    let account = alice.verifying_key().to_string();
+    let cheap = Transaction::with_fee_limit(account.clone(), "Bob".to_string(), vec![], 0, "test_program".to_string(), 2).sign(&alice);
+    assert!(sequencer.process_transaction(cheap).is_err(), "Fee limits below the base fee should be rejected");
+    let tx = Transaction::with_fee_limit(account.clone(), "Bob".to_string(), vec![], 0, "test_program".to_string(), 1000).sign(&alice);
+    sequencer.process_transaction(tx).unwrap();
+    sequencer.submit_deposit(Deposit::new(0, account.clone(), 1000, 1)).unwrap();
+
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.gas_price(), 3);
+    batch.record_receipt(ReceiptStatus::Success, 150, Vec::new(), Vec::new());
+    assert_eq!(batch.receipts()[0].fee, 450);
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+
+    assert_eq!(sequencer.state().account_balance("sequencer"), 450);
+    assert_eq!(sequencer.state().account_balance(&account), 550, "Fees should be debited from the sender");
+    assert_eq!(sequencer.fee_market().base_fee(), 4, "A batch over the target should raise the base fee");
+}
+
+#[test]
+fn test_receipts_are_stored_by_transaction_hash() {
+    let mut sequencer = create_test_sequencer();
+    let (alice, bob) = (new_key(), new_key());
+    let transactions = vec![
+        signed_transaction(&alice, &bob.verifying_key().to_string(), vec![100], 0),
+        signed_transaction(&bob, &alice.verifying_key().to_string(), vec![50], 0),
+    ];
+    for tx in &transactions {
+        sequencer.process_transaction(tx.clone()).unwrap();
+    }
+    for (nonce, user) in [&alice, &bob].into_iter().enumerate() {
+        sequencer.submit_deposit(Deposit::new(nonce as u64, user.verifying_key().to_string(), 100, 1)).unwrap();
+    }
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.header().receipts_root, [0u8; 32]);
+
+    batch.record_receipt(ReceiptStatus::Success, 10, vec![1, 2], Vec::new());
+    let root = batch.header().receipts_root;
+    batch.record_receipt(ReceiptStatus::OutOfGas, 20, Vec::new(), Vec::new());
+    let header = batch.header();
+    assert_ne!(header.receipts_root, root, "Every receipt should be committed to");
+    assert_eq!(header.gas_used, 30);
+    assert_eq!(header.transaction_count, 2);
+
+    assert!(sequencer.receipt(&transactions[0].hash()).is_none(), "Receipts should only be stored once the batch is applied");
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    for (i, tx) in transactions.iter().enumerate() {
+        let receipt = sequencer.receipt(&tx.hash()).unwrap();
+        assert_eq!(receipt, &batch.receipts()[i]);
+        assert_eq!(receipt.index, i as u32);
+        assert_eq!(receipt.batch_number, batch.batch_number());
+        assert_eq!(&sequencer.applied_batch(receipt.batch_number).unwrap().batch.receipts()[i], receipt);
+    }
+    assert_eq!(sequencer.receipt(&transactions[0].hash()).unwrap().return_data, vec![1, 2]);
+    assert_eq!(sequencer.receipt(&transactions[1].hash()).unwrap().status, ReceiptStatus::OutOfGas);
+}
+
+#[test]
+fn test_deposits_are_credited_once_in_order() {
+    let mut sequencer = create_test_sequencer();
+    let deposits: Vec<_> = (0..3)
+        .map(|nonce| Deposit::new(nonce, "Alice".to_string(), 100, 7))
+        .collect();
+
+    assert!(sequencer.submit_deposit(deposits[1].clone()).is_err(), "Deposits should not skip ahead");
+    assert!(sequencer.submit_deposit(deposits[0].clone()).unwrap());
+    assert!(!sequencer.submit_deposit(deposits[0].clone()).unwrap(), "A forwarded deposit should be ignored");
+    assert!(sequencer.submit_deposit(deposits[1].clone()).unwrap());
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.deposits(), &deposits[..2]);
+    sequencer.requeue_deposits(&batch);
+    assert!(!sequencer.submit_deposit(deposits[1].clone()).unwrap(), "Requeued deposits are still pending");
+    assert!(sequencer.submit_deposit(deposits[2].clone()).unwrap());
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.deposits(), &deposits[..]);
+    assert_eq!(sequencer.state().account_balance("Alice"), 0, "Deposits should be credited when the batch is applied");
+
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert_eq!(sequencer.state().account_balance("Alice"), 300);
+    assert_eq!(sequencer.state().deposit_nonce(), 3);
+
+    let config = SequencerConfig {
+        max_pending_transactions: 5,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 3,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 3,
+    };
+    let mut restarted = Sequencer::new(sequencer.get_current_state(), config);
+    for deposit in deposits {
+        assert!(!restarted.submit_deposit(deposit).unwrap(), "Credited deposits should be ignored after a restart");
+    }
+    assert_eq!(restarted.pending_deposits_count(), 0);
+}
+
+#[test]
+fn test_merkle_proofs_lead_to_the_root() {
+    for count in 1..=7u8 {
+        let leaves: Vec<[u8; 32]> = (0..count).map(|i| [i; 32]).collect();
+        let root = merkle_root(leaves.clone());
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = merkle_proof(leaves.clone(), index).unwrap();
+            assert_eq!(proof.root(*leaf), root, "Leaf {} of {} should prove against the root", index, count);
+            assert_ne!(proof.root([0xff; 32]), root);
+        }
+        assert!(merkle_proof(leaves, count as usize).is_none());
+    }
+}
+
+#[test]
+fn test_withdrawals_reserve_balance_and_are_provable() {
+    let mut sequencer = create_test_sequencer();
+    let alice = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let mallory = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    let account = alice.verifying_key().to_string();
+    sequencer.submit_deposit(Deposit::new(0, account.clone(), 100, 1)).unwrap();
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+
+    assert!(sequencer.submit_withdrawal(Withdrawal::new(&alice, "5Alice".to_string(), 101, 0)).is_err());
+    let stolen = Withdrawal { account: account.clone(), ..Withdrawal::new(&mallory, "5Mallory".to_string(), 50, 0) };
+    assert!(sequencer.submit_withdrawal(stolen).is_err(), "Only the account's owner can withdraw from it");
+    let mut forged = Withdrawal::new(&alice, "5Alice".to_string(), 50, 0);
+    forged.l1_recipient = "5Mallory".to_string();
+    assert!(sequencer.submit_withdrawal(forged).is_err(), "The signature should cover the recipient");
+
+    let withdrawals = vec![
+        Withdrawal::new(&alice, "5Alice".to_string(), 60, 0),
+        Withdrawal::new(&alice, "5Bob".to_string(), 30, 1),
+    ];
+    for withdrawal in &withdrawals {
+        sequencer.submit_withdrawal(withdrawal.clone()).unwrap();
+    }
+    assert!(sequencer.submit_withdrawal(withdrawals[0].clone()).is_err(), "A withdrawal can only be submitted once");
+    assert!(sequencer.submit_withdrawal(Withdrawal::new(&alice, "5Alice".to_string(), 1, 3)).is_err(), "Nonces should not skip ahead");
+    assert_eq!(sequencer.available_balance(&account), 10, "Withdrawn amounts should be reserved right away");
+    assert_eq!(sequencer.state().account_balance(&account), 100, "Withdrawn amounts should only be debited by the batch");
+    assert!(sequencer.withdrawal_proof(&withdrawals[0].hash()).is_err(), "Withdrawals should only be provable once applied");
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.withdrawals(), &withdrawals[..]);
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert_eq!(sequencer.state().account_balance(&account), 10);
+    assert_eq!(sequencer.available_balance(&account), 10);
+
+    let header = batch.header();
+    for (index, withdrawal) in withdrawals.iter().enumerate() {
+        let proof = sequencer.withdrawal_proof(&withdrawal.hash()).unwrap();
+        assert!(proof.verify());
+        assert_eq!(proof.withdrawals_root, header.withdrawals_root);
+        assert_eq!((proof.batch_number, proof.index), (batch.batch_number(), index as u32));
+
+        let mut forged = proof.clone();
+        forged.withdrawal.amount += 1;
+        assert!(!forged.verify(), "A proof should not cover a different amount");
+    }
+
+    assert_eq!(sequencer.state().withdrawal_nonce(&account), 2);
+    let config = SequencerConfig {
+        max_pending_transactions: 5,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 3,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 3,
+    };
+    let mut restarted = Sequencer::new(sequencer.get_current_state(), config);
+    assert!(restarted.submit_withdrawal(withdrawals[0].clone()).is_err(), "Applied withdrawals should be rejected after a restart");
+    let replayed = Batch::new(2, ForcedInclusion { queue_start: 0, transactions: Vec::new() }, Vec::new(), Vec::new(), Vec::new(), withdrawals[..1].to_vec(), 1);
+    assert!(sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &replayed).is_err(), "A batch should not apply a withdrawal again");
+    restarted.submit_withdrawal(Withdrawal::new(&alice, "5Alice".to_string(), 10, 2)).unwrap();
+}
+
+fn forced_transaction(index: u64, queued_at_batch: u64) -> ForcedTransaction {
+    let tx = Transaction::new(format!("User{}", index), "Bob".to_string(), vec![], index, "test_program".to_string());
+    ForcedTransaction::new(index, queued_at_batch, 1, tx)
+}
+
+#[test]
+fn test_forced_transactions_are_included_by_their_deadline() {
+    let config = SequencerConfig {
+        max_pending_transactions: 5,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 3,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 2,
+        max_batch_attempts: 3,
+    };
+    let mut sequencer = Sequencer::new(State::default(), config);
+    assert!(sequencer.submit_forced_transaction(forced_transaction(1, 0)).is_err(), "Forced transactions should not skip ahead");
+    for index in 0..2 {
+        assert!(sequencer.submit_forced_transaction(forced_transaction(index, 0)).unwrap());
+    }
+    assert!(!sequencer.submit_forced_transaction(forced_transaction(0, 0)).unwrap());
+    for index in 2..5 {
+        sequencer.submit_forced_transaction(forced_transaction(index, 1)).unwrap();
+    }
+    let regular = signed_transaction(&new_key(), "Bob", vec![], 0);
+    sequencer.process_transaction(regular.clone()).unwrap();
+
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.batch_number(), 0);
+    assert_eq!(batch.forced_inclusion().transactions.len(), 3, "Forced transactions should fill the batch first");
+    assert!(!batch.transactions().contains(&regular));
+    let check = sequencer.forced_inclusion_check(&batch).unwrap();
+    assert_eq!(check.next_deadline, 2, "The next deadline should come from the L1 queue");
+    assert!(check.is_satisfied());
+    batch.record_receipt(ReceiptStatus::Success, 100, Vec::new(), Vec::new());
+    assert_eq!(batch.receipts()[0].fee, 0, "Forced transactions are not charged on the rollup");
+
+    sequencer.requeue_batch(&batch);
+    assert_eq!(sequencer.pending_forced_count(), 5);
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert_eq!(sequencer.state().forced_queue_index(), 3);
+    let header = batch.header();
+    assert_eq!((header.forced_queue_start, header.forced_count), (0, 3));
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.batch_number(), 1);
+    assert_eq!(batch.transactions(), &[forced_transaction(3, 1).transaction, forced_transaction(4, 1).transaction, regular][..]);
+    assert_eq!(batch.forced_inclusion().queue_start, 3);
+
+    let forged = Batch::new(
+        1,
+        ForcedInclusion { queue_start: 3, transactions: vec![forced_transaction(3, 0)] },
+        Vec::new(), Vec::new(), Vec::new(), Vec::new(), 1,
+    );
+    assert!(sequencer.forced_inclusion_check(&forged).is_err(), "Forced transactions should match the L1 queue");
+    let skipping = Batch::new(1, ForcedInclusion { queue_start: 4, transactions: Vec::new() }, Vec::new(), Vec::new(), Vec::new(), Vec::new(), 1);
+    assert!(sequencer.forced_inclusion_check(&skipping).is_err(), "Batches should not skip part of the L1 queue");
+}
+
+#[test]
+fn test_overdue_forced_transactions_exceed_the_batch_size() {
+    let config = SequencerConfig {
+        max_pending_transactions: 5,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 2,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 1,
+        max_batch_attempts: 3,
+    };
+    let mut sequencer = Sequencer::new(State::default(), config);
+    for index in 0..3 {
+        sequencer.submit_forced_transaction(forced_transaction(index, 0)).unwrap();
+    }
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.forced_inclusion().transactions.len(), 3, "Overdue forced transactions must all be included");
+    assert_eq!(sequencer.forced_inclusion_check(&batch).unwrap().next_deadline, 1);
+}
+
+#[test]
+fn test_forced_transactions_run_with_the_protocol_gas_allowance() {
+    let mut sequencer = create_test_sequencer();
+    let program = create_versioned_program(1, "1.0.0", "Alice");
+    let key = SigningKey::generate(&mut ark_std::rand::thread_rng());
+    deploy(&mut sequencer, SignedDeployment::new(program.clone(), &key, 0)).unwrap();
+
+    let mut transaction = Transaction::new("Alice".to_string(), "Bob".to_string(), vec![], 0, program.id().to_string());
+    transaction.fee_limit = 0;
+    sequencer.submit_forced_transaction(ForcedTransaction::new(0, 1, 1, transaction)).unwrap();
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+    execute_batch(sequencer.deployed_programs(), &mut batch, sequencer.state(), &ExecutionConfig::default());
+    let receipt = &batch.receipts()[0];
+    assert_eq!(receipt.status, ReceiptStatus::Success, "A forced transaction should not need a fee limit");
+    assert!(receipt.gas_used > 0 && receipt.gas_used <= fees::FORCED_TRANSACTION_GAS);
+    assert_eq!(receipt.fee, 0);
+}
+
+#[test]
+fn test_batches_call_programs_by_registry_reference() {
+    let mut sequencer = create_test_sequencer();
+    let key = new_key();
+    deploy(&mut sequencer, SignedDeployment::new(create_versioned_program(7, "1.2.0", "Alice"), &key, 0)).unwrap();
+    let wat = r#"
+        (module
+            (import "hvm" "call" (func $call (param i32 i32 i32 i32 i64 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "Token@^1")
+            (func (export "alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func (export "run") (param i32 i32) (result i32 i32)
+                (if (i32.lt_s
+                        (call $call (i32.const 0) (i32.const 8) (i32.const 0) (i32.const 0) (i64.const 100000) (i32.const 64) (i32.const 32))
+                        (i32.const 0))
+                    (then unreachable))
+                (i32.const 64)
+                (i32.const 32)))
+    "#;
+    let caller = BendProgram::new(
+        wat::parse_str(wat).unwrap(),
+        ProgramMetadata {
+            name: "Caller".to_string(),
+            version: "1.0.0".to_string(),
+            description: "Calls the token by name".to_string(),
+            source: None,
+        },
+        "Alice".to_string(),
+    );
+    let deployment = SignedDeployment::new(caller, &key, 0);
+    let caller_id = deployment.program.id().to_string();
+    deploy(&mut sequencer, deployment).unwrap();
+
+    let sender = new_key();
+    for program_id in [caller_id, "Caller@^1".to_string()] {
+        let nonce = sequencer.next_nonce(&sender.verifying_key().to_string());
+        let transaction = Transaction::new(sender.verifying_key().to_string(), "Bob".to_string(), vec![], nonce, program_id).sign(&sender);
+        sequencer.process_transaction(transaction).unwrap();
+    }
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+    execute_batch(sequencer.deployed_programs(), &mut batch, sequencer.state(), &ExecutionConfig::default());
+    for receipt in batch.receipts() {
+        assert_eq!(receipt.status, ReceiptStatus::Success, "Programs should be found through the registry");
+    }
+    assert_eq!(batch.receipts().len(), 2);
+}
+
+#[test]
+fn test_forced_inclusion_circuit() {
+    let satisfied = |check: ForcedInclusionCheck| {
+        let cs = ConstraintSystem::new_ref();
+        check.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    };
+    let on_time = ForcedInclusionCheck { batch_number: 4, next_deadline: 5 };
+    assert!(on_time.is_satisfied());
+    assert!(satisfied(on_time));
+    let circuit = BendCircuit { receipts_commitment: Fr::from(7u64), forced_inclusion: on_time };
+    assert_eq!(circuit.public_inputs().len(), 3, "The public inputs should not depend on the batch");
+    let cs = ConstraintSystem::new_ref();
+    circuit.generate_constraints(cs.clone()).unwrap();
+    assert!(cs.is_satisfied().unwrap());
+    assert!(satisfied(ForcedInclusionCheck { batch_number: 4, next_deadline: 1000 }));
+
+    for overdue in [ForcedInclusionCheck { batch_number: 4, next_deadline: 4 }, ForcedInclusionCheck { batch_number: 4, next_deadline: 0 }] {
+        assert!(!overdue.is_satisfied());
+        assert!(!satisfied(overdue), "The circuit should reject {:?}", overdue);
+    }
+}
+
+#[test]
+fn test_reverted_batches_requeue_then_reject_transactions() {
+    let config = SequencerConfig {
+        max_pending_transactions: 5,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 3,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 2,
+    };
+    let mut sequencer = Sequencer::new(State::default(), config);
+    let alice = new_key();
+    let account = alice.verifying_key().to_string();
+    sequencer.submit_deposit(Deposit::new(0, account.clone(), 1_000, 1)).unwrap();
+    sequencer.submit_forced_transaction(forced_transaction(0, 0)).unwrap();
+    let tx = signed_transaction(&alice, "Bob", vec![100], 0);
+    sequencer.process_transaction(tx.clone()).unwrap();
+    let before = sequencer.get_current_state();
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.release_funds("Mallory", 500);
+    let failure = sequencer.revert_batch(&batch, "Batch proof failed verification").clone();
+    assert_eq!(failure.batch_id, batch.batch_id());
+    assert_eq!(failure.requeued, vec![forced_transaction(0, 0).transaction.hash(), tx.hash()]);
+    assert!(failure.rejected.is_empty());
+    assert_eq!(sequencer.state().account_balance("Mallory"), 0, "State should be rolled back to before the batch");
+    assert_eq!(sequencer.state().nonce(), before.nonce());
+    assert_eq!(sequencer.pending_transactions_count(), 1);
+    assert_eq!(sequencer.pending_forced_count(), 1);
+    assert_eq!(sequencer.pending_deposits_count(), 1);
+    assert_eq!(sequencer.processed_transactions_count(), 0);
+    let follower = signed_transaction(&alice, "Bob", vec![50], 1);
+    sequencer.process_transaction(follower.clone()).unwrap();
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    assert_eq!(batch.transactions().len(), 3);
+    let failure = sequencer.revert_batch(&batch, "Batch proof failed verification").clone();
+    assert_eq!(failure.rejected, vec![tx.hash(), follower.hash()], "Later transactions of the sender are dropped with it");
+    assert_eq!(sequencer.pending_transactions_count(), 0, "Transactions in too many failed batches are rejected");
+    assert_eq!(sequencer.next_nonce(&account), 0, "The nonces of rejected transactions can be used again");
+    assert_eq!(sequencer.pending_forced_count(), 1, "Forced transactions are never rejected");
+    let receipt = sequencer.receipt(&tx.hash()).unwrap();
+    assert_eq!(receipt.status, ReceiptStatus::Rejected { reason: "Batch proof failed verification".to_string() });
+    assert_eq!(receipt.fee, 0);
+    assert_eq!(receipt.batch_number, batch.batch_number());
+    assert_eq!(sequencer.batch_failures().len(), 2);
+
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    assert_eq!(sequencer.state().account_balance(&account), 1_000);
+    assert_eq!(sequencer.state().forced_queue_index(), 1);
+}
+
+#[test]
+fn test_snapshot_bootstraps_a_node_that_replays_later_batches() {
+    let mut sequencer = create_test_sequencer();
+    let (alice, bob) = (new_key(), new_key());
+    for (nonce, user) in [alice.verifying_key().to_string(), bob.verifying_key().to_string(), "Charlie".to_string()].into_iter().enumerate() {
+        sequencer.submit_deposit(Deposit::new(nonce as u64, user, 1_000, 1)).unwrap();
+    }
+    sequencer.process_transaction(signed_transaction(&alice, "Bob", vec![], 0)).unwrap();
+    let program = create_versioned_program(1, "1.0.0", "Bob");
+    sequencer.submit_program(SignedDeployment::new(program.clone(), &bob, 0), &ExecutionConfig::default()).unwrap();
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+    batch.record_receipt(ReceiptStatus::Success, 25, Vec::new(), Vec::new());
+    let mut changes = StorageChanges::new();
+    for key in 0..5u8 {
+        changes.insert(vec![key], Some(vec![key; 3]));
+    }
+    batch.record_storage_changes("counter", changes);
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    let header = sequencer.batch_header(0).unwrap().clone();
+    assert_eq!(header.state_root, sequencer.state().root());
+
+    let path = std::env::temp_dir().join(format!("hvm_snapshot_{}.jsonl", std::process::id()));
+    let manifest = sequencer.export_snapshot(&path, 3).unwrap();
+    assert_eq!(manifest.version, SNAPSHOT_VERSION);
+    assert_eq!(manifest.batch_count, 1);
+    assert_eq!(manifest.chunk_hashes.len(), 5, "1 counters, 4 account, 1 nonce, 5 storage, 1 registry, 1 program and 1 status entries in chunks of 3");
+    assert_eq!(read_snapshot(&path).unwrap().1, sequencer.get_current_state());
+
+    sequencer.submit_withdrawal(Withdrawal::new(&bob, "5Bob".to_string(), 300, 0)).unwrap();
+    sequencer.submit_deposit(Deposit::new(3, "Dave".to_string(), 50, 2)).unwrap();
+    let later = sequencer.create_batch(true).unwrap().unwrap();
+    let proof = Proof::new(vec![5, 6, 7]);
+    sequencer.apply_proof(proof.clone(), &later).unwrap();
+    let later_header = sequencer.batch_header(1).unwrap().clone();
+
+    let config = SequencerConfig {
+        max_pending_transactions: 5,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 3,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 3,
+    };
+    assert!(Sequencer::from_snapshot(&path, &later_header, config.clone(), FeeConfig::default()).is_err(), "The snapshot is not of batch 1");
+    let mut synced = Sequencer::from_snapshot(&path, &header, config, FeeConfig::default()).unwrap();
+    assert_eq!(synced.fee_market().base_fee(), sequencer.fee_market().base_fee());
+    assert!(synced.replay_batch(proof.clone(), &later, &header, &ExecutionConfig::default()).is_err(), "The header should match the batch");
+    let mut tampered = later_header.clone();
+    tampered.state_root[0] ^= 1;
+    assert!(synced.replay_batch(proof.clone(), &later, &tampered, &ExecutionConfig::default()).is_err(), "The state root should be checked");
+    let mut forged = later.clone();
+    forged.record_receipt(ReceiptStatus::Success, 0, Vec::new(), Vec::new());
+    let forged_header = BatchHeader { state_root: later_header.state_root, ..forged.header() };
+    assert!(synced.replay_batch(proof.clone(), &forged, &forged_header, &ExecutionConfig::default()).is_err(), "The batch should be executed again");
+    synced.replay_batch(proof.clone(), &later, &later_header, &ExecutionConfig::default()).unwrap();
+    assert_eq!(synced.get_current_state(), sequencer.get_current_state());
+    assert_eq!(synced.resolve_program("Token").unwrap().id(), program.id(), "Deployed programs should come with the snapshot");
+    assert_eq!(synced.program_status(program.id()), Some(&ProgramStatus::Deployed { batch_number: 0 }));
+    assert_eq!(synced.state().account_balance(&bob.verifying_key().to_string()), 700);
+    assert!(synced.withdrawal_proof(&later.withdrawals()[0].hash()).unwrap().verify());
+    assert!(!synced.submit_deposit(Deposit::new(3, "Dave".to_string(), 50, 2)).unwrap(), "Replayed deposits are credited");
+    assert!(synced.replay_batch(proof, &later, &later_header, &ExecutionConfig::default()).is_err(), "A batch can only be replayed once");
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::write(&path, contents.replacen("\"balance\":1000", "\"balance\":9000", 1)).unwrap();
+    assert!(read_snapshot(&path).is_err(), "A modified chunk should be detected");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_state_history_keeps_recent_states_and_checkpoints() {
+    let apply_batches = |history: HistoryConfig| {
+        let mut sequencer = create_test_sequencer().with_history(history);
+        let alice = new_key();
+        for nonce in 0..8 {
+            sequencer.submit_deposit(Deposit::new(nonce, "Alice".to_string(), 10, 1)).unwrap();
+            sequencer.process_transaction(signed_transaction(&alice, "Bob", vec![], nonce)).unwrap();
+            let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+            let mut changes = StorageChanges::new();
+            changes.insert(vec![nonce as u8], Some(vec![nonce as u8]));
+            if nonce >= 2 {
+                changes.insert(vec![nonce as u8 - 2], None);
+            }
+            batch.record_storage_changes("test_program", changes);
+            sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+        }
+        sequencer
+    };
+
+    let pruned = apply_batches(HistoryConfig { archive: false, keep_recent: 2, checkpoint_interval: 3 });
+    assert_eq!(pruned.history().versions().collect::<Vec<_>>(), vec![0, 3, 6, 7]);
+    for batch_number in pruned.history().versions() {
+        let state = pruned.state_at(batch_number).unwrap();
+        assert_eq!(state.root(), pruned.batch_header(batch_number).unwrap().state_root, "State {} should be rebuilt as it was", batch_number);
+        assert_eq!(state.program_storage("test_program").unwrap().len(), 2.min(batch_number as usize + 1));
+    }
+    assert_eq!(pruned.state_at(3).unwrap().account_balance("Alice"), 40);
+    assert_eq!(&pruned.state_at(7).unwrap(), pruned.state());
+    assert!(matches!(pruned.state_at(4), Err(HVMError::StatePruned(4))));
+    assert!(matches!(pruned.state_at(8), Err(HVMError::Sequencer(_))), "Future batches are not pruned");
+    assert_eq!(pruned.state_at(6).unwrap().root(), pruned.batch_header(6).unwrap().state_root);
+
+    let archive = apply_batches(HistoryConfig { archive: true, keep_recent: 1, checkpoint_interval: 0 });
+    assert_eq!(archive.history().versions().count(), 8);
+    let latest_only = archive.with_history(HistoryConfig { archive: false, keep_recent: 1, checkpoint_interval: 0 });
+    assert_eq!(latest_only.history().versions().collect::<Vec<_>>(), vec![7]);
+}
+
+#[test]
+fn test_historical_state_is_proven_against_batch_state_roots() {
+    let mut sequencer = create_test_sequencer();
+    let carol = new_key();
+    for nonce in 0..3 {
+        sequencer.submit_deposit(Deposit::new(nonce, "Alice".to_string(), 10, 1)).unwrap();
+        sequencer.process_transaction(signed_transaction(&carol, "Alice", vec![], nonce)).unwrap();
+        let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+        let mut changes = StorageChanges::new();
+        changes.insert(b"counter".to_vec(), Some(vec![nonce as u8]));
+        batch.record_storage_changes("test_program", changes);
+        sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+    }
+    sequencer.submit_deposit(Deposit::new(3, "Bob".to_string(), 5, 1)).unwrap();
+    let batch = sequencer.create_batch(true).unwrap().unwrap();
+    sequencer.apply_proof(Proof::new(vec![1, 2, 3, 4]), &batch).unwrap();
+
+    let state = sequencer.state_at(1).unwrap();
+    let state_root = sequencer.batch_header(1).unwrap().state_root;
+    let account = state.account_proof("Alice").unwrap();
+    assert!(account.verify());
+    assert_eq!(account.state_root, state_root);
+    assert_eq!(account.leaf, StateLeaf::Account { account: "Alice".to_string(), balance: 20, nonce: 0, withdrawal_nonce: 0 });
+    assert!(state.account_proof("Bob").is_none(), "Bob was credited in a later batch");
+    let sender = state.account_proof(&carol.verifying_key().to_string()).unwrap();
+    assert!(sender.verify());
+    assert!(matches!(sender.leaf, StateLeaf::Account { balance: 0, nonce: 2, .. }), "Account proofs should cover the nonce");
+
+    let counters = state.counters_proof();
+    assert!(counters.verify());
+    assert!(matches!(counters.leaf, StateLeaf::Counters { nonce: 2, deposit_nonce: 2, .. }));
+
+    let storage = state.storage_proof("test_program", b"counter").unwrap();
+    assert!(storage.verify());
+    assert_eq!(storage.program.state_root, state_root);
+    assert_eq!(storage.value, vec![1]);
+    assert!(state.storage_proof("test_program", b"missing").is_none());
+
+    let mut forged = storage.clone();
+    forged.value = vec![2];
+    assert!(!forged.verify());
+    let mut forged = account.clone();
+    forged.leaf = StateLeaf::Account { account: "Alice".to_string(), balance: 30, nonce: 0, withdrawal_nonce: 0 };
+    assert!(!forged.verify());
+    assert!(sequencer.state_at(3).unwrap().account_proof("Bob").unwrap().verify());
+}
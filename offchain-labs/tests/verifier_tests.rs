@@ -1,8 +1,19 @@
+use ark_bn254::Bn254;
+use ark_groth16::Groth16;
+use ark_snark::SNARK;
 use offchain_labs::{Config, OffchainLabs};
-use offchain_labs::sequencer::Transaction;
-use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig};
+use offchain_labs::bend::{BendCircuit, BendProgram, ProgramMetadata, registry::SignedDeployment};
+use offchain_labs::crypto::SigningKey;
+use offchain_labs::prover::execute_batch;
+use offchain_labs::sequencer::{Deposit, ReceiptStatus, Sequencer, StoredBatch, Transaction, Withdrawal};
+use offchain_labs::config::{ProverConfig, VerifierConfig, SequencerConfig, ExecutionConfig, CompilerConfig, FeeConfig, HistoryConfig, RpcConfig};
+use offchain_labs::verifier::{Auditor, Divergence, ZKVerifier};
+use offchain_labs::zk_rollup::{Proof, State};
 use std::path::PathBuf;
 
+mod common;
+use common::{account, assert_fees_charged, deploy_test_program, fund_users};
+
 #[tokio::test]
 async fn test_verifier_verify_proof() {
     let config = Config {
@@ -21,21 +32,34 @@ async fn test_verifier_verify_proof() {
             batch_interval_seconds: 10,
             max_batch_size: 50,
             max_programs_per_batch: 25,
+            forced_inclusion_batches: 10,
+            max_batch_attempts: 3,
         },
+        execution_config: ExecutionConfig::default(),
+        compiler_config: CompilerConfig::default(),
+        fee_config: FeeConfig::default(),
+        history_config: HistoryConfig::default(),
+        rpc_config: RpcConfig::default(),
     };
 
     let mut hvm = OffchainLabs::new(config).unwrap();
+
+    let [alice, bob, charlie] = fund_users(&mut hvm);
+    let program_id = deploy_test_program(&mut hvm);
     let transactions = vec![
-        Transaction::new("Alice".to_string(), "Bob".to_string(), vec![100], 1, "test_program".to_string()),
-        Transaction::new("Bob".to_string(), "Charlie".to_string(), vec![50], 2, "test_program".to_string()),
-        Transaction::new("Charlie".to_string(), "Alice".to_string(), vec![25], 3, "test_program".to_string()),
+        Transaction::new(account(&alice), account(&bob), vec![100], 0, program_id.clone()).sign(&alice),
+        Transaction::new(account(&bob), account(&charlie), vec![50], 0, program_id.clone()).sign(&bob),
+        Transaction::new(account(&charlie), account(&alice), vec![25], 0, program_id.clone()).sign(&charlie),
     ];
 
+    let initial_state = hvm.get_current_state().unwrap();
+    let mut receipts = Vec::new();
     for (i, tx) in transactions.into_iter().enumerate() {
         let result = hvm.process_transaction(tx);
         assert!(result.is_ok(), "Failed to process transaction {}: {:?}", i, result.err());
-        let is_valid = result.unwrap();
-        assert!(is_valid, "Transaction {} was invalid", i);
+        let receipt = result.unwrap();
+        assert_eq!(receipt.status, ReceiptStatus::Success, "Transaction {} failed", i);
+        receipts.push(receipt);
         
         let current_state = hvm.get_current_state().unwrap();
         println!("State after transaction {}: {:?}", i, current_state);
@@ -43,6 +67,79 @@ async fn test_verifier_verify_proof() {
 
     let final_state = hvm.get_current_state().unwrap();
     println!("Final state: {:?}", final_state);
-    assert_eq!(final_state.balance(), 768, "Unexpected final balance");
-    assert_eq!(final_state.nonce(), 3, "Unexpected final nonce");
-}
\ No newline at end of file
+    assert_fees_charged(&initial_state, &final_state, &[alice, bob, charlie], &receipts);
+    assert_eq!(final_state.nonce(), initial_state.nonce() + 3, "Unexpected final nonce");
+}
+fn audit_sequencer() -> Sequencer {
+    let config = SequencerConfig {
+        max_pending_transactions: 10,
+        max_pending_programs: 3,
+        batch_interval_seconds: 1,
+        max_batch_size: 10,
+        max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 3,
+    };
+    Sequencer::new(State::default(), config)
+}
+
+fn execute_and_apply(sequencer: &mut Sequencer) {
+    let mut batch = sequencer.create_batch(true).unwrap().unwrap();
+    execute_batch(sequencer.deployed_programs(), &mut batch, sequencer.state(), &ExecutionConfig::default());
+    sequencer.apply_proof(Proof::new(vec![batch.batch_number() as u8]), &batch).unwrap();
+}
+
+#[test]
+fn test_auditor_re_executes_batches_and_reports_divergence() {
+    let wat = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                (i32.const 1024))
+            (func (export "run") (param i32 i32) (result i32 i32)
+                (i32.const 7)
+                (i32.const 0)))
+    "#;
+    let metadata = ProgramMetadata {
+        name: "Audited".to_string(),
+        version: "1.0.0".to_string(),
+        description: "Audited program".to_string(),
+        source: None,
+    };
+    let program = BendProgram::new(wat::parse_str(wat).unwrap(), metadata, "Alice".to_string());
+    let key = SigningKey::generate(&mut ark_std::rand::thread_rng());
+
+    let mut operator = audit_sequencer();
+    operator.submit_program(SignedDeployment::new(program.clone(), &key, 0), &ExecutionConfig::default()).unwrap();
+    execute_and_apply(&mut operator);
+    let alice = key.verifying_key().to_string();
+    operator.submit_deposit(Deposit::new(0, alice.clone(), 1_000_000_000, 1)).unwrap();
+    operator.process_transaction(Transaction::new(alice.clone(), "Bob".to_string(), vec![], 0, program.id().to_string()).sign(&key)).unwrap();
+    operator.process_transaction(Transaction::new(alice, "Bob".to_string(), vec![], 1, "missing".to_string()).sign(&key)).unwrap();
+    execute_and_apply(&mut operator);
+    operator.submit_withdrawal(Withdrawal::new(&key, "5Alice".to_string(), 500, 0)).unwrap();
+    execute_and_apply(&mut operator);
+
+    let batches: Vec<StoredBatch> = operator.applied_batches().cloned().collect();
+    assert_eq!(batches[1].batch.receipts()[0].status, ReceiptStatus::Success);
+    let audit = |batches: &[StoredBatch]| Auditor::new(audit_sequencer(), None, ExecutionConfig::default()).audit(batches);
+
+    let report = audit(&batches);
+    assert_eq!((report.batches_checked, report.divergence), (3, None));
+
+    let mut tampered = batches.clone();
+    tampered[1].batch.record_receipt(ReceiptStatus::Success, 0, Vec::new(), Vec::new());
+    let report = audit(&tampered);
+    assert_eq!(report.batches_checked, 1);
+    assert_eq!(report.divergence, Some((1, Divergence::Receipt { index: 2 })));
+
+    let mut tampered = batches.clone();
+    tampered[2].header.state_root[0] ^= 1;
+    assert!(matches!(audit(&tampered).divergence, Some((2, Divergence::StateTransition { .. }))));
+
+    assert_eq!(audit(&batches[1..]).divergence, Some((1, Divergence::OutOfOrder { expected: 0 })));
+
+    let (_, vk) = Groth16::<Bn254>::circuit_specific_setup(BendCircuit::default(), &mut ark_std::rand::thread_rng()).unwrap();
+    let report = Auditor::new(audit_sequencer(), Some(ZKVerifier::new(vk)), ExecutionConfig::default()).audit(&batches);
+    assert!(matches!(report.divergence, Some((0, Divergence::InvalidProof { .. }))), "Proofs should be checked when a verifier is given");
+}
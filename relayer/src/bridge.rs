@@ -0,0 +1,270 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::{info, warn};
+use offchain_labs::crypto::{SigningKey, SECRET_KEY_LENGTH};
+use offchain_labs::rpc::bridge_message;
+use offchain_labs::sequencer::{Deposit, ForcedTransaction, Receipt, Transaction, WithdrawalProof};
+use offchain_labs::zk_rollup::MerkleSibling;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use subxt::backend::legacy::LegacyRpcMethods;
+use subxt::backend::rpc::RpcClient;
+use subxt::dynamic::Value;
+use subxt::events::StaticEvent;
+use subxt::utils::AccountId32;
+use subxt::{OnlineClient, PolkadotConfig};
+use subxt_signer::sr25519::dev;
+use url::Url;
+
+/// Call of the QuantumFusion pallet that pays out a rollup withdrawal.
+pub const CLAIM_WITHDRAWAL_CALL: &str = "claim_withdrawal";
+
+/// `Deposited` event of the QuantumFusion pallet.
+#[derive(Debug, subxt::ext::scale_decode::DecodeAsType)]
+#[decode_as_type(crate_path = "subxt::ext::scale_decode")]
+pub struct Deposited {
+    pub who: AccountId32,
+    pub amount: u128,
+}
+
+impl StaticEvent for Deposited {
+    const PALLET: &'static str = "QuantumFusion";
+    const EVENT: &'static str = "Deposited";
+}
+
+/// `ForcedTransactionQueued` event of the QuantumFusion pallet. The
+/// transaction is the JSON encoded rollup transaction the user posted.
+#[derive(Debug, subxt::ext::scale_decode::DecodeAsType)]
+#[decode_as_type(crate_path = "subxt::ext::scale_decode")]
+pub struct ForcedTransactionQueued {
+    pub index: u64,
+    pub who: AccountId32,
+    pub queued_at_batch: u64,
+    pub transaction: Vec<u8>,
+}
+
+impl StaticEvent for ForcedTransactionQueued {
+    const PALLET: &'static str = "QuantumFusion";
+    const EVENT: &'static str = "ForcedTransactionQueued";
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct BridgeOpts {
+    #[arg(short, long, default_value = "ws://127.0.0.1:9944")]
+    pub substrate_url: Url,
+
+    /// HTTP JSON-RPC endpoint of the rollup node.
+    #[arg(short = 'q', long)]
+    pub sequencer_url: Url,
+
+    /// Where the last processed block and deposit nonce are kept across
+    /// restarts.
+    #[arg(short, long, default_value = "bridge_cursor.json")]
+    pub cursor_path: PathBuf,
+
+    /// First L1 block to scan when there is no cursor yet.
+    #[arg(short = 'b', long, default_value = "0")]
+    pub start_block: u32,
+
+    #[arg(short, long, default_value = "6")]
+    pub poll_interval_seconds: u64,
+
+    /// Hex secret key the calls to the sequencer are signed with. The node
+    /// has to be configured with its public key as `relayer_key`.
+    #[arg(short = 'k', long)]
+    pub relayer_key: String,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ClaimOpts {
+    #[arg(short, long, default_value = "ws://127.0.0.1:9944")]
+    pub substrate_url: Url,
+
+    /// HTTP JSON-RPC endpoint of the rollup node.
+    #[arg(short = 'q', long)]
+    pub sequencer_url: Url,
+
+    /// Hash of the applied withdrawal to claim.
+    #[arg(short, long)]
+    pub withdrawal: String,
+}
+
+/// Progress through the L1: the next block to scan and the nonce the next
+/// deposit found gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub next_block: u32,
+    pub next_nonce: u64,
+}
+
+impl Cursor {
+    pub fn load(path: &Path, start_block: u32) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self { next_block: start_block, next_nonce: 0 });
+        }
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid cursor file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Forwards QuantumFusion deposits and forced transactions to the sequencer.
+///
+/// Only finalized blocks are scanned, so a reorg cannot take back a deposit
+/// that was forwarded. Deposits are numbered in the order they appear from
+/// `start_block` on, and the cursor is saved after each block. After a
+/// restart the deposits of a block that was not saved are forwarded again
+/// with the same nonces, and the sequencer ignores the ones it has already
+/// accepted. Forced transactions are numbered by the pallet, so they are
+/// forwarded with the queue index from the event.
+pub async fn run(opts: BridgeOpts) -> Result<()> {
+    let rpc = RpcClient::from_url(opts.substrate_url.as_str()).await?;
+    let methods = LegacyRpcMethods::<PolkadotConfig>::new(rpc.clone());
+    let client = OnlineClient::<PolkadotConfig>::from_rpc_client(rpc).await?;
+    let http = reqwest::Client::new();
+    let key = relayer_key(&opts.relayer_key)?;
+    let mut cursor = Cursor::load(&opts.cursor_path, opts.start_block)?;
+    info!("Bridging deposits from block {} with nonce {}", cursor.next_block, cursor.next_nonce);
+
+    loop {
+        let finalized = methods.chain_get_finalized_head().await?;
+        let finalized_number = client.blocks().at(finalized).await?.number();
+
+        while cursor.next_block <= finalized_number {
+            let hash = methods.chain_get_block_hash(Some(cursor.next_block.into())).await?
+                .with_context(|| format!("Finalized block {} has no hash", cursor.next_block))?;
+            let events = client.blocks().at(hash).await?.events().await?;
+            for event in events.find::<Deposited>() {
+                let event = event?;
+                let amount = u64::try_from(event.amount)
+                    .with_context(|| format!("Deposit of {} in block {} does not fit in a rollup balance", event.amount, cursor.next_block))?;
+                let deposit = Deposit::new(cursor.next_nonce, event.who.to_string(), amount, cursor.next_block as u64);
+                forward_deposit(&http, &opts.sequencer_url, &key, &deposit).await?;
+                cursor.next_nonce += 1;
+            }
+            for event in events.find::<ForcedTransactionQueued>() {
+                let event = event?;
+                let forced = forced_transaction(event, cursor.next_block);
+                forward_forced_transaction(&http, &opts.sequencer_url, &key, &forced).await?;
+            }
+            cursor.next_block += 1;
+            cursor.save(&opts.cursor_path)?;
+        }
+
+        tokio::time::sleep(Duration::from_secs(opts.poll_interval_seconds)).await;
+    }
+}
+
+fn relayer_key(hex_key: &str) -> Result<SigningKey> {
+    let bytes: [u8; SECRET_KEY_LENGTH] = hex::decode(hex_key)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Relayer key must be {} bytes", SECRET_KEY_LENGTH))?;
+    Ok(SigningKey::from_bytes(&bytes)?)
+}
+
+async fn forward_deposit(client: &reqwest::Client, sequencer_url: &Url, key: &SigningKey, deposit: &Deposit) -> Result<()> {
+    let signature = key.sign(&bridge_message("rollup_submitDeposit", deposit)?);
+    let credited: bool = sequencer_call(client, sequencer_url, "rollup_submitDeposit", json!([deposit, signature])).await
+        .with_context(|| format!("Sequencer rejected deposit {}", deposit.nonce))?;
+    if credited {
+        info!("Forwarded deposit {} of {} to {}", deposit.nonce, deposit.amount, deposit.account);
+    } else {
+        info!("Deposit {} was already credited", deposit.nonce);
+    }
+    Ok(())
+}
+
+/// The sender of a forced transaction is the L1 account that queued it,
+/// whatever the posted transaction says. Anyone can post to the queue, so
+/// bytes that are not a rollup transaction still take their place in it as a
+/// call to no program, which fails without touching the state.
+fn forced_transaction(event: ForcedTransactionQueued, l1_block: u32) -> ForcedTransaction {
+    let sender = event.who.to_string();
+    let transaction = match serde_json::from_slice::<Transaction>(&event.transaction) {
+        Ok(transaction) => Transaction { sender, signature: None, ..transaction },
+        Err(e) => {
+            warn!("Forced transaction {} in block {} is not a rollup transaction: {}", event.index, l1_block, e);
+            Transaction::new(sender.clone(), sender, Vec::new(), 0, String::new())
+        }
+    };
+    ForcedTransaction::new(event.index, event.queued_at_batch, l1_block as u64, transaction)
+}
+
+async fn forward_forced_transaction(client: &reqwest::Client, sequencer_url: &Url, key: &SigningKey, forced: &ForcedTransaction) -> Result<()> {
+    let signature = key.sign(&bridge_message("rollup_sendForcedTransaction", forced)?);
+    let receipt: Option<Receipt> = sequencer_call(client, sequencer_url, "rollup_sendForcedTransaction", json!([forced, signature])).await
+        .with_context(|| format!("Sequencer rejected forced transaction {}", forced.index))?;
+    match receipt {
+        Some(receipt) => info!("Forwarded forced transaction {} from {}: {:?}", forced.index, forced.transaction.sender, receipt.status),
+        None => info!("Forced transaction {} was already queued", forced.index),
+    }
+    Ok(())
+}
+
+/// Calls a method of the sequencer's JSON-RPC API over HTTP.
+async fn sequencer_call<T: DeserializeOwned>(client: &reqwest::Client, sequencer_url: &Url, method: &str, params: serde_json::Value) -> Result<T> {
+    let mut response: serde_json::Value = client.post(sequencer_url.clone())
+        .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if let Some(error) = response.get("error") {
+        anyhow::bail!("{} failed: {}", method, error);
+    }
+    let result = response.get_mut("result").map(serde_json::Value::take).unwrap_or_default();
+    Ok(serde_json::from_value(result)?)
+}
+
+/// Fetches the proof of an applied withdrawal from the sequencer and submits
+/// the claim to the QuantumFusion pallet, which checks it against the
+/// withdrawals root of the batch header.
+pub async fn claim(opts: ClaimOpts) -> Result<()> {
+    let http = reqwest::Client::new();
+    let proof: WithdrawalProof = sequencer_call(&http, &opts.sequencer_url, "rollup_getWithdrawalProof", json!([opts.withdrawal])).await?;
+    if !proof.verify() {
+        anyhow::bail!("Sequencer returned an invalid proof for withdrawal {}", opts.withdrawal);
+    }
+    let recipient = AccountId32::from_str(&proof.withdrawal.l1_recipient)
+        .map_err(|e| anyhow::anyhow!("Invalid L1 recipient {}: {:?}", proof.withdrawal.l1_recipient, e))?;
+
+    let client = OnlineClient::<PolkadotConfig>::from_url(opts.substrate_url.as_str()).await?;
+    let tx = subxt::dynamic::tx(Deposited::PALLET, CLAIM_WITHDRAWAL_CALL, claim_fields(&proof, &recipient));
+    let from = dev::alice();
+    let events = client.tx()
+        .sign_and_submit_then_watch_default(&tx, &from)
+        .await?
+        .wait_for_finalized_success()
+        .await?;
+    info!(
+        "Claimed withdrawal {} of {} to {} in extrinsic {:?}",
+        opts.withdrawal, proof.withdrawal.amount, proof.withdrawal.l1_recipient, events.extrinsic_hash()
+    );
+    Ok(())
+}
+
+fn claim_fields(proof: &WithdrawalProof, recipient: &AccountId32) -> Vec<Value> {
+    let siblings = proof.proof.siblings.iter().map(|sibling| match sibling {
+        MerkleSibling::Left(hash) => Value::unnamed_variant("Left", [Value::from_bytes(hash)]),
+        MerkleSibling::Right(hash) => Value::unnamed_variant("Right", [Value::from_bytes(hash)]),
+    });
+    vec![
+        Value::u128(proof.batch_number as u128),
+        Value::u128(proof.index as u128),
+        Value::from_bytes(recipient.0),
+        Value::u128(proof.withdrawal.amount as u128),
+        Value::from_bytes(proof.withdrawals_root),
+        Value::unnamed_composite(siblings),
+    ]
+}
@@ -5,7 +5,7 @@ use offchain_labs::bend::{BendProgram, ProgramMetadata, storage::Storage};
 
 use offchain_labs::{
     Config, OffchainLabs,
-    config::{self, ProverConfig, VerifierConfig, SequencerConfig},
+    config::{self, ProverConfig, VerifierConfig, SequencerConfig, ExecutionConfig, CompilerConfig, FeeConfig, HistoryConfig, RpcConfig},
     zk_rollup::{State, Proof},
 };
 
@@ -20,6 +20,7 @@ pub fn upload_bend_program() -> Result<(), Box<dyn std::error::Error>> {
             name: "Test Program".to_string(),
             version: "1.0.0".to_string(),
             description: "Bend program for test".to_string(),
+            source: None,
         },
         "Test Author".to_string(),
     );
@@ -35,6 +36,8 @@ pub fn process_batch() -> Result<(), Box<dyn std::error::Error>> {
         batch_interval_seconds: 1,
         max_batch_size: 3,
         max_programs_per_batch: 2,
+        forced_inclusion_batches: 10,
+        max_batch_attempts: 3,
     };
 
     let mut sequencer = Sequencer::new(State::default(), config);
@@ -61,7 +64,14 @@ pub fn verify_and_submit() -> Result<(), Box<dyn std::error::Error>> {
             batch_interval_seconds: 10,
             max_batch_size: 50,
             max_programs_per_batch: 25,
+            forced_inclusion_batches: 10,
+            max_batch_attempts: 3,
         },
+        execution_config: ExecutionConfig::default(),
+        compiler_config: CompilerConfig::default(),
+        fee_config: FeeConfig::default(),
+        history_config: HistoryConfig::default(),
+        rpc_config: RpcConfig::default(),
     };
 
     let hvm = OffchainLabs::new(config);
@@ -7,6 +7,7 @@ pub mod util;
 pub mod error;
 pub mod offchain_lab;
 pub mod bend_program;
+pub mod bridge;
 
 pub type Result<T> = std::result::Result<T, RelayerError>;
 
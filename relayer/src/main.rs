@@ -1,5 +1,5 @@
 use clap::Parser;
-use hvm_relayer::{commands, calldata, connect, relay, runtime, offchain_lab};
+use hvm_relayer::{bridge, commands, calldata, connect, relay, runtime, offchain_lab};
 use log::info;
 
 #[derive(Debug, Parser)]
@@ -19,6 +19,10 @@ enum Commands {
     Relay(relay::RelayOpts),
     #[command(arg_required_else_help = true)]
     OffchainLab(offchain_lab::OffchainLabOpts),
+    #[command(arg_required_else_help = true)]
+    Bridge(bridge::BridgeOpts),
+    #[command(arg_required_else_help = true)]
+    Claim(bridge::ClaimOpts),
 }
 
 #[tokio::main]
@@ -35,6 +39,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         Commands::Relay(opts) => relay::run(opts).await?,
         Commands::OffchainLab(opts) => offchain_lab::run(opts).await?,
+        Commands::Bridge(opts) => bridge::run(opts).await?,
+        Commands::Claim(opts) => bridge::claim(opts).await?,
     }
 
     Ok(())